@@ -0,0 +1,302 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Custom derives for `sel4::IpcEncode` and `sel4::IpcDecode`.
+//!
+//! Structs are encoded as their fields in declaration order. Enums are encoded as the index of the
+//! variant (not its discriminant), followed by the variant's fields. Every field must itself
+//! implement the trait being derived. Generic types are not supported.
+//!
+//! The generated code refers to the `sel4` crate by name, so it must be linked with
+//! `extern crate sel4` at the root of the crate using the derive.
+
+extern crate proc_macro;
+
+use proc_macro::{Delimiter, TokenStream, TokenTree};
+
+/// The fields of a struct or enum variant.
+enum Fields {
+    Named(Vec<(String, String)>),
+    Unnamed(Vec<String>),
+    Unit,
+}
+
+enum Shape {
+    Struct(Fields),
+    Enum(Vec<(String, Fields)>),
+}
+
+struct Input {
+    name: String,
+    shape: Shape,
+}
+
+/// Split a token list at top-level commas, skipping commas inside angle brackets.
+fn split_commas(tokens: Vec<TokenTree>) -> Vec<Vec<TokenTree>> {
+    let mut parts = Vec::new();
+    let mut current = Vec::new();
+    let mut depth = 0i32;
+    for tt in tokens {
+        if let TokenTree::Punct(ref p) = tt {
+            match p.as_char() {
+                '<' => depth += 1,
+                '>' => depth -= 1,
+                ',' if depth == 0 => {
+                    parts.push(current);
+                    current = Vec::new();
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        current.push(tt);
+    }
+    if !current.is_empty() {
+        parts.push(current);
+    }
+    parts
+}
+
+/// Remove leading attributes and visibility from a token list.
+fn strip_prefix(mut tokens: Vec<TokenTree>) -> Vec<TokenTree> {
+    loop {
+        let skip = match (tokens.get(0), tokens.get(1)) {
+            (Some(&TokenTree::Punct(ref p)), Some(&TokenTree::Group(_))) if p.as_char() == '#' => 2,
+            (Some(&TokenTree::Ident(ref i)), next) if i.to_string() == "pub" => {
+                match next {
+                    Some(&TokenTree::Group(ref g)) if g.delimiter() == Delimiter::Parenthesis => 2,
+                    _ => 1,
+                }
+            }
+            _ => return tokens,
+        };
+        tokens.drain(..skip);
+    }
+}
+
+fn tokens_to_string(tokens: &[TokenTree]) -> String {
+    tokens.iter().cloned().collect::<TokenStream>().to_string()
+}
+
+fn parse_fields(group: Option<&TokenTree>) -> Result<Fields, String> {
+    let group = match group {
+        Some(&TokenTree::Group(ref g)) => g,
+        _ => return Ok(Fields::Unit),
+    };
+    let parts = split_commas(group.stream().into_iter().collect());
+    match group.delimiter() {
+        Delimiter::Brace => {
+            let mut fields = Vec::new();
+            for part in parts {
+                let part = strip_prefix(part);
+                match (part.get(0), part.get(1)) {
+                    (Some(&TokenTree::Ident(ref name)), Some(&TokenTree::Punct(ref colon)))
+                        if colon.as_char() == ':' => {
+                        fields.push((name.to_string(), tokens_to_string(&part[2..])));
+                    }
+                    _ => return Err("expected a named field".to_string()),
+                }
+            }
+            Ok(Fields::Named(fields))
+        }
+        Delimiter::Parenthesis => {
+            let types = parts.into_iter().map(|p| tokens_to_string(&strip_prefix(p))).collect();
+            Ok(Fields::Unnamed(types))
+        }
+        _ => Err("unexpected delimiter".to_string()),
+    }
+}
+
+fn parse(input: TokenStream) -> Result<Input, String> {
+    let tokens = strip_prefix(input.into_iter().collect());
+    let (kind, name) = match (tokens.get(0), tokens.get(1)) {
+        (Some(&TokenTree::Ident(ref kind)), Some(&TokenTree::Ident(ref name))) => {
+            (kind.to_string(), name.to_string())
+        }
+        _ => return Err("expected a struct or enum".to_string()),
+    };
+    match tokens.get(2) {
+        Some(&TokenTree::Punct(ref p)) if p.as_char() == '<' => {
+            return Err("generic types are not supported".to_string())
+        }
+        Some(&TokenTree::Ident(ref i)) if i.to_string() == "where" => {
+            return Err("generic types are not supported".to_string())
+        }
+        _ => {}
+    }
+
+    let shape = match &kind[..] {
+        "struct" => Shape::Struct(parse_fields(tokens.get(2))?),
+        "enum" => {
+            let body = match tokens.get(2) {
+                Some(&TokenTree::Group(ref g)) if g.delimiter() == Delimiter::Brace => g,
+                _ => return Err("expected an enum body".to_string()),
+            };
+            let mut variants = Vec::new();
+            for part in split_commas(body.stream().into_iter().collect()) {
+                let part = strip_prefix(part);
+                let name = match part.get(0) {
+                    Some(&TokenTree::Ident(ref name)) => name.to_string(),
+                    _ => return Err("expected a variant".to_string()),
+                };
+                variants.push((name, parse_fields(part.get(1))?));
+            }
+            Shape::Enum(variants)
+        }
+        _ => return Err("only structs and enums are supported".to_string()),
+    };
+    Ok(Input {
+        name: name,
+        shape: shape,
+    })
+}
+
+fn field_types(fields: &Fields) -> Vec<String> {
+    match *fields {
+        Fields::Named(ref fields) => fields.iter().map(|&(_, ref ty)| ty.clone()).collect(),
+        Fields::Unnamed(ref types) => types.clone(),
+        Fields::Unit => Vec::new(),
+    }
+}
+
+/// Sum of an associated constant of `sel4::IpcEncode` over the types of `fields`.
+fn sum(fields: &Fields, konst: &str) -> String {
+    let mut out = "0".to_string();
+    for ty in field_types(fields) {
+        out.push_str(&format!(" + <{} as ::sel4::IpcEncode>::{}", ty, konst));
+    }
+    out
+}
+
+/// A pattern binding every field of `fields` by reference, to `f0`, `f1`, ...
+fn pattern(path: &str, fields: &Fields) -> (String, Vec<String>) {
+    match *fields {
+        Fields::Named(ref fields) => {
+            let names: Vec<String> = (0..fields.len()).map(|i| format!("f{}", i)).collect();
+            let binds: Vec<String> = fields.iter()
+                .zip(&names)
+                .map(|(&(ref field, _), name)| format!("{}: ref {}", field, name))
+                .collect();
+            (format!("{} {{ {} }}", path, binds.join(", ")), names)
+        }
+        Fields::Unnamed(ref types) => {
+            let names: Vec<String> = (0..types.len()).map(|i| format!("f{}", i)).collect();
+            let binds: Vec<String> = names.iter().map(|n| format!("ref {}", n)).collect();
+            (format!("{}({})", path, binds.join(", ")), names)
+        }
+        Fields::Unit => (path.to_string(), Vec::new()),
+    }
+}
+
+/// An expression constructing `path` with every field decoded from `msg`.
+fn construct(path: &str, fields: &Fields) -> String {
+    let decode = "::sel4::IpcDecode::decode(msg)?";
+    match *fields {
+        Fields::Named(ref fields) => {
+            let inits: Vec<String> =
+                fields.iter().map(|&(ref field, _)| format!("{}: {}", field, decode)).collect();
+            format!("{} {{ {} }}", path, inits.join(", "))
+        }
+        Fields::Unnamed(ref types) => {
+            let inits: Vec<&str> = types.iter().map(|_| decode).collect();
+            format!("{}({})", path, inits.join(", "))
+        }
+        Fields::Unit => path.to_string(),
+    }
+}
+
+fn encode_fields(names: &[String]) -> String {
+    names.iter().map(|n| format!("::sel4::IpcEncode::encode({}, msg)?;", n)).collect()
+}
+
+fn error(msg: &str) -> TokenStream {
+    format!("compile_error!({:?});", msg).parse().unwrap()
+}
+
+#[proc_macro_derive(IpcEncode)]
+pub fn derive_ipc_encode(input: TokenStream) -> TokenStream {
+    let input = match parse(input) {
+        Ok(input) => input,
+        Err(msg) => return error(&format!("cannot derive IpcEncode: {}", msg)),
+    };
+    let name = &input.name;
+    let (words, caps, body) = match input.shape {
+        Shape::Struct(ref fields) => {
+            let (pat, names) = pattern(name, fields);
+            let body = format!("let {} = *self; {}", pat, encode_fields(&names));
+            (sum(fields, "WORDS"), sum(fields, "CAPS"), body)
+        }
+        Shape::Enum(ref variants) => {
+            let mut words = "0".to_string();
+            let mut caps = "0".to_string();
+            let mut arms = String::new();
+            for (i, &(ref variant, ref fields)) in variants.iter().enumerate() {
+                words = format!("::sel4::max_words({}, {})", words, sum(fields, "WORDS"));
+                caps = format!("::sel4::max_words({}, {})", caps, sum(fields, "CAPS"));
+                let (pat, names) = pattern(&format!("{}::{}", name, variant), fields);
+                arms.push_str(&format!("{} => {{ msg.push_word({})?; {} }}",
+                                       pat,
+                                       i,
+                                       encode_fields(&names)));
+            }
+            (format!("1 + {}", words), caps, format!("match *self {{ {} }}", arms))
+        }
+    };
+    format!("impl ::sel4::IpcEncode for {name} {{
+                 const WORDS: usize = {words};
+                 const CAPS: usize = {caps};
+
+                 #[allow(unused_variables)]
+                 fn encode(&self, msg: &mut ::sel4::MessageBuilder) -> ::sel4::Result {{
+                     {body}
+                     Ok(())
+                 }}
+             }}",
+            name = name,
+            words = words,
+            caps = caps,
+            body = body)
+        .parse()
+        .unwrap()
+}
+
+#[proc_macro_derive(IpcDecode)]
+pub fn derive_ipc_decode(input: TokenStream) -> TokenStream {
+    let input = match parse(input) {
+        Ok(input) => input,
+        Err(msg) => return error(&format!("cannot derive IpcDecode: {}", msg)),
+    };
+    let name = &input.name;
+    let body = match input.shape {
+        Shape::Struct(ref fields) => format!("Ok({})", construct(name, fields)),
+        Shape::Enum(ref variants) => {
+            let mut arms = String::new();
+            for (i, &(ref variant, ref fields)) in variants.iter().enumerate() {
+                let path = format!("{}::{}", name, variant);
+                arms.push_str(&format!("Some({}) => Ok({}),", i, construct(&path, fields)));
+            }
+            format!("match msg.read_word() {{
+                         {}
+                         Some(_) => Err(::sel4::DecodeError::InvalidValue),
+                         None => Err(::sel4::DecodeError::Truncated),
+                     }}",
+                    arms)
+        }
+    };
+    format!("impl ::sel4::IpcDecode for {name} {{
+                 #[allow(unused_variables)]
+                 fn decode(msg: &mut ::sel4::MessageReader) -> ::sel4::DecodeResult<{name}> {{
+                     {body}
+                 }}
+             }}",
+            name = name,
+            body = body)
+        .parse()
+        .unwrap()
+}
@@ -0,0 +1,385 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! A buddy allocator over untyped memory.
+
+use core::cell::RefCell;
+
+use sel4_sys::seL4_Word;
+
+use {Allocatable, CNode, SlotRef, ToCap, Untyped, UntypedDescriptor};
+use super::{BitmapSlotAllocator, ObjectAllocator, SizedObjectAllocator, SlotAllocator,
+            UntypedAllocError};
+
+/// The smallest block the buddy allocator will split down to.
+///
+/// This is the smallest untyped object the kernel will create.
+pub const MIN_BLOCK_BITS: u8 = 4;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum BlockState {
+    /// The block is available.
+    Free,
+    /// The block has been retyped into two half-sized untyped blocks.
+    Split,
+    /// The block has been retyped into an object.
+    Allocated,
+}
+
+/// Bookkeeping for a single block of untyped memory.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BuddyBlock {
+    untyped: Untyped,
+    /// The slot holding `untyped`.
+    slot: SlotRef,
+    paddr: seL4_Word,
+    size_bits: u8,
+    /// Index of the block this was split from, if any.
+    parent: Option<usize>,
+    state: BlockState,
+    /// The slot holding the object retyped from this block, while allocated.
+    object: Option<SlotRef>,
+    /// Size of that object in bytes.
+    used: seL4_Word,
+}
+
+/// A snapshot of how memory in a `BuddyAllocator` is being used.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BuddyStats {
+    /// Total bytes of untyped memory managed.
+    pub total_bytes: seL4_Word,
+    /// Bytes in free blocks.
+    pub free_bytes: seL4_Word,
+    /// Number of free blocks.
+    pub free_blocks: usize,
+    /// Size of the largest free block in bytes.
+    pub largest_free_block: seL4_Word,
+    /// Bytes in blocks holding objects.
+    pub allocated_bytes: seL4_Word,
+    /// Bytes actually used by the objects in those blocks.
+    pub requested_bytes: seL4_Word,
+}
+
+impl BuddyStats {
+    /// Percentage of free memory which is not in the largest free block.
+    ///
+    /// This is 0 when all free memory is contiguous, and approaches 100 as it is scattered across
+    /// many small blocks.
+    pub fn external_fragmentation(&self) -> seL4_Word {
+        if self.free_bytes == 0 {
+            0
+        } else {
+            100 - self.largest_free_block * 100 / self.free_bytes
+        }
+    }
+
+    /// Bytes lost to rounding objects up to a power-of-two block.
+    pub fn internal_fragmentation(&self) -> seL4_Word {
+        self.allocated_bytes - self.requested_bytes
+    }
+}
+
+/// Smallest block size that can hold `size` bytes.
+fn block_bits(size: seL4_Word) -> u8 {
+    let mut bits = MIN_BLOCK_BITS;
+    while ((1 as seL4_Word) << bits) < size {
+        bits += 1;
+    }
+    bits
+}
+
+/// An `ObjectAllocator` which manages untyped memory as power-of-two blocks.
+///
+/// To allocate an object, the smallest free block which can hold it is found and, if it is
+/// larger than needed, repeatedly retyped into two half-sized untyped objects until it is the
+/// right size. When an object is freed and its block's buddy is also free, the parent untyped is
+/// revoked, deleting both halves and making the parent free again. This lets memory be reused
+/// for objects of any size without fragmenting the untyped regions.
+///
+/// Slots for the split untypeds come from a `BitmapSlotAllocator`, and the destination slot given
+/// to `allocate_object` must have been allocated from it. The caller provides storage for the
+/// block bookkeeping; each split uses two entries.
+pub struct BuddyAllocator<'a, 'b: 'a> {
+    slots: &'a BitmapSlotAllocator<'b>,
+    root: CNode,
+    blocks: RefCell<&'a mut [Option<BuddyBlock>]>,
+}
+
+impl<'a, 'b> BuddyAllocator<'a, 'b> {
+    /// Create an allocator with no untyped memory.
+    ///
+    /// `root` is the root CNode of the current CSpace, in which the untyped capabilities given to
+    /// `add_untyped` are looked up. Every entry of `blocks` is cleared.
+    pub fn new(slots: &'a BitmapSlotAllocator<'b>, root: CNode,
+               blocks: &'a mut [Option<BuddyBlock>])
+               -> BuddyAllocator<'a, 'b> {
+        for block in blocks.iter_mut() {
+            *block = None;
+        }
+
+        BuddyAllocator {
+            slots: slots,
+            root: root,
+            blocks: RefCell::new(blocks),
+        }
+    }
+
+    /// Make the memory in `desc` available for allocation.
+    ///
+    /// The untyped must not have any children yet.
+    pub fn add_untyped(&self, desc: UntypedDescriptor) -> Result<(), UntypedAllocError> {
+        if desc.is_device {
+            return Err(UntypedAllocError::DeviceMemory);
+        }
+
+        let depth = ::core::mem::size_of::<seL4_Word>() * 8;
+        let mut blocks = self.blocks.borrow_mut();
+        match blocks.iter_mut().find(|b| b.is_none()) {
+            Some(entry) => {
+                *entry = Some(BuddyBlock {
+                    untyped: desc.cap,
+                    slot: SlotRef::new(self.root, desc.cap.to_cap(), depth as u8),
+                    paddr: desc.paddr,
+                    size_bits: desc.size_bits,
+                    parent: None,
+                    state: BlockState::Free,
+                    object: None,
+                    used: 0,
+                });
+                Ok(())
+            }
+            None => Err(UntypedAllocError::OutOfStorage),
+        }
+    }
+
+    /// Gather statistics about memory usage and fragmentation.
+    pub fn stats(&self) -> BuddyStats {
+        let mut stats = BuddyStats {
+            total_bytes: 0,
+            free_bytes: 0,
+            free_blocks: 0,
+            largest_free_block: 0,
+            allocated_bytes: 0,
+            requested_bytes: 0,
+        };
+
+        for block in self.blocks.borrow().iter().filter_map(|b| b.as_ref()) {
+            let size = (1 as seL4_Word) << block.size_bits;
+            if block.parent.is_none() {
+                stats.total_bytes += size;
+            }
+            match block.state {
+                BlockState::Free => {
+                    stats.free_bytes += size;
+                    stats.free_blocks += 1;
+                    if size > stats.largest_free_block {
+                        stats.largest_free_block = size;
+                    }
+                }
+                BlockState::Allocated => {
+                    stats.allocated_bytes += size;
+                    stats.requested_bytes += block.used;
+                }
+                BlockState::Split => {}
+            }
+        }
+
+        stats
+    }
+
+    /// Split the free block at `idx` in half, returning the index of the lower half.
+    fn split(&self, blocks: &mut [Option<BuddyBlock>], idx: usize)
+             -> Result<usize, UntypedAllocError> {
+        let (lower, upper) = {
+            let mut free = blocks.iter().enumerate().filter(|&(_, b)| b.is_none()).map(|(i, _)| i);
+            match (free.next(), free.next()) {
+                (Some(lower), Some(upper)) => (lower, upper),
+                _ => return Err(UntypedAllocError::OutOfStorage),
+            }
+        };
+
+        let (window, info) = match self.slots.allocate_range(2) {
+            Some(range) => range,
+            None => return Err(UntypedAllocError::OutOfSlots),
+        };
+
+        let parent = blocks[idx].expect("split of a missing block");
+        let child_bits = parent.size_bits - 1;
+        if let Err(e) = parent.untyped.retype::<Untyped>(window, child_bits as seL4_Word) {
+            self.slots.free_range(window).map_err(UntypedAllocError::Slot)?;
+            return Err(UntypedAllocError::Kernel(e));
+        }
+
+        for (n, &entry) in [lower, upper].iter().enumerate() {
+            let slot = window.slotref_to(&info, n).expect("retype window too small");
+            blocks[entry] = Some(BuddyBlock {
+                untyped: Untyped::from_cap(slot.cptr),
+                slot: slot,
+                paddr: parent.paddr + ((n as seL4_Word) << child_bits),
+                size_bits: child_bits,
+                parent: Some(idx),
+                state: BlockState::Free,
+                object: None,
+                used: 0,
+            });
+        }
+
+        if let Some(ref mut block) = blocks[idx] {
+            block.state = BlockState::Split;
+        }
+
+        Ok(lower)
+    }
+
+    /// Coalesce the free block at `idx` with its buddy, as far up as possible.
+    fn merge(&self, blocks: &mut [Option<BuddyBlock>], mut idx: usize)
+             -> Result<(), UntypedAllocError> {
+        loop {
+            let parent = match blocks[idx].and_then(|b| b.parent) {
+                Some(parent) => parent,
+                None => return Ok(()),
+            };
+
+            let mut children = [0; 2];
+            let mut count = 0;
+            for (i, entry) in blocks.iter().enumerate() {
+                if let Some(ref block) = *entry {
+                    if block.parent == Some(parent) {
+                        if block.state != BlockState::Free {
+                            return Ok(());
+                        }
+                        children[count] = i;
+                        count += 1;
+                    }
+                }
+            }
+
+            // Revoking the parent deletes both halves.
+            let parent_slot = blocks[parent].expect("merge into a missing block").slot;
+            parent_slot.revoke().map_err(UntypedAllocError::Kernel)?;
+            for &child in &children[..count] {
+                let slot = blocks[child].expect("merge of a missing block").slot;
+                self.slots.free_slot(slot).map_err(UntypedAllocError::Slot)?;
+                blocks[child] = None;
+            }
+
+            if let Some(ref mut block) = blocks[parent] {
+                block.state = BlockState::Free;
+            }
+            idx = parent;
+        }
+    }
+}
+
+impl<'a, 'b> SlotAllocator for BuddyAllocator<'a, 'b> {
+    type SlotFreeError = UntypedAllocError;
+
+    fn allocate_slot(&self) -> Option<SlotRef> {
+        self.slots.allocate_slot()
+    }
+
+    fn free_slot(&self, slot: SlotRef) -> Result<(), UntypedAllocError> {
+        self.slots.free_slot(slot).map_err(UntypedAllocError::Slot)
+    }
+}
+
+impl<'a, 'b> ObjectAllocator for BuddyAllocator<'a, 'b> {
+    type ObjectAllocError = UntypedAllocError;
+    type ObjectFreeError = UntypedAllocError;
+
+    /// Allocate an object, storing the capability into the specified slot.
+    ///
+    /// Returns `Ok(None)` if there is no free block large enough.
+    fn allocate_object<T: Allocatable>(&self, dest: SlotRef)
+                                       -> Result<Option<T>, UntypedAllocError> {
+        self.allocate_object_sized(dest, 0)
+    }
+
+    /// Free an object, deleting its capability and merging its block with any free buddies.
+    ///
+    /// The slot the object was stored in remains allocated.
+    fn free_object<T: Allocatable>(&self, obj: T) -> Result<(), UntypedAllocError> {
+        let cptr = obj.to_cap();
+        let mut blocks = self.blocks.borrow_mut();
+        let idx = match blocks.iter().position(|b| {
+            b.map_or(false, |b| b.object.map_or(false, |o| o.cptr == cptr))
+        }) {
+            Some(idx) => idx,
+            None => return Err(UntypedAllocError::NotOwned),
+        };
+
+        let object = blocks[idx].and_then(|b| b.object).expect("allocated block has no object");
+        object.delete().map_err(UntypedAllocError::Kernel)?;
+
+        if let Some(ref mut block) = blocks[idx] {
+            block.state = BlockState::Free;
+            block.object = None;
+            block.used = 0;
+        }
+
+        self.merge(&mut **blocks, idx)
+    }
+}
+
+impl<'a, 'b> SizedObjectAllocator for BuddyAllocator<'a, 'b> {
+    /// Allocate an object with the given `size_bits`, storing the capability into the specified
+    /// slot.
+    ///
+    /// Returns `Ok(None)` if there is no free block large enough.
+    fn allocate_object_sized<T: Allocatable>(&self, dest: SlotRef, size_bits: seL4_Word)
+                                             -> Result<Option<T>, UntypedAllocError> {
+        let window = match self.slots.window_for(&dest) {
+            Some(window) => window,
+            None => return Err(UntypedAllocError::ForeignSlot),
+        };
+
+        let obj_size = T::object_size(size_bits) as seL4_Word;
+        let bits = block_bits(obj_size);
+        let mut blocks = self.blocks.borrow_mut();
+
+        let mut best: Option<(usize, u8)> = None;
+        for (i, entry) in blocks.iter().enumerate() {
+            if let Some(ref block) = *entry {
+                if block.state == BlockState::Free && block.size_bits >= bits &&
+                   best.map_or(true, |(_, best_bits)| block.size_bits < best_bits) {
+                    best = Some((i, block.size_bits));
+                }
+            }
+        }
+
+        let mut idx = match best {
+            Some((i, _)) => i,
+            None => return Ok(None),
+        };
+
+        while blocks[idx].as_ref().map_or(0, |b| b.size_bits) > bits {
+            match self.split(&mut **blocks, idx) {
+                Ok(child) => idx = child,
+                Err(e) => {
+                    self.merge(&mut **blocks, idx)?;
+                    return Err(e);
+                }
+            }
+        }
+
+        let untyped = blocks[idx].as_ref().expect("allocated block vanished").untyped;
+        if let Err(e) = untyped.retype::<T>(window, size_bits) {
+            self.merge(&mut **blocks, idx)?;
+            return Err(UntypedAllocError::Kernel(e));
+        }
+
+        if let Some(ref mut block) = blocks[idx] {
+            block.state = BlockState::Allocated;
+            block.object = Some(dest);
+            block.used = obj_size;
+        }
+
+        Ok(Some(T::from_cap(dest.cptr)))
+    }
+}
@@ -9,19 +9,33 @@
 
 //! Traits for basic object allocation and cspace management.
 
+use sel4_sys::seL4_Word;
+
 use {Allocatable, SlotRef};
 
-/// Interface for allocating objects.
-pub trait ObjectAllocator {
-    type ObjectAllocError;
+mod buddy;
+mod slot;
+mod untyped;
+
+pub use self::buddy::{BuddyAllocator, BuddyBlock, BuddyStats, MIN_BLOCK_BITS};
+pub use self::slot::{BitmapSlotAllocator, MAX_SLOT_WINDOWS, SlotAllocError, bitmap_words};
+pub use self::untyped::{Allocation, UntypedAllocError, UntypedAllocator, UntypedRegion};
+
+/// Interface for allocating CSpace slots.
+pub trait SlotAllocator {
     type SlotFreeError;
-    type ObjectFreeError;
 
     /// Otherwise, allocate a slot in this thread's CSpace.
     fn allocate_slot(&self) -> Option<SlotRef>;
 
     /// Mark a slot unused and available for allocation.
     fn free_slot(&self, slot: SlotRef) -> Result<(), Self::SlotFreeError>;
+}
+
+/// Interface for allocating objects, and the slots to hold them.
+pub trait ObjectAllocator: SlotAllocator {
+    type ObjectAllocError;
+    type ObjectFreeError;
 
     /// Allocate an object, storing the capability into the specified slot.
     fn allocate_object<T: Allocatable>(&self, dest: SlotRef)
@@ -31,3 +45,14 @@ pub trait ObjectAllocator {
     /// return the memory for use by the allocator.
     fn free_object<T: Allocatable>(&self, obj: T) -> Result<(), Self::ObjectFreeError>;
 }
+
+/// Allocators which can also create objects of a chosen size.
+pub trait SizedObjectAllocator: ObjectAllocator {
+    /// Allocate an object with the given `size_bits`, storing the capability into the specified
+    /// slot.
+    ///
+    /// `size_bits` is only meaningful for objects which have a variable size, such as `CNode` and
+    /// `Untyped`.
+    fn allocate_object_sized<T: Allocatable>(&self, dest: SlotRef, size_bits: seL4_Word)
+                                             -> Result<Option<T>, Self::ObjectAllocError>;
+}
@@ -0,0 +1,307 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! A bitmap-backed CSpace slot allocator.
+//!
+//! `BitmapSlotAllocator` implements `SlotAllocator`, the slot half of `ObjectAllocator`. It cannot
+//! create objects itself; `UntypedAllocator` and `BuddyAllocator` take their slots from one.
+
+use core::cell::RefCell;
+
+use {CNodeInfo, SlotRef, Window};
+use super::SlotAllocator;
+
+/// Maximum number of windows a single `BitmapSlotAllocator` can manage.
+pub const MAX_SLOT_WINDOWS: usize = 8;
+
+#[inline(always)]
+fn word_bits() -> usize {
+    ::core::mem::size_of::<usize>() * 8
+}
+
+/// Number of bitmap words needed to track `num_slots` slots.
+#[inline(always)]
+pub fn bitmap_words(num_slots: usize) -> usize {
+    (num_slots + word_bits() - 1) / word_bits()
+}
+
+/// Errors from managing slots with a `BitmapSlotAllocator`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SlotAllocError {
+    /// The bitmap storage is too small to track every slot in the window.
+    BitmapTooSmall,
+    /// The allocator is already managing `MAX_SLOT_WINDOWS` windows.
+    TooManyWindows,
+    /// The slot is not in any window managed by this allocator.
+    NotOwned,
+    /// The slot is not currently allocated.
+    NotAllocated,
+}
+
+struct Region<'a> {
+    window: Window,
+    info: CNodeInfo,
+    /// One bit per slot, set if the slot is in use.
+    bitmap: &'a mut [usize],
+    free: usize,
+}
+
+impl<'a> Region<'a> {
+    fn new(window: Window, info: CNodeInfo, bitmap: &'a mut [usize])
+           -> Result<Region<'a>, SlotAllocError> {
+        let words = bitmap_words(window.num_slots);
+        if bitmap.len() < words {
+            return Err(SlotAllocError::BitmapTooSmall);
+        }
+
+        for word in bitmap.iter_mut() {
+            *word = 0;
+        }
+        // Mark the bits past the end of the window as used so they are never handed out.
+        for i in window.num_slots..bitmap.len() * word_bits() {
+            bitmap[i / word_bits()] |= 1 << (i % word_bits());
+        }
+
+        Ok(Region {
+            window: window,
+            info: info,
+            bitmap: bitmap,
+            free: window.num_slots,
+        })
+    }
+
+    #[inline(always)]
+    fn is_used(&self, i: usize) -> bool {
+        self.bitmap[i / word_bits()] & (1 << (i % word_bits())) != 0
+    }
+
+    #[inline(always)]
+    fn set_used(&mut self, i: usize, used: bool) {
+        if used {
+            self.bitmap[i / word_bits()] |= 1 << (i % word_bits());
+        } else {
+            self.bitmap[i / word_bits()] &= !(1 << (i % word_bits()));
+        }
+    }
+
+    fn allocate(&mut self) -> Option<usize> {
+        if self.free == 0 {
+            return None;
+        }
+
+        for (w, word) in self.bitmap.iter_mut().enumerate() {
+            if *word != !0 {
+                let bit = (!*word).trailing_zeros() as usize;
+                *word |= 1 << bit;
+                self.free -= 1;
+                return Some(w * word_bits() + bit);
+            }
+        }
+
+        None
+    }
+
+    fn allocate_range(&mut self, count: usize) -> Option<usize> {
+        if count == 0 || self.free < count {
+            return None;
+        }
+
+        let mut run_start = 0;
+        let mut run_len = 0;
+        for i in 0..self.window.num_slots {
+            if self.is_used(i) {
+                run_len = 0;
+                run_start = i + 1;
+            } else {
+                run_len += 1;
+                if run_len == count {
+                    for j in run_start..run_start + count {
+                        self.set_used(j, true);
+                    }
+                    self.free -= count;
+                    return Some(run_start);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Find the index of `slot` in this region, if it belongs here.
+    fn index_of(&self, slot: &SlotRef) -> Option<usize> {
+        if slot.root != self.window.cnode.root {
+            return None;
+        }
+
+        let radix = self.info.decode(slot.cptr).radix as usize;
+        let idx = radix.wrapping_sub(self.window.first_slot_idx);
+        match self.window.slotref_to(&self.info, idx) {
+            Some(ref s) if s == slot => Some(idx),
+            _ => None,
+        }
+    }
+
+    /// Find the index of the first slot of `window` in this region, if it lies entirely here.
+    fn range_index_of(&self, window: &Window) -> Option<usize> {
+        if window.cnode != self.window.cnode ||
+           window.first_slot_idx < self.window.first_slot_idx {
+            return None;
+        }
+
+        let idx = window.first_slot_idx - self.window.first_slot_idx;
+        if idx + window.num_slots > self.window.num_slots {
+            return None;
+        }
+
+        Some(idx)
+    }
+}
+
+/// A CSpace slot allocator which tracks free slots in one or more `Window`s with a bitmap.
+///
+/// The allocator does not own any memory: each window is paired with caller-provided bitmap
+/// storage of at least `bitmap_words(window.num_slots)` words. All methods take `&self`, so the
+/// allocator can be shared by the other allocators in this crate.
+pub struct BitmapSlotAllocator<'a> {
+    regions: RefCell<[Option<Region<'a>>; MAX_SLOT_WINDOWS]>,
+}
+
+impl<'a> BitmapSlotAllocator<'a> {
+    /// Create an allocator managing every slot in `window`.
+    ///
+    /// `info` describes the CNode that `window` refers into, and is used to construct `SlotRef`s.
+    pub fn new(window: Window, info: CNodeInfo, bitmap: &'a mut [usize])
+               -> Result<BitmapSlotAllocator<'a>, SlotAllocError> {
+        let region = Region::new(window, info, bitmap)?;
+        Ok(BitmapSlotAllocator {
+            regions: RefCell::new([Some(region), None, None, None, None, None, None, None]),
+        })
+    }
+
+    /// Start managing the slots in another window.
+    ///
+    /// This is useful when the existing windows are full and a new CNode has been created.
+    pub fn add_window(&self, window: Window, info: CNodeInfo, bitmap: &'a mut [usize])
+                      -> Result<(), SlotAllocError> {
+        let mut regions = self.regions.borrow_mut();
+        match regions.iter_mut().find(|r| r.is_none()) {
+            Some(entry) => {
+                *entry = Some(Region::new(window, info, bitmap)?);
+                Ok(())
+            }
+            None => Err(SlotAllocError::TooManyWindows),
+        }
+    }
+
+    /// Allocate a single slot.
+    pub fn allocate_slot(&self) -> Option<SlotRef> {
+        let mut regions = self.regions.borrow_mut();
+        for region in regions.iter_mut().filter_map(|r| r.as_mut()) {
+            if let Some(idx) = region.allocate() {
+                return region.window.slotref_to(&region.info, idx);
+            }
+        }
+
+        None
+    }
+
+    /// Mark a slot unused and available for allocation.
+    ///
+    /// This does not delete any capability which may be in the slot.
+    pub fn free_slot(&self, slot: SlotRef) -> Result<(), SlotAllocError> {
+        let mut regions = self.regions.borrow_mut();
+        for region in regions.iter_mut().filter_map(|r| r.as_mut()) {
+            if let Some(idx) = region.index_of(&slot) {
+                if !region.is_used(idx) {
+                    return Err(SlotAllocError::NotAllocated);
+                }
+                region.set_used(idx, false);
+                region.free += 1;
+                return Ok(());
+            }
+        }
+
+        Err(SlotAllocError::NotOwned)
+    }
+
+    /// Allocate `count` contiguous slots, suitable as the destination of a bulk retype.
+    ///
+    /// Returns the window of allocated slots and the information for the CNode it is in.
+    pub fn allocate_range(&self, count: usize) -> Option<(Window, CNodeInfo)> {
+        let mut regions = self.regions.borrow_mut();
+        for region in regions.iter_mut().filter_map(|r| r.as_mut()) {
+            if let Some(idx) = region.allocate_range(count) {
+                let window = Window {
+                    cnode: region.window.cnode,
+                    first_slot_idx: region.window.first_slot_idx + idx,
+                    num_slots: count,
+                };
+                return Some((window, region.info));
+            }
+        }
+
+        None
+    }
+
+    /// Mark every slot in `window`, previously returned by `allocate_range`, as unused.
+    pub fn free_range(&self, window: Window) -> Result<(), SlotAllocError> {
+        let mut regions = self.regions.borrow_mut();
+        for region in regions.iter_mut().filter_map(|r| r.as_mut()) {
+            if let Some(start) = region.range_index_of(&window) {
+                if (start..start + window.num_slots).any(|i| !region.is_used(i)) {
+                    return Err(SlotAllocError::NotAllocated);
+                }
+                for i in start..start + window.num_slots {
+                    region.set_used(i, false);
+                }
+                region.free += window.num_slots;
+                return Ok(());
+            }
+        }
+
+        Err(SlotAllocError::NotOwned)
+    }
+
+    /// The single-slot window addressing `slot`, for use as the destination of a retype.
+    ///
+    /// Returns `None` if `slot` is not managed by this allocator.
+    pub fn window_for(&self, slot: &SlotRef) -> Option<Window> {
+        let regions = self.regions.borrow();
+        for region in regions.iter().filter_map(|r| r.as_ref()) {
+            if let Some(idx) = region.index_of(slot) {
+                return Some(Window {
+                    cnode: region.window.cnode,
+                    first_slot_idx: region.window.first_slot_idx + idx,
+                    num_slots: 1,
+                });
+            }
+        }
+
+        None
+    }
+
+    /// Number of slots available for allocation, across all windows.
+    pub fn available(&self) -> usize {
+        self.regions.borrow().iter().filter_map(|r| r.as_ref()).map(|r| r.free).sum()
+    }
+}
+
+impl<'a> SlotAllocator for BitmapSlotAllocator<'a> {
+    type SlotFreeError = SlotAllocError;
+
+    #[inline(always)]
+    fn allocate_slot(&self) -> Option<SlotRef> {
+        BitmapSlotAllocator::allocate_slot(self)
+    }
+
+    #[inline(always)]
+    fn free_slot(&self, slot: SlotRef) -> Result<(), SlotAllocError> {
+        BitmapSlotAllocator::free_slot(self, slot)
+    }
+}
@@ -0,0 +1,234 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! A watermark allocator over untyped memory.
+
+use core::cell::RefCell;
+
+use sel4_sys::seL4_Word;
+
+use {Allocatable, CNode, SlotRef, UntypedDescriptor};
+use super::{BitmapSlotAllocator, ObjectAllocator, SizedObjectAllocator, SlotAllocator,
+            SlotAllocError};
+
+/// Errors from allocating and freeing objects with an `UntypedAllocator`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UntypedAllocError {
+    /// The destination slot was not handed out by the allocator's slot allocator.
+    ForeignSlot,
+    /// The object was not allocated by this allocator.
+    NotOwned,
+    /// Device memory can only be retyped into frames, so it cannot be used by this allocator.
+    DeviceMemory,
+    /// The storage given for tracking untyped regions or live objects is full.
+    OutOfStorage,
+    /// The slot allocator has no free slots left.
+    OutOfSlots,
+    /// Managing the slot failed.
+    Slot(SlotAllocError),
+    /// Invoking the kernel failed.
+    Kernel(::Error),
+}
+
+/// Bookkeeping for a single untyped region.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct UntypedRegion {
+    desc: UntypedDescriptor,
+    /// Offset of the first unused byte in the region.
+    watermark: seL4_Word,
+    /// Number of live objects retyped from the region.
+    children: usize,
+}
+
+/// Bookkeeping for a single live object.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Allocation {
+    slot: SlotRef,
+    region: usize,
+}
+
+#[inline(always)]
+fn align_up(val: seL4_Word, align: seL4_Word) -> seL4_Word {
+    (val + align - 1) & !(align - 1)
+}
+
+/// An `ObjectAllocator` which carves objects out of untyped memory in order.
+///
+/// Each untyped region has a watermark, below which all memory has been handed out. New objects
+/// are placed at the watermark, rounded up to the object's natural alignment the same way the
+/// kernel does. Memory below the watermark is only reused once every object in the region has
+/// been freed, at which point the untyped capability is revoked and the watermark reset.
+///
+/// Slots come from a `BitmapSlotAllocator`, and the destination slot given to `allocate_object`
+/// must have been allocated from it. The allocator keeps no memory of its own: the caller
+/// provides storage for the untyped regions and one entry per live object.
+pub struct UntypedAllocator<'a, 'b: 'a> {
+    slots: &'a BitmapSlotAllocator<'b>,
+    root: CNode,
+    regions: RefCell<&'a mut [Option<UntypedRegion>]>,
+    allocations: RefCell<&'a mut [Option<Allocation>]>,
+}
+
+impl<'a, 'b> UntypedAllocator<'a, 'b> {
+    /// Create an allocator with no untyped memory.
+    ///
+    /// `root` is the root CNode of the current CSpace, which is used to revoke the untyped
+    /// capabilities. Every entry of `regions` and `allocations` is cleared.
+    pub fn new(slots: &'a BitmapSlotAllocator<'b>, root: CNode,
+               regions: &'a mut [Option<UntypedRegion>],
+               allocations: &'a mut [Option<Allocation>])
+               -> UntypedAllocator<'a, 'b> {
+        for region in regions.iter_mut() {
+            *region = None;
+        }
+        for allocation in allocations.iter_mut() {
+            *allocation = None;
+        }
+
+        UntypedAllocator {
+            slots: slots,
+            root: root,
+            regions: RefCell::new(regions),
+            allocations: RefCell::new(allocations),
+        }
+    }
+
+    /// Make the memory in `desc` available for allocation.
+    ///
+    /// The untyped must not have any children yet.
+    pub fn add_untyped(&self, desc: UntypedDescriptor) -> Result<(), UntypedAllocError> {
+        if desc.is_device {
+            return Err(UntypedAllocError::DeviceMemory);
+        }
+
+        let mut regions = self.regions.borrow_mut();
+        match regions.iter_mut().find(|r| r.is_none()) {
+            Some(entry) => {
+                *entry = Some(UntypedRegion {
+                    desc: desc,
+                    watermark: 0,
+                    children: 0,
+                });
+                Ok(())
+            }
+            None => Err(UntypedAllocError::OutOfStorage),
+        }
+    }
+
+    /// Number of bytes available above the watermarks of all regions.
+    ///
+    /// Alignment padding means not all of this is necessarily usable.
+    pub fn available(&self) -> seL4_Word {
+        self.regions
+            .borrow()
+            .iter()
+            .filter_map(|r| r.as_ref())
+            .map(|r| r.desc.size() - r.watermark)
+            .sum()
+    }
+}
+
+impl<'a, 'b> SlotAllocator for UntypedAllocator<'a, 'b> {
+    type SlotFreeError = UntypedAllocError;
+
+    fn allocate_slot(&self) -> Option<SlotRef> {
+        self.slots.allocate_slot()
+    }
+
+    fn free_slot(&self, slot: SlotRef) -> Result<(), UntypedAllocError> {
+        self.slots.free_slot(slot).map_err(UntypedAllocError::Slot)
+    }
+}
+
+impl<'a, 'b> ObjectAllocator for UntypedAllocator<'a, 'b> {
+    type ObjectAllocError = UntypedAllocError;
+    type ObjectFreeError = UntypedAllocError;
+
+    /// Allocate an object, storing the capability into the specified slot.
+    ///
+    /// Returns `Ok(None)` if no region has enough memory left.
+    fn allocate_object<T: Allocatable>(&self, dest: SlotRef)
+                                       -> Result<Option<T>, UntypedAllocError> {
+        self.allocate_object_sized(dest, 0)
+    }
+
+    /// Free an object, deleting its capability.
+    ///
+    /// The slot the object was stored in remains allocated.
+    fn free_object<T: Allocatable>(&self, obj: T) -> Result<(), UntypedAllocError> {
+        let cptr = obj.to_cap();
+        let mut allocations = self.allocations.borrow_mut();
+        let record = match allocations.iter_mut()
+            .find(|a| a.map_or(false, |a| a.slot.cptr == cptr)) {
+            Some(record) => record,
+            None => return Err(UntypedAllocError::NotOwned),
+        };
+        let allocation = record.expect("matched allocation record was empty");
+
+        allocation.slot.delete().map_err(UntypedAllocError::Kernel)?;
+        *record = None;
+
+        let mut regions = self.regions.borrow_mut();
+        if let Some(ref mut region) = regions[allocation.region] {
+            region.children -= 1;
+            if region.children == 0 {
+                region.desc.cap.revoke_all(self.root).map_err(UntypedAllocError::Kernel)?;
+                region.watermark = 0;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a, 'b> SizedObjectAllocator for UntypedAllocator<'a, 'b> {
+    /// Allocate an object with the given `size_bits`, storing the capability into the specified
+    /// slot.
+    ///
+    /// Returns `Ok(None)` if no region has enough memory left.
+    fn allocate_object_sized<T: Allocatable>(&self, dest: SlotRef, size_bits: seL4_Word)
+                                             -> Result<Option<T>, UntypedAllocError> {
+        let window = match self.slots.window_for(&dest) {
+            Some(window) => window,
+            None => return Err(UntypedAllocError::ForeignSlot),
+        };
+
+        let mut allocations = self.allocations.borrow_mut();
+        let record = match allocations.iter_mut().find(|a| a.is_none()) {
+            Some(record) => record,
+            None => return Err(UntypedAllocError::OutOfStorage),
+        };
+
+        let obj_size = T::object_size(size_bits) as seL4_Word;
+        let mut regions = self.regions.borrow_mut();
+        for (i, entry) in regions.iter_mut().enumerate() {
+            let region = match *entry {
+                Some(ref mut region) => region,
+                None => continue,
+            };
+
+            let offset = align_up(region.watermark, obj_size);
+            if offset + obj_size > region.desc.size() {
+                continue;
+            }
+
+            region.desc.cap.retype::<T>(window, size_bits).map_err(UntypedAllocError::Kernel)?;
+            region.watermark = offset + obj_size;
+            region.children += 1;
+            *record = Some(Allocation {
+                slot: dest,
+                region: i,
+            });
+
+            return Ok(Some(T::from_cap(dest.cptr)));
+        }
+
+        Ok(None)
+    }
+}
@@ -9,7 +9,7 @@
 
 use sel4_sys::*;
 
-use ToCap;
+use {CapRights, ToCap};
 
 cap_wrapper!{ ()
     /// Authority to allocate ASID pools
@@ -18,7 +18,7 @@ cap_wrapper!{ ()
     ASIDPool,
 
     /// A 4K page of physical memory mapped into a page table
-    SmallPage = seL4_ARM_SmallPageObject |_| 1 << 10,
+    SmallPage = seL4_ARM_SmallPageObject |_| 1 << 12,
     /// A 64K page of physical memory mapped into a page table
     LargePage = seL4_ARM_LargePageObject |_| 1 << 16,
     /// A 1M page of physical memory mapped into a page directory
@@ -61,16 +61,16 @@ macro_rules! page_impls {
 impl $name {
     /// Map this page into an address space.
     #[inline(always)]
-    pub fn map(&self, pd: PageDirectory, addr: seL4_Word, rights: seL4_CapRights,
+    pub fn map(&self, pd: PageDirectory, addr: seL4_Word, rights: CapRights,
                attr: seL4_ARM_VMAttributes) -> ::Result {
-        unsafe_as_result!(seL4_ARM_Page_Map(self.cptr, pd.to_cap(), addr, rights, attr))
+        unsafe_as_result!(seL4_ARM_Page_Map(self.cptr, pd.to_cap(), addr, rights.to_raw(), attr))
     }
 
     /// Remap this page, possibly changing rights or attribute but not address.
     #[inline(always)]
-    pub fn remap(&self, pd: PageDirectory, rights: seL4_CapRights,
+    pub fn remap(&self, pd: PageDirectory, rights: CapRights,
                  attr: seL4_ARM_VMAttributes) -> ::Result {
-        unsafe_as_result!(seL4_ARM_Page_Remap(self.cptr, pd.to_cap(), rights, attr))
+        unsafe_as_result!(seL4_ARM_Page_Remap(self.cptr, pd.to_cap(), rights.to_raw(), attr))
     }
 
     /// Unmap this page.
@@ -92,6 +92,8 @@ impl $name {
         }
     }
 }
+
+frame_rights!($name);
 }}
 
 page_impls!(SmallPage);
@@ -9,7 +9,7 @@
 
 use sel4_sys::*;
 
-use ToCap;
+use {CapRights, ToCap};
 
 cap_wrapper!{ ()
     /// Authority to create ASID pools
@@ -22,15 +22,15 @@ cap_wrapper!{ ()
     IOSpace,
 
     /// A page table for the IOMMU
-    IOPageTable = seL4_X86_IOPageTableObject |_| 1 << 10,
+    IOPageTable = seL4_X86_IOPageTableObject |_| 1 << 12,
     /// A page of physical memory that can be mapped into a vspace
-    Page = seL4_X86_4K |_| 1 << 10,
+    Page = seL4_X86_4K |_| 1 << 12,
     /// A 'large page' (4MiB) for use with PAE
     LargePage = seL4_X86_LargePageObject |_| 1 << 22,
     /// A page table, which can have pages mapped into it
-    PageTable = seL4_X86_PageTableObject |_| 1 << 10,
+    PageTable = seL4_X86_PageTableObject |_| 1 << 12,
     /// A page directory, which holds page tables and forms the root of the vspace
-    PageDirectory = seL4_X86_PageDirectoryObject |_| 1 << 10,
+    PageDirectory = seL4_X86_PageDirectoryObject |_| 1 << 12,
 }
 
 impl ASIDControl {
@@ -123,22 +123,22 @@ impl IOPageTable {
 impl Page {
     /// Map this page into an IOSpace with `rights` at `addr`.
     #[inline(always)]
-    pub fn map_io(&self, iospace: IOSpace, rights: seL4_CapRights, addr: seL4_Word) -> ::Result {
-        unsafe_as_result!(seL4_X86_Page_MapIO(self.cptr, iospace.to_cap(), rights, addr))
+    pub fn map_io(&self, iospace: IOSpace, rights: CapRights, addr: seL4_Word) -> ::Result {
+        unsafe_as_result!(seL4_X86_Page_MapIO(self.cptr, iospace.to_cap(), rights.to_raw(), addr))
     }
 
     /// Map this page into an address space.
     #[inline(always)]
-    pub fn map(&self, pd: PageDirectory, addr: seL4_Word, rights: seL4_CapRights,
+    pub fn map(&self, pd: PageDirectory, addr: seL4_Word, rights: CapRights,
                attr: seL4_X86_VMAttributes) -> ::Result {
-        unsafe_as_result!(seL4_X86_Page_Map(self.cptr, pd.to_cap(), addr, rights, attr))
+        unsafe_as_result!(seL4_X86_Page_Map(self.cptr, pd.to_cap(), addr, rights.to_raw(), attr))
     }
 
     /// Remap this page, possibly changing rights or attribute but not address.
     #[inline(always)]
-    pub fn remap(&self, pd: PageDirectory, rights: seL4_CapRights, attr: seL4_X86_VMAttributes)
+    pub fn remap(&self, pd: PageDirectory, rights: CapRights, attr: seL4_X86_VMAttributes)
                  -> ::Result {
-        unsafe_as_result!(seL4_X86_Page_Remap(self.cptr, pd.to_cap(), rights, attr))
+        unsafe_as_result!(seL4_X86_Page_Remap(self.cptr, pd.to_cap(), rights.to_raw(), attr))
     }
 
     /// Unmap this page.
@@ -161,6 +161,8 @@ impl Page {
     }
 }
 
+frame_rights!(Page, LargePage);
+
 impl PageTable {
     /// Map this page table into an address space.
     #[inline(always)]
@@ -9,7 +9,7 @@
 
 use sel4_sys::*;
 
-use ToCap;
+use {CapRights, ToCap};
 
 cap_wrapper!{ ()
     /// Authority to create ASID pools
@@ -139,22 +139,22 @@ impl IOPageTable {
 impl Page {
     /// Map this page into an IOSpace with `rights` at `addr`.
     #[inline(always)]
-    pub fn map_io(&self, iospace: IOSpace, rights: seL4_CapRights, addr: seL4_Word) -> ::Result {
-        unsafe_as_result!(seL4_X86_Page_MapIO(self.cptr, iospace.to_cap(), rights, addr))
+    pub fn map_io(&self, iospace: IOSpace, rights: CapRights, addr: seL4_Word) -> ::Result {
+        unsafe_as_result!(seL4_X86_Page_MapIO(self.cptr, iospace.to_cap(), rights.to_raw(), addr))
     }
 
     /// Map this page into an address space.
     #[inline(always)]
-    pub fn map(&self, pd: PageDirectory, addr: seL4_Word, rights: seL4_CapRights,
+    pub fn map(&self, pd: PageDirectory, addr: seL4_Word, rights: CapRights,
                attr: seL4_X86_VMAttributes) -> ::Result {
-        unsafe_as_result!(seL4_X86_Page_Map(self.cptr, pd.to_cap(), addr, rights, attr))
+        unsafe_as_result!(seL4_X86_Page_Map(self.cptr, pd.to_cap(), addr, rights.to_raw(), attr))
     }
 
     /// Remap this page, possibly changing rights or attribute but not address.
     #[inline(always)]
-    pub fn remap(&self, pd: PageDirectory, rights: seL4_CapRights, attr: seL4_X86_VMAttributes)
+    pub fn remap(&self, pd: PageDirectory, rights: CapRights, attr: seL4_X86_VMAttributes)
                  -> ::Result {
-        unsafe_as_result!(seL4_X86_Page_Remap(self.cptr, pd.to_cap(), rights, attr))
+        unsafe_as_result!(seL4_X86_Page_Remap(self.cptr, pd.to_cap(), rights.to_raw(), attr))
     }
 
     /// Unmap this page.
@@ -175,6 +175,8 @@ impl Page {
     }
 }
 
+frame_rights!(Page, LargePage, HugePage);
+
 impl PageTable {
     /// Map this page table into an address space.
     #[inline(always)]
@@ -0,0 +1,206 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Serving many clients on one endpoint, told apart by badge
+//!
+//! A server gives each client its own badged copy of an endpoint. The kernel reports the badge of
+//! the capability each message was sent through, so the server knows who it is talking to.
+//! `BadgeAllocator` hands out those badges from a range and remembers what each belongs to, and
+//! `BadgeDispatcher` routes received messages to the handler for their badge.
+
+use sel4_sys::seL4_Word;
+
+use {Badge, CapRights, RecvToken, SlotRef};
+
+/// The largest badge value which fits in a capability on every architecture.
+pub const MAX_BADGE: u32 = (1 << 28) - 1;
+
+/// Errors from managing badges with a `BadgeAllocator`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BadgeError {
+    /// The range of badges includes 0, which marks an unbadged capability, or goes past
+    /// `MAX_BADGE`.
+    InvalidRange,
+    /// Every badge in the range is in use.
+    Exhausted,
+    /// The badge was not handed out by this allocator.
+    NotAllocated,
+    /// Invoking the kernel failed.
+    Kernel(::Error),
+}
+
+/// A client known to a `BadgeAllocator`.
+#[derive(Debug)]
+pub struct BadgedClient<T> {
+    /// The badge given to the client.
+    pub badge: Badge,
+    /// The slot holding the badged capability the client's copies derive from.
+    pub slot: SlotRef,
+    /// What the server associates with the client.
+    pub data: T,
+}
+
+/// Mints badged copies of an endpoint, each with a distinct badge.
+///
+/// Badges are taken from `first..first + storage.len()`, one per entry of the caller-provided
+/// storage.
+pub struct BadgeAllocator<'a, T: 'a> {
+    endpoint: SlotRef,
+    first: u32,
+    clients: &'a mut [Option<BadgedClient<T>>],
+}
+
+impl<'a, T> BadgeAllocator<'a, T> {
+    /// Create an allocator minting from the unbadged capability in `endpoint`.
+    pub fn new(endpoint: SlotRef, first: u32, storage: &'a mut [Option<BadgedClient<T>>])
+               -> Result<BadgeAllocator<'a, T>, BadgeError> {
+        if first == 0 || first > MAX_BADGE ||
+           storage.len() as u64 > (MAX_BADGE - first) as u64 + 1 {
+            return Err(BadgeError::InvalidRange);
+        }
+        for client in storage.iter_mut() {
+            *client = None;
+        }
+        Ok(BadgeAllocator {
+            endpoint: endpoint,
+            first: first,
+            clients: storage,
+        })
+    }
+
+    /// Mint a capability to the endpoint into `dest` with a fresh badge, and remember `data` as
+    /// belonging to it.
+    ///
+    /// The capability in `dest` is what to give the client, with `SlotRef::copy` or by sending it.
+    pub fn mint(&mut self, dest: SlotRef, rights: CapRights, data: T)
+                -> Result<Badge, BadgeError> {
+        let idx = match self.clients.iter().position(|c| c.is_none()) {
+            Some(idx) => idx,
+            None => return Err(BadgeError::Exhausted),
+        };
+        let badge = Badge::new(self.first + idx as u32);
+        self.endpoint.mint(dest, rights, badge).map_err(BadgeError::Kernel)?;
+        self.clients[idx] = Some(BadgedClient {
+            badge: badge,
+            slot: dest,
+            data: data,
+        });
+        Ok(badge)
+    }
+
+    fn index_of(&self, badge: seL4_Word) -> Option<usize> {
+        let idx = badge.wrapping_sub(self.first as seL4_Word) as usize;
+        match self.clients.get(idx) {
+            Some(&Some(_)) => Some(idx),
+            _ => None,
+        }
+    }
+
+    /// The client holding `badge`, as reported in `RecvToken::badge`.
+    #[inline(always)]
+    pub fn get(&self, badge: seL4_Word) -> Option<&BadgedClient<T>> {
+        self.index_of(badge).and_then(move |idx| self.clients[idx].as_ref())
+    }
+
+    /// The client holding `badge`, mutably.
+    #[inline(always)]
+    pub fn get_mut(&mut self, badge: seL4_Word) -> Option<&mut BadgedClient<T>> {
+        match self.index_of(badge) {
+            Some(idx) => self.clients[idx].as_mut(),
+            None => None,
+        }
+    }
+
+    /// Take away a client's access, returning what was associated with it.
+    ///
+    /// Every capability derived from the client's badged capability is revoked, messages the
+    /// client sent which are still queued are cancelled, and the badged capability is deleted.
+    /// The badge can then be reused for a new client without it receiving stale messages.
+    pub fn revoke(&mut self, badge: seL4_Word) -> Result<T, BadgeError> {
+        let idx = match self.index_of(badge) {
+            Some(idx) => idx,
+            None => return Err(BadgeError::NotAllocated),
+        };
+        {
+            let slot = self.clients[idx].as_ref().unwrap().slot;
+            slot.revoke().map_err(BadgeError::Kernel)?;
+            slot.cancel_badged_sends().map_err(BadgeError::Kernel)?;
+            slot.delete().map_err(BadgeError::Kernel)?;
+        }
+        Ok(self.clients[idx].take().unwrap().data)
+    }
+
+    /// Number of badges which can still be handed out.
+    pub fn available(&self) -> usize {
+        self.clients.iter().filter(|c| c.is_none()).count()
+    }
+}
+
+/// Something which handles the messages sent by one client.
+pub trait BadgeHandler {
+    /// The result of handling a message, such as a reply to send.
+    type Output;
+
+    fn handle(&mut self, token: &RecvToken) -> Self::Output;
+}
+
+impl<'a, R> BadgeHandler for &'a mut dyn FnMut(&RecvToken) -> R {
+    type Output = R;
+
+    #[inline(always)]
+    fn handle(&mut self, token: &RecvToken) -> R {
+        self(token)
+    }
+}
+
+/// Routes messages to a handler per badge.
+pub struct BadgeDispatcher<'a, T: BadgeHandler + 'a> {
+    badges: BadgeAllocator<'a, T>,
+}
+
+impl<'a, T: BadgeHandler> BadgeDispatcher<'a, T> {
+    #[inline(always)]
+    pub fn new(badges: BadgeAllocator<'a, T>) -> BadgeDispatcher<'a, T> {
+        BadgeDispatcher { badges: badges }
+    }
+
+    /// Add a client, minting its capability into `dest`. Its messages go to `handler`.
+    #[inline(always)]
+    pub fn add(&mut self, dest: SlotRef, rights: CapRights, handler: T)
+               -> Result<Badge, BadgeError> {
+        self.badges.mint(dest, rights, handler)
+    }
+
+    /// Remove a client, cancelling its pending messages and returning its handler.
+    #[inline(always)]
+    pub fn revoke(&mut self, badge: seL4_Word) -> Result<T, BadgeError> {
+        self.badges.revoke(badge)
+    }
+
+    /// Pass a received message to the handler for its badge.
+    ///
+    /// Returns `None` if no client holds the badge, such as for a message sent through the
+    /// unbadged endpoint.
+    #[inline(always)]
+    pub fn dispatch(&mut self, token: &RecvToken) -> Option<T::Output> {
+        self.badges.get_mut(token.badge).map(|client| client.data.handle(token))
+    }
+
+    /// The underlying badge allocator.
+    #[inline(always)]
+    pub fn badges(&self) -> &BadgeAllocator<'a, T> {
+        &self.badges
+    }
+
+    /// The underlying badge allocator, mutably.
+    #[inline(always)]
+    pub fn badges_mut(&mut self) -> &mut BadgeAllocator<'a, T> {
+        &mut self.badges
+    }
+}
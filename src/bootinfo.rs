@@ -0,0 +1,230 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Interpreting the boot information given to the root task.
+//!
+//! When the kernel starts the root task, it passes a pointer to a `seL4_BootInfo` frame which
+//! describes the initial CSpace: which slots hold which capabilities, which slots are empty, and
+//! what untyped memory is available. `BootInfo` wraps that frame and hands out the types from this
+//! crate instead of raw CPtrs.
+
+use sel4_sys::*;
+
+use {ASIDControl, ASIDPool, CNode, CNodeInfo, DomainSet, IRQControl, SlotRef, Thread, Untyped,
+     UntypedDescriptor, Window};
+
+/// A typed view of the `seL4_BootInfo` frame.
+#[derive(Copy, Clone)]
+pub struct BootInfo {
+    raw: &'static seL4_BootInfo,
+}
+
+impl BootInfo {
+    /// Wrap the bootinfo frame at `ptr`.
+    ///
+    /// This is unsafe because `ptr` must point to a valid, mapped `seL4_BootInfo` which is never
+    /// unmapped or modified.
+    #[inline(always)]
+    pub unsafe fn from_raw(ptr: *const seL4_BootInfo) -> BootInfo {
+        BootInfo { raw: &*ptr }
+    }
+
+    /// Access the underlying `seL4_BootInfo`.
+    #[inline(always)]
+    pub fn raw(&self) -> &'static seL4_BootInfo {
+        self.raw
+    }
+
+    /// The CNode at the root of the initial thread's CSpace.
+    #[inline(always)]
+    pub fn root_cnode(&self) -> CNode {
+        CNode::from_cap(seL4_CapInitThreadCNode)
+    }
+
+    /// Addressing information for the root CNode.
+    ///
+    /// The kernel sets the guard of the initial CNode to zero and makes it as large as needed for
+    /// a single level lookup to consume the whole CPtr.
+    #[inline(always)]
+    pub fn root_cnode_info(&self) -> CNodeInfo {
+        let radix_bits = self.raw.initThreadCNodeSizeBits as u8;
+        CNodeInfo {
+            guard_val: 0,
+            radix_bits: radix_bits,
+            guard_bits: (word_bits() as u8).wrapping_sub(radix_bits),
+            prefix_bits: 0,
+        }
+    }
+
+    /// The empty slots in the root CNode, which are free for use.
+    #[inline(always)]
+    pub fn empty_slots(&self) -> Window {
+        self.region_window(&self.raw.empty)
+    }
+
+    /// Slots holding the frames that back the root task's image.
+    #[inline(always)]
+    pub fn user_image_frames(&self) -> Window {
+        self.region_window(&self.raw.userImageFrames)
+    }
+
+    /// Slots holding the paging structures that map the root task's image.
+    #[inline(always)]
+    pub fn user_image_paging(&self) -> Window {
+        self.region_window(&self.raw.userImagePaging)
+    }
+
+    /// Slots holding frames shared with other nodes.
+    #[inline(always)]
+    pub fn shared_frames(&self) -> Window {
+        self.region_window(&self.raw.sharedFrames)
+    }
+
+    /// Slots holding the untyped memory capabilities.
+    #[inline(always)]
+    pub fn untyped_slots(&self) -> Window {
+        self.region_window(&self.raw.untyped)
+    }
+
+    /// Iterate over the untyped memory given to the root task.
+    #[inline(always)]
+    pub fn untyped(&self) -> UntypedIter {
+        UntypedIter {
+            info: *self,
+            idx: 0,
+        }
+    }
+
+    /// The node this root task is running on.
+    #[inline(always)]
+    pub fn node_id(&self) -> seL4_Word {
+        self.raw.nodeID as seL4_Word
+    }
+
+    /// The total number of nodes in the system.
+    #[inline(always)]
+    pub fn num_nodes(&self) -> seL4_Word {
+        self.raw.numNodes
+    }
+
+    /// The address of the root task's IPC buffer.
+    #[inline(always)]
+    pub fn ipc_buffer(&self) -> *mut seL4_IPCBuffer {
+        self.raw.ipcBuffer
+    }
+
+    /// The domain the root task is running in.
+    #[inline(always)]
+    pub fn init_thread_domain(&self) -> u8 {
+        self.raw.initThreadDomain as u8
+    }
+
+    /// The root task's TCB.
+    #[inline(always)]
+    pub fn thread(&self) -> Thread {
+        Thread::from_cap(seL4_CapInitThreadTCB)
+    }
+
+    /// Authority to create IRQ handlers.
+    #[inline(always)]
+    pub fn irq_control(&self) -> IRQControl {
+        IRQControl::from_cap(seL4_CapIRQControl)
+    }
+
+    /// Authority to create ASID pools.
+    #[inline(always)]
+    pub fn asid_control(&self) -> ASIDControl {
+        ASIDControl::from_cap(seL4_CapASIDControl)
+    }
+
+    /// The ASID pool the root task's VSpace is assigned to.
+    #[inline(always)]
+    pub fn asid_pool(&self) -> ASIDPool {
+        ASIDPool::from_cap(seL4_CapInitThreadASIDPool)
+    }
+
+    /// Authority to set the domain of threads.
+    #[inline(always)]
+    pub fn domain_set(&self) -> DomainSet {
+        DomainSet::from_cap(seL4_CapDomain)
+    }
+
+    /// The root of the root task's VSpace.
+    #[cfg(any(target_arch = "x86", target_arch = "arm"))]
+    #[inline(always)]
+    pub fn vspace_root(&self) -> ::PageDirectory {
+        ::PageDirectory::from_cap(seL4_CapInitThreadVSpace)
+    }
+
+    /// The root of the root task's VSpace.
+    #[cfg(target_arch = "x86_64")]
+    #[inline(always)]
+    pub fn vspace_root(&self) -> ::PML4 {
+        ::PML4::from_cap(seL4_CapInitThreadVSpace)
+    }
+
+    /// Authority to use all IO ports.
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    #[inline(always)]
+    pub fn io_port(&self) -> ::IOPort {
+        ::IOPort::from_cap(seL4_CapIOPort)
+    }
+
+    /// Authority to create IO spaces.
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    #[inline(always)]
+    pub fn io_space(&self) -> ::IOSpace {
+        ::IOSpace::from_cap(seL4_CapIOSpace)
+    }
+
+    fn region_window(&self, region: &seL4_SlotRegion) -> Window {
+        Window {
+            // depth 0 refers to the root CNode itself
+            cnode: SlotRef::new(self.root_cnode(), 0, 0),
+            first_slot_idx: region.start as usize,
+            num_slots: region.end.wrapping_sub(region.start) as usize,
+        }
+    }
+}
+
+/// Iterator over the untyped memory described by the bootinfo.
+pub struct UntypedIter {
+    info: BootInfo,
+    idx: usize,
+}
+
+impl Iterator for UntypedIter {
+    type Item = UntypedDescriptor;
+
+    fn next(&mut self) -> Option<UntypedDescriptor> {
+        let raw = self.info.raw;
+        let count = raw.untyped.end.wrapping_sub(raw.untyped.start) as usize;
+        if self.idx >= count {
+            return None;
+        }
+
+        let desc = &raw.untypedList[self.idx];
+        let cap = Untyped::from_cap(raw.untyped.start.wrapping_add(self.idx as seL4_Word));
+        self.idx += 1;
+
+        Some(cap.describe(desc.paddr, desc.sizeBits as u8, desc.isDevice != 0))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let raw = self.info.raw;
+        let count = raw.untyped.end.wrapping_sub(raw.untyped.start) as usize;
+        let left = count.saturating_sub(self.idx);
+        (left, Some(left))
+    }
+}
+
+#[inline(always)]
+fn word_bits() -> usize {
+    ::core::mem::size_of::<seL4_Word>() * 8
+}
@@ -0,0 +1,214 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Sending buffers larger than a message through shared memory
+//!
+//! A message can carry at most `seL4_MsgMaxLength` words. `BulkSender` and `BulkReceiver` move
+//! byte buffers of any size over an endpoint by copying them through a frame (or run of frames)
+//! mapped into both address spaces. Mapping the memory is up to the caller.
+//!
+//! Each transfer is sent with the channel's label, and its first word is the total length in
+//! bytes. If the bytes fit in the remaining message registers they follow directly, and the
+//! message is sent with `send`. Otherwise the buffer is sent in chunks the size of the shared
+//! memory: the sender writes a chunk, then `call`s with the total length, the chunk's offset and
+//! its length. The receiver copies the chunk out before replying, so the sender never overwrites
+//! a chunk which has not been read.
+
+use core::{mem, ptr};
+
+use sel4_sys::{seL4_MsgMaxLength, seL4_Word};
+
+use {Endpoint, MessageBuilder, RecvToken, reply_built};
+
+/// Label of the reply acknowledging a chunk.
+const CHUNK_ACK: seL4_Word = 0;
+
+/// Label of the reply refusing a chunk, clear of the kernel's error codes like `REPLY_REJECTED`.
+const CHUNK_REJECTED: seL4_Word = 0xf_ffff;
+
+/// The largest transfer which is sent in message registers rather than shared memory.
+#[inline(always)]
+pub fn bulk_inline_bytes() -> usize {
+    (seL4_MsgMaxLength - 1) * mem::size_of::<seL4_Word>()
+}
+
+/// Errors from transferring a buffer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BulkError {
+    /// The buffer does not fit where it is being received into, or needs shared memory and the
+    /// channel has none.
+    TooLarge,
+    /// The receiver refused a chunk.
+    Rejected,
+    /// A message was not part of a transfer, or arrived out of order.
+    Malformed,
+    /// Invoking the kernel failed.
+    Kernel(::Error),
+}
+
+/// The sending side of a bulk channel.
+#[derive(Debug)]
+pub struct BulkSender {
+    ep: Endpoint,
+    label: seL4_Word,
+    buf: *mut u8,
+    size: usize,
+}
+
+impl BulkSender {
+    /// Send through `ep` with `label`, using the `size` bytes of shared memory at `buf`.
+    ///
+    /// This is unsafe because `buf` must be valid for writes of `size` bytes for as long as the
+    /// sender is used, and must be the memory the receiver reads from.
+    #[inline(always)]
+    pub unsafe fn new(ep: Endpoint, label: seL4_Word, buf: *mut u8, size: usize) -> BulkSender {
+        BulkSender {
+            ep: ep,
+            label: label,
+            buf: buf,
+            size: size,
+        }
+    }
+
+    /// Send `data`, blocking until the receiver has taken all of it.
+    ///
+    /// Small buffers are sent in message registers and return as soon as they are delivered. A
+    /// chunk the receiver refuses fails with `Rejected`, and any other reply label is the kernel
+    /// failing the call.
+    pub fn send(&self, data: &[u8]) -> Result<(), BulkError> {
+        let too_much = |_| BulkError::TooLarge;
+        let mut msg = MessageBuilder::new(self.label);
+        msg.push_word(data.len() as seL4_Word).map_err(too_much)?;
+        if data.len() <= bulk_inline_bytes() {
+            msg.push_bytes(data).map_err(too_much)?;
+            return self.ep.send_built(msg).map_err(BulkError::Kernel);
+        }
+        if self.size == 0 {
+            return Err(BulkError::TooLarge);
+        }
+
+        for (i, chunk) in data.chunks(self.size).enumerate() {
+            unsafe {
+                ptr::copy_nonoverlapping(chunk.as_ptr(), self.buf, chunk.len());
+            }
+            let mut msg = MessageBuilder::new(self.label);
+            msg.push_words(&[data.len() as seL4_Word,
+                             (i * self.size) as seL4_Word,
+                             chunk.len() as seL4_Word])
+               .map_err(too_much)?;
+            match self.ep.call_built(msg).label {
+                CHUNK_ACK => {}
+                CHUNK_REJECTED => return Err(BulkError::Rejected),
+                _ => return Err(BulkError::Kernel(::Error(::GoOn::CheckIPCBuf))),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The receiving side of a bulk channel.
+///
+/// It does not receive messages itself, so that a server can receive from many clients on one
+/// endpoint and pass each transfer message to the right client's receiver.
+#[derive(Debug)]
+pub struct BulkReceiver {
+    label: seL4_Word,
+    buf: *const u8,
+    size: usize,
+    received: usize,
+}
+
+impl BulkReceiver {
+    /// Receive transfers with `label`, through the `size` bytes of shared memory at `buf`.
+    ///
+    /// This is unsafe because `buf` must be valid for reads of `size` bytes for as long as the
+    /// receiver is used, and must be the memory the sender writes to.
+    #[inline(always)]
+    pub unsafe fn new(label: seL4_Word, buf: *const u8, size: usize) -> BulkReceiver {
+        BulkReceiver {
+            label: label,
+            buf: buf,
+            size: size,
+            received: 0,
+        }
+    }
+
+    /// The label transfers to this receiver are sent with.
+    #[inline(always)]
+    pub fn label(&self) -> seL4_Word {
+        self.label
+    }
+
+    /// Number of bytes of the current transfer received so far.
+    #[inline(always)]
+    pub fn received(&self) -> usize {
+        self.received
+    }
+
+    /// Take the part of a transfer carried by the message in `token`, storing it in `out`.
+    ///
+    /// Returns the length of the transfer once it is complete, and `None` while more chunks are
+    /// to come. Each chunk is acknowledged with `reply`, so nothing else may be received between
+    /// receiving a chunk and passing it here. A chunk which is refused is replied to with a
+    /// rejection and abandons the transfer.
+    pub fn accept(&mut self, token: &RecvToken, out: &mut [u8])
+                  -> Result<Option<usize>, BulkError> {
+        if token.label != self.label {
+            return Err(BulkError::Malformed);
+        }
+        let mut msg = token.reader();
+        let total = match msg.read_word() {
+            Some(total) => total as usize,
+            None => return Err(BulkError::Malformed),
+        };
+        if total <= bulk_inline_bytes() {
+            if total > out.len() {
+                return Err(BulkError::TooLarge);
+            }
+            return match msg.read_bytes(&mut out[..total]) {
+                Ok(()) => Ok(Some(total)),
+                Err(()) => Err(BulkError::Malformed),
+            };
+        }
+
+        let mut header = [0; 2];
+        let checked = if msg.read_words(&mut header).is_err() {
+            Err(BulkError::Malformed)
+        } else if total > out.len() {
+            Err(BulkError::TooLarge)
+        } else {
+            let (offset, len) = (header[0] as usize, header[1] as usize);
+            if offset != self.received || len == 0 || len > self.size || len > total - offset {
+                Err(BulkError::Malformed)
+            } else {
+                Ok((offset, len))
+            }
+        };
+        let (offset, len) = match checked {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                self.received = 0;
+                reply_built(MessageBuilder::new(CHUNK_REJECTED)).map_err(BulkError::Kernel)?;
+                return Err(err);
+            }
+        };
+
+        unsafe {
+            ptr::copy_nonoverlapping(self.buf, out[offset..].as_mut_ptr(), len);
+        }
+        reply_built(MessageBuilder::new(CHUNK_ACK)).map_err(BulkError::Kernel)?;
+        self.received += len;
+        if self.received == total {
+            self.received = 0;
+            Ok(Some(total))
+        } else {
+            Ok(None)
+        }
+    }
+}
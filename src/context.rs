@@ -0,0 +1,258 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Thread register contexts
+
+use core::ops::{BitAnd, BitOr, Sub};
+
+use sel4_sys::{seL4_UserContext, seL4_Word};
+
+/// The registers of a thread, as read and written by `Thread::read_context` and
+/// `Thread::write_context`.
+///
+/// The accessors name registers by their role, so the same code works on every architecture. The
+/// architecture's own register names are available through `raw` and `raw_mut`.
+#[derive(Copy, Clone)]
+pub struct UserContext {
+    raw: seL4_UserContext,
+}
+
+impl UserContext {
+    /// A context with every register zero.
+    #[inline(always)]
+    pub fn new() -> UserContext {
+        UserContext { raw: unsafe { ::core::mem::zeroed() } }
+    }
+
+    /// Wrap a raw context.
+    #[inline(always)]
+    pub fn from_raw(raw: seL4_UserContext) -> UserContext {
+        UserContext { raw: raw }
+    }
+
+    /// The raw context.
+    #[inline(always)]
+    pub fn raw(&self) -> &seL4_UserContext {
+        &self.raw
+    }
+
+    /// The raw context, for changing registers by name.
+    #[inline(always)]
+    pub fn raw_mut(&mut self) -> &mut seL4_UserContext {
+        &mut self.raw
+    }
+
+    /// Unwrap the raw context.
+    #[inline(always)]
+    pub fn into_raw(self) -> seL4_UserContext {
+        self.raw
+    }
+
+    /// The number of words in the context, as passed to the kernel.
+    #[inline(always)]
+    pub fn words() -> usize {
+        ::core::mem::size_of::<seL4_UserContext>() / ::core::mem::size_of::<seL4_Word>()
+    }
+
+    /// Argument `n` of a function just called.
+    ///
+    /// Panics if argument `n` is not passed in a register. See `ARG_REGISTERS`.
+    #[inline(always)]
+    pub fn arg(&self, n: usize) -> seL4_Word {
+        match self.arg_register(n) {
+            Some(reg) => *reg,
+            None => panic!("argument {} is not passed in a register", n),
+        }
+    }
+
+    /// Set argument `n` of the function the thread will start in.
+    ///
+    /// Panics if argument `n` is not passed in a register. See `ARG_REGISTERS`.
+    #[inline(always)]
+    pub fn set_arg(&mut self, n: usize, val: seL4_Word) {
+        match self.arg_register_mut(n) {
+            Some(reg) => *reg = val,
+            None => panic!("argument {} is not passed in a register", n),
+        }
+    }
+}
+
+impl Default for UserContext {
+    #[inline(always)]
+    fn default() -> UserContext {
+        UserContext::new()
+    }
+}
+
+impl From<seL4_UserContext> for UserContext {
+    #[inline(always)]
+    fn from(raw: seL4_UserContext) -> UserContext {
+        UserContext::from_raw(raw)
+    }
+}
+
+impl From<UserContext> for seL4_UserContext {
+    #[inline(always)]
+    fn from(context: UserContext) -> seL4_UserContext {
+        context.raw
+    }
+}
+
+macro_rules! user_context_accessors {
+    ($pc:ident, $sp:ident, $ret:ident, $tls:ident, [$($n:expr => $arg:ident),*]) => {
+        /// Number of function arguments passed in registers.
+        pub const ARG_REGISTERS: usize = <[usize]>::len(&[$($n),*]);
+
+        impl UserContext {
+            /// The program counter.
+            #[inline(always)]
+            pub fn pc(&self) -> seL4_Word {
+                self.raw.$pc
+            }
+
+            /// Set the program counter.
+            #[inline(always)]
+            pub fn set_pc(&mut self, val: seL4_Word) {
+                self.raw.$pc = val;
+            }
+
+            /// The stack pointer.
+            #[inline(always)]
+            pub fn sp(&self) -> seL4_Word {
+                self.raw.$sp
+            }
+
+            /// Set the stack pointer.
+            #[inline(always)]
+            pub fn set_sp(&mut self, val: seL4_Word) {
+                self.raw.$sp = val;
+            }
+
+            /// The register a function returns a word in.
+            #[inline(always)]
+            pub fn return_value(&self) -> seL4_Word {
+                self.raw.$ret
+            }
+
+            /// Set the register a function returns a word in.
+            #[inline(always)]
+            pub fn set_return_value(&mut self, val: seL4_Word) {
+                self.raw.$ret = val;
+            }
+
+            /// The base address of the thread-local storage.
+            #[inline(always)]
+            pub fn tls_base(&self) -> seL4_Word {
+                self.raw.$tls
+            }
+
+            /// Set the base address of the thread-local storage.
+            #[inline(always)]
+            pub fn set_tls_base(&mut self, val: seL4_Word) {
+                self.raw.$tls = val;
+            }
+
+            #[inline(always)]
+            fn arg_register(&self, n: usize) -> Option<&seL4_Word> {
+                match n {
+                    $($n => Some(&self.raw.$arg),)*
+                    _ => None,
+                }
+            }
+
+            #[inline(always)]
+            fn arg_register_mut(&mut self, n: usize) -> Option<&mut seL4_Word> {
+                match n {
+                    $($n => Some(&mut self.raw.$arg),)*
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+// The System V calling convention.
+#[cfg(target_arch = "x86_64")]
+user_context_accessors!(rip, rsp, rax, tls_base, [0 => rdi, 1 => rsi, 2 => rdx, 3 => rcx,
+                                                  4 => r8, 5 => r9]);
+
+// The C calling convention passes every argument on the stack.
+#[cfg(target_arch = "x86")]
+user_context_accessors!(eip, esp, eax, tls_base, []);
+
+// The AAPCS calling convention.
+#[cfg(target_arch = "arm")]
+user_context_accessors!(pc, sp, r0, tpidrurw, [0 => r0, 1 => r1, 2 => r2, 3 => r3]);
+
+const REGS_FRAME: u8 = 1 << 0;
+const REGS_INTEGER: u8 = 1 << 1;
+
+/// The registers `Thread::copy_register_set` transfers.
+///
+/// Combine sets with `|`, intersect them with `&`, and remove them with `-`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct RegisterSet {
+    bits: u8,
+}
+
+impl RegisterSet {
+    /// No registers.
+    pub const NONE: RegisterSet = RegisterSet { bits: 0 };
+    /// The frame registers, which are those read, modified, or preserved by system calls.
+    pub const FRAME: RegisterSet = RegisterSet { bits: REGS_FRAME };
+    /// The integer registers, which are all the others.
+    pub const INTEGER: RegisterSet = RegisterSet { bits: REGS_INTEGER };
+    /// Every register.
+    pub const ALL: RegisterSet = RegisterSet { bits: REGS_FRAME | REGS_INTEGER };
+
+    /// Whether the frame registers are in the set.
+    #[inline(always)]
+    pub fn frame(&self) -> bool {
+        self.bits & REGS_FRAME != 0
+    }
+
+    /// Whether the integer registers are in the set.
+    #[inline(always)]
+    pub fn integer(&self) -> bool {
+        self.bits & REGS_INTEGER != 0
+    }
+
+    /// Whether every register in `other` is also in this set.
+    #[inline(always)]
+    pub fn contains(&self, other: RegisterSet) -> bool {
+        self.bits & other.bits == other.bits
+    }
+}
+
+impl BitOr for RegisterSet {
+    type Output = RegisterSet;
+
+    #[inline(always)]
+    fn bitor(self, rhs: RegisterSet) -> RegisterSet {
+        RegisterSet { bits: self.bits | rhs.bits }
+    }
+}
+
+impl BitAnd for RegisterSet {
+    type Output = RegisterSet;
+
+    #[inline(always)]
+    fn bitand(self, rhs: RegisterSet) -> RegisterSet {
+        RegisterSet { bits: self.bits & rhs.bits }
+    }
+}
+
+impl Sub for RegisterSet {
+    type Output = RegisterSet;
+
+    #[inline(always)]
+    fn sub(self, rhs: RegisterSet) -> RegisterSet {
+        RegisterSet { bits: self.bits & !rhs.bits }
+    }
+}
@@ -12,13 +12,19 @@
 use sel4_sys::{seL4_CNode_CancelBadgedSends, seL4_CNode_Copy, seL4_CNode_Delete, seL4_CNode_Mint,
                seL4_CNode_Move, seL4_CNode_Mutate, seL4_CNode_Revoke, seL4_CNode_Rotate,
                seL4_CNode_SaveCaller, seL4_CPtr, seL4_CapData, seL4_CapRights,
-               seL4_CapTableObject, seL4_Word};
+               seL4_CapTableObject, seL4_SlotBits, seL4_Word};
 
-use ToCap;
+use core::marker::PhantomData;
+use core::ops::{BitAnd, BitOr, Sub};
+
+use {FromCap, SizedObjectAllocator, Thread, ThreadConfiguration, ToCap};
+
+/// Maximum number of levels in a `CSpace`.
+pub const MAX_CSPACE_DEPTH: usize = 8;
 
 cap_wrapper!{ ()
     /// Fixed-length table for storing capabilities
-    CNode = seL4_CapTableObject |i| 16 * i,
+    CNode = seL4_CapTableObject |i| (1 as seL4_Word) << (i + seL4_SlotBits),
 }
 
 /// An unforgeable marker on a capability.
@@ -40,6 +46,144 @@ impl Badge {
     }
 }
 
+/// Access rights for a capability.
+///
+/// Rights can only be removed when deriving a capability with `SlotRef::copy` or `SlotRef::mint`,
+/// never added. Combine rights with `|`, intersect them with `&`, and remove them with `-`.
+///
+/// This kernel version has no separate grant-reply right: the grant right on an endpoint covers
+/// the reply capability as well.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct CapRights {
+    bits: u8,
+}
+
+const RIGHT_READ: u8 = 1 << 0;
+const RIGHT_WRITE: u8 = 1 << 1;
+const RIGHT_GRANT: u8 = 1 << 2;
+
+impl CapRights {
+    /// No rights at all.
+    pub const NONE: CapRights = CapRights { bits: 0 };
+    /// Read only.
+    pub const R: CapRights = CapRights { bits: RIGHT_READ };
+    /// Write only.
+    pub const W: CapRights = CapRights { bits: RIGHT_WRITE };
+    /// Grant only.
+    pub const G: CapRights = CapRights { bits: RIGHT_GRANT };
+    /// Read and write.
+    pub const RW: CapRights = CapRights { bits: RIGHT_READ | RIGHT_WRITE };
+    /// Read, write, and grant.
+    pub const RWG: CapRights = CapRights { bits: RIGHT_READ | RIGHT_WRITE | RIGHT_GRANT };
+    /// Every right.
+    pub const ALL: CapRights = CapRights::RWG;
+
+    /// Build a set of rights from individual flags.
+    #[inline(always)]
+    pub fn new(read: bool, write: bool, grant: bool) -> CapRights {
+        let mut bits = 0;
+        if read {
+            bits |= RIGHT_READ;
+        }
+        if write {
+            bits |= RIGHT_WRITE;
+        }
+        if grant {
+            bits |= RIGHT_GRANT;
+        }
+        CapRights { bits: bits }
+    }
+
+    /// Whether the read right is present.
+    ///
+    /// On endpoints this allows receiving, on notifications waiting, and on frames reading.
+    #[inline(always)]
+    pub fn read(&self) -> bool {
+        self.bits & RIGHT_READ != 0
+    }
+
+    /// Whether the write right is present.
+    ///
+    /// On endpoints this allows sending, on notifications signalling, and on frames writing.
+    #[inline(always)]
+    pub fn write(&self) -> bool {
+        self.bits & RIGHT_WRITE != 0
+    }
+
+    /// Whether the grant right is present.
+    ///
+    /// On endpoints this allows sending capabilities along with messages.
+    #[inline(always)]
+    pub fn grant(&self) -> bool {
+        self.bits & RIGHT_GRANT != 0
+    }
+
+    /// Whether every right in `other` is also in this set.
+    #[inline(always)]
+    pub fn contains(&self, other: CapRights) -> bool {
+        self.bits & other.bits == other.bits
+    }
+
+    /// Whether every right in this set has an effect on capabilities to objects of type `T`.
+    #[inline(always)]
+    pub fn is_meaningful_for<T: ObjectRights>(&self) -> bool {
+        T::meaningful_rights().contains(*self)
+    }
+
+    /// Convert to the representation used by the kernel.
+    #[inline(always)]
+    pub fn to_raw(&self) -> seL4_CapRights {
+        // unsafe: mem: maybe use a Default::default() ?
+        let mut raw: seL4_CapRights = unsafe { ::core::mem::zeroed() };
+        raw.set_capAllowRead(self.read() as seL4_Word);
+        raw.set_capAllowWrite(self.write() as seL4_Word);
+        raw.set_capAllowGrant(self.grant() as seL4_Word);
+        raw
+    }
+}
+
+impl BitOr for CapRights {
+    type Output = CapRights;
+
+    #[inline(always)]
+    fn bitor(self, rhs: CapRights) -> CapRights {
+        CapRights { bits: self.bits | rhs.bits }
+    }
+}
+
+impl BitAnd for CapRights {
+    type Output = CapRights;
+
+    #[inline(always)]
+    fn bitand(self, rhs: CapRights) -> CapRights {
+        CapRights { bits: self.bits & rhs.bits }
+    }
+}
+
+impl Sub for CapRights {
+    type Output = CapRights;
+
+    #[inline(always)]
+    fn sub(self, rhs: CapRights) -> CapRights {
+        CapRights { bits: self.bits & !rhs.bits }
+    }
+}
+
+impl From<CapRights> for seL4_CapRights {
+    #[inline(always)]
+    fn from(rights: CapRights) -> seL4_CapRights {
+        rights.to_raw()
+    }
+}
+
+/// Objects whose capabilities are affected by their rights.
+///
+/// Rights on capabilities to other objects are accepted by the kernel but have no effect.
+pub trait ObjectRights {
+    /// The rights which change what a capability to this object can do.
+    fn meaningful_rights() -> CapRights;
+}
+
 /// A qualified reference to a capability slot.
 ///
 /// This has three fields: a CPtr to a CNode, a CPtr, and a depth. Together, this information
@@ -72,7 +216,7 @@ impl SlotRef {
 
     /// Copy the capability in this slot into `dest`, inheriting `rights`.
     #[inline(always)]
-    pub fn copy(&self, dest: SlotRef, rights: seL4_CapRights) -> ::Result {
+    pub fn copy(&self, dest: SlotRef, rights: CapRights) -> ::Result {
         unsafe_as_result!(seL4_CNode_Copy(
             dest.root.to_cap(),
             dest.cptr,
@@ -80,7 +224,7 @@ impl SlotRef {
             self.root.to_cap(),
             self.cptr,
             self.depth,
-            rights,
+            rights.to_raw(),
         ))
     }
 
@@ -92,7 +236,7 @@ impl SlotRef {
 
     /// Copy the capability in this slot into `dest`, inheriting `rights` and applying `badge`.
     #[inline(always)]
-    pub fn mint(&self, dest: SlotRef, rights: seL4_CapRights, badge: Badge) -> ::Result {
+    pub fn mint(&self, dest: SlotRef, rights: CapRights, badge: Badge) -> ::Result {
         unsafe_as_result!(seL4_CNode_Mint(
             dest.root.to_cap(),
             dest.cptr,
@@ -100,7 +244,7 @@ impl SlotRef {
             self.root.to_cap(),
             self.cptr,
             self.depth,
-            rights,
+            rights.to_raw(),
             badge.bits,
         ))
     }
@@ -181,6 +325,12 @@ impl SlotRef {
         ))
     }
 
+    /// Assert that this slot holds a capability of type `T`.
+    #[inline(always)]
+    pub fn typed<T: ToCap + FromCap>(self) -> TypedSlot<T> {
+        TypedSlot::new(self)
+    }
+
     /// Save the reply capability into this slot.
     #[inline(always)]
     pub fn save_caller(&self) -> ::Result {
@@ -188,6 +338,113 @@ impl SlotRef {
     }
 }
 
+/// A slot which is known to hold a capability of type `T`.
+///
+/// The CNode operations on a `TypedSlot` return `TypedSlot`s for their destination, so the type
+/// of a capability is tracked as it is copied, minted and moved between slots. This catches
+/// mixing up, say, endpoint and notification slots at compile time.
+///
+/// Converting a `TypedSlot` into its capability with `cap` uses the slot's CPtr directly, so this
+/// only yields a usable capability if the slot's depth is the full word size (that is, it is
+/// addressed relative to the current thread's CSpace root).
+#[derive(Debug)]
+pub struct TypedSlot<T> {
+    slot: SlotRef,
+    _marker: PhantomData<T>,
+}
+
+impl<T> Clone for TypedSlot<T> {
+    #[inline(always)]
+    fn clone(&self) -> TypedSlot<T> {
+        *self
+    }
+}
+
+impl<T> Copy for TypedSlot<T> {}
+
+impl<T> PartialEq for TypedSlot<T> {
+    #[inline(always)]
+    fn eq(&self, other: &TypedSlot<T>) -> bool {
+        self.slot == other.slot
+    }
+}
+
+impl<T> Eq for TypedSlot<T> {}
+
+impl<T: ToCap + FromCap> TypedSlot<T> {
+    /// Assert that `slot` holds a capability of type `T`.
+    #[inline(always)]
+    pub fn new(slot: SlotRef) -> TypedSlot<T> {
+        TypedSlot {
+            slot: slot,
+            _marker: PhantomData,
+        }
+    }
+
+    /// The untyped reference to this slot.
+    #[inline(always)]
+    pub fn slot(&self) -> SlotRef {
+        self.slot
+    }
+
+    /// The capability stored in this slot, ready for invocation.
+    #[inline(always)]
+    pub fn cap(&self) -> T {
+        T::from_cap(self.slot.cptr)
+    }
+
+    /// Copy the capability in this slot into `dest`, inheriting `rights`.
+    #[inline(always)]
+    pub fn copy(&self, dest: SlotRef, rights: CapRights) -> Result<TypedSlot<T>, ::Error> {
+        self.slot.copy(dest, rights).map(|()| TypedSlot::new(dest))
+    }
+
+    /// Copy the capability in this slot into `dest`, inheriting `rights` and applying `badge`.
+    #[inline(always)]
+    pub fn mint(&self, dest: SlotRef, rights: CapRights, badge: Badge)
+                -> Result<TypedSlot<T>, ::Error> {
+        self.slot.mint(dest, rights, badge).map(|()| TypedSlot::new(dest))
+    }
+
+    /// Move the capability in this slot into `dest`, clearing this slot.
+    #[inline(always)]
+    pub fn move_(self, dest: SlotRef) -> Result<TypedSlot<T>, ::Error> {
+        self.slot.move_(dest).map(|()| TypedSlot::new(dest))
+    }
+
+    /// Move the capability in this slot into `dest`, applying `badge` and clearing this slot.
+    #[inline(always)]
+    pub fn mutate(self, dest: SlotRef, badge: Badge) -> Result<TypedSlot<T>, ::Error> {
+        self.slot.mutate(dest, badge).map(|()| TypedSlot::new(dest))
+    }
+
+    /// Delete the capability in this slot, returning the now-empty slot.
+    #[inline(always)]
+    pub fn delete(self) -> Result<SlotRef, ::Error> {
+        self.slot.delete().map(|()| self.slot)
+    }
+
+    /// Delete all child capabilities of the capability in this slot.
+    #[inline(always)]
+    pub fn revoke(&self) -> ::Result {
+        self.slot.revoke()
+    }
+}
+
+impl<T: ToCap + FromCap> ToCap for TypedSlot<T> {
+    #[inline(always)]
+    fn to_cap(&self) -> seL4_CPtr {
+        self.slot.cptr
+    }
+}
+
+impl<T> From<TypedSlot<T>> for SlotRef {
+    #[inline(always)]
+    fn from(slot: TypedSlot<T>) -> SlotRef {
+        slot.slot
+    }
+}
+
 /// Extra information needed to know how to address caps in a CNode.
 ///
 /// This information isn't needed to interact with the kernel, but is necessary for reconstructing
@@ -222,7 +479,7 @@ pub struct DecodedCPtr {
 impl CNodeInfo {
     /// Decode a CPtr into 4 pieces: prefix, guard, radix, and leftover, like such:
     ///
-    /// ```
+    /// ```text
     /// pppppppp|gggg|rrrrrrrr|llllllllllll
     /// ^-------|^---|^-------|^-----------
     /// |       |    |        |
@@ -321,3 +578,302 @@ impl Window {
         })
     }
 }
+
+impl CNodeInfo {
+    /// The capability data which installs this CNode's guard.
+    ///
+    /// This is what `Thread::set_space` and `ThreadConfiguration` expect as the CSpace root data,
+    /// and what must be minted onto a CNode capability to give it this guard.
+    pub fn guard_data(&self) -> seL4_CapData {
+        // unsafe: mem: maybe use a Default::default() ?
+        let mut data: seL4_CapData = unsafe { ::core::mem::zeroed() };
+        data.set_GuardBits(self.guard_val);
+        data.set_GuardSize(self.guard_bits as seL4_Word);
+        data
+    }
+
+    /// Number of bits of a CPtr this CNode consumes, including its guard.
+    #[inline(always)]
+    pub fn level_bits(&self) -> u8 {
+        self.guard_bits.wrapping_add(self.radix_bits)
+    }
+}
+
+/// Errors from building a `CSpace`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CSpaceError<E> {
+    /// The node id does not refer to a CNode in the tree.
+    NoSuchNode,
+    /// The index is larger than the radix of the CNode allows.
+    IndexOutOfRange,
+    /// A CNode is already linked at that index.
+    Occupied,
+    /// The path through the tree would need more bits than fit in a CPtr, or the radix or guard
+    /// is as wide as a word.
+    TooDeep,
+    /// The storage for tracking CNodes is full.
+    OutOfStorage,
+    /// The allocator has no free slots.
+    OutOfSlots,
+    /// The allocator has no memory left for the CNode.
+    OutOfMemory,
+    /// The allocator failed to create the CNode.
+    Alloc(E),
+    /// Invoking the kernel failed.
+    Kernel(::Error),
+}
+
+/// A CNode in a `CSpace`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CSpaceNode {
+    /// A capability to the CNode, valid in the current thread's CSpace.
+    pub cnode: CNode,
+    /// How the CNode is addressed. `prefix_bits` is the number of bits consumed by the CNodes
+    /// above it in the tree.
+    pub info: CNodeInfo,
+    /// The node this is linked into, and the index it is linked at.
+    parent: Option<(usize, seL4_Word)>,
+}
+
+/// Whether a CNode's radix and guard are each narrower than a CPtr, so shifts by them are defined.
+#[inline(always)]
+fn fits_in_word(radix_bits: u8, guard_bits: u8) -> bool {
+    let word_bits = ::core::mem::size_of::<seL4_Word>() * 8;
+    (radix_bits as usize) < word_bits && (guard_bits as usize) < word_bits
+}
+
+/// A model of a (possibly multi-level) CSpace.
+///
+/// This records the tree of CNodes making up a CSpace, along with the radix and guard of each, so
+/// that slots anywhere in the tree can be addressed. Nodes are identified by their index in the
+/// caller-provided storage; the root is always node 0.
+///
+/// A path is the sequence of indices to follow from the root: all but the last must name slots
+/// with CNodes linked in, and the last names the slot itself.
+pub struct CSpace<'a> {
+    nodes: &'a mut [Option<CSpaceNode>],
+}
+
+impl<'a> CSpace<'a> {
+    /// Create a model of the CSpace rooted at `root`.
+    ///
+    /// `info` describes the root CNode's radix and guard; its `prefix_bits` is ignored. `storage`
+    /// must have room for at least the root, and every entry is cleared.
+    ///
+    /// Panics if the radix or guard is as wide as a word or wider.
+    pub fn new(root: CNode, mut info: CNodeInfo, storage: &'a mut [Option<CSpaceNode>])
+               -> CSpace<'a> {
+        assert!(storage.len() > 0, "CSpace storage must have room for the root");
+        assert!(fits_in_word(info.radix_bits, info.guard_bits),
+                "CSpace root radix and guard must each be narrower than a word");
+        for entry in storage.iter_mut() {
+            *entry = None;
+        }
+
+        info.prefix_bits = 0;
+        storage[0] = Some(CSpaceNode {
+            cnode: root,
+            info: info,
+            parent: None,
+        });
+
+        CSpace { nodes: storage }
+    }
+
+    /// The root CNode.
+    #[inline(always)]
+    pub fn root(&self) -> CNode {
+        self.nodes[0].expect("CSpace has no root").cnode
+    }
+
+    /// The CSpace root data to use when giving a thread this CSpace.
+    #[inline(always)]
+    pub fn root_data(&self) -> seL4_CapData {
+        self.nodes[0].expect("CSpace has no root").info.guard_data()
+    }
+
+    /// Look up a node in the tree.
+    #[inline(always)]
+    pub fn node(&self, id: usize) -> Option<CSpaceNode> {
+        self.nodes.get(id).and_then(|n| *n)
+    }
+
+    /// Find the node linked into `parent` at `index`.
+    pub fn child(&self, parent: usize, index: seL4_Word) -> Option<usize> {
+        self.nodes.iter().position(|n| {
+            n.map_or(false, |n| n.parent == Some((parent, index)))
+        })
+    }
+
+    /// Resolve `path` into a reference to the slot it names.
+    ///
+    /// The resulting `SlotRef` is relative to the root CNode, and its depth is the number of bits
+    /// consumed along the path. Returns `None` if the path is empty, goes through a slot with no
+    /// CNode linked in, or does not fit in a CPtr.
+    pub fn resolve(&self, path: &[seL4_Word]) -> Option<SlotRef> {
+        let mut node = 0;
+        let mut cptr: seL4_Word = 0;
+        let mut depth: usize = 0;
+
+        for (level, &index) in path.iter().enumerate() {
+            let info = match self.node(node) {
+                Some(n) => n.info,
+                None => return None,
+            };
+            if index.checked_shr(info.radix_bits as u32).unwrap_or(0) != 0 {
+                return None;
+            }
+
+            depth += info.level_bits() as usize;
+            if depth > ::core::mem::size_of::<seL4_Word>() * 8 {
+                return None;
+            }
+            cptr = cptr.checked_shl(info.guard_bits as u32).unwrap_or(0) | info.guard_val;
+            cptr = cptr.checked_shl(info.radix_bits as u32).unwrap_or(0) | index;
+
+            if level + 1 < path.len() {
+                node = match self.child(node, index) {
+                    Some(child) => child,
+                    None => return None,
+                };
+            }
+        }
+
+        if depth == 0 {
+            return None;
+        }
+
+        Some(SlotRef::new(self.root(), cptr, depth as u8))
+    }
+
+    /// Resolve slot `index` of the CNode `node`.
+    fn slot_in(&self, node: usize, index: seL4_Word) -> Option<SlotRef> {
+        let mut path = [0; MAX_CSPACE_DEPTH];
+        let mut len = 0;
+        let mut cur = node;
+        while let Some((parent, idx)) = self.node(cur).and_then(|n| n.parent) {
+            if len + 1 >= MAX_CSPACE_DEPTH {
+                return None;
+            }
+            path[len] = idx;
+            len += 1;
+            cur = parent;
+        }
+
+        path[..len].reverse();
+        path[len] = index;
+        self.resolve(&path[..len + 1])
+    }
+
+    /// Link the CNode in `src` into slot `index` of `parent`, with the given radix and guard.
+    ///
+    /// The capability in `src` is minted into the tree with the guard applied; `src` itself is
+    /// left untouched and `cnode` should be a capability to the same CNode which is usable from
+    /// the current thread. Returns the id of the new node.
+    pub fn link(&mut self, parent: usize, index: seL4_Word, src: SlotRef, cnode: CNode,
+                radix_bits: u8, guard_bits: u8, guard_val: seL4_Word)
+                -> Result<usize, CSpaceError<()>> {
+        self.link_inner(parent, index, src, cnode, radix_bits, guard_bits, guard_val)
+    }
+
+    fn link_inner<E>(&mut self, parent: usize, index: seL4_Word, src: SlotRef, cnode: CNode,
+                     radix_bits: u8, guard_bits: u8, guard_val: seL4_Word)
+                     -> Result<usize, CSpaceError<E>> {
+        let parent_info = match self.node(parent) {
+            Some(n) => n.info,
+            None => return Err(CSpaceError::NoSuchNode),
+        };
+        if index.checked_shr(parent_info.radix_bits as u32).unwrap_or(0) != 0 {
+            return Err(CSpaceError::IndexOutOfRange);
+        }
+        if !fits_in_word(radix_bits, guard_bits) {
+            return Err(CSpaceError::TooDeep);
+        }
+        if self.child(parent, index).is_some() {
+            return Err(CSpaceError::Occupied);
+        }
+
+        let info = CNodeInfo {
+            guard_val: guard_val,
+            radix_bits: radix_bits,
+            guard_bits: guard_bits,
+            prefix_bits: parent_info.prefix_bits.wrapping_add(parent_info.level_bits()),
+        };
+        if info.prefix_bits as usize + info.level_bits() as usize >
+           ::core::mem::size_of::<seL4_Word>() * 8 {
+            return Err(CSpaceError::TooDeep);
+        }
+
+        let dest = match self.slot_in(parent, index) {
+            Some(dest) => dest,
+            None => return Err(CSpaceError::TooDeep),
+        };
+        let id = match self.nodes.iter().position(|n| n.is_none()) {
+            Some(id) => id,
+            None => return Err(CSpaceError::OutOfStorage),
+        };
+
+        src.mint(dest, CapRights::ALL, Badge { bits: info.guard_data() })
+            .map_err(CSpaceError::Kernel)?;
+        self.nodes[id] = Some(CSpaceNode {
+            cnode: cnode,
+            info: info,
+            parent: Some((parent, index)),
+        });
+
+        Ok(id)
+    }
+
+    /// Create a new CNode with `radix_bits` using `alloc`, and link it into slot `index` of
+    /// `parent` with the given guard.
+    ///
+    /// The original capability to the new CNode stays in a slot from `alloc`, and is what is used
+    /// to invoke it. Returns the id of the new node.
+    pub fn create_child<A: SizedObjectAllocator>(&mut self, alloc: &A, parent: usize,
+                                            index: seL4_Word, radix_bits: u8, guard_bits: u8,
+                                            guard_val: seL4_Word)
+                                            -> Result<usize, CSpaceError<A::ObjectAllocError>> {
+        let slot = match alloc.allocate_slot() {
+            Some(slot) => slot,
+            None => return Err(CSpaceError::OutOfSlots),
+        };
+        let cnode = match alloc.allocate_object_sized::<CNode>(slot, radix_bits as seL4_Word) {
+            Ok(Some(cnode)) => cnode,
+            Ok(None) => {
+                let _ = alloc.free_slot(slot);
+                return Err(CSpaceError::OutOfMemory);
+            }
+            Err(e) => {
+                let _ = alloc.free_slot(slot);
+                return Err(CSpaceError::Alloc(e));
+            }
+        };
+
+        match self.link_inner(parent, index, slot, cnode, radix_bits, guard_bits, guard_val) {
+            Ok(id) => Ok(id),
+            Err(e) => {
+                let _ = alloc.free_object(cnode);
+                let _ = alloc.free_slot(slot);
+                Err(e)
+            }
+        }
+    }
+
+    /// Give `thread` this CSpace, along with the given fault endpoint and VSpace.
+    ///
+    /// The fault endpoint is a CPtr interpreted in this CSpace.
+    #[inline(always)]
+    pub fn install(&self, thread: Thread, fault_endpoint: seL4_CPtr, vspace_root: seL4_CPtr,
+                   vspace_root_data: seL4_CapData)
+                   -> ::Result {
+        thread.set_space(fault_endpoint, self.root(), self.root_data(), vspace_root,
+                         vspace_root_data)
+    }
+
+    /// Fill in the CSpace fields of a thread configuration.
+    #[inline(always)]
+    pub fn configure(&self, config: &mut ThreadConfiguration) {
+        config.cspace_root = self.root();
+        config.cspace_root_data = self.root_data();
+    }
+}
@@ -25,38 +25,68 @@
 //! The endpoint must have the `CanGrant` bit set in its rights. In practice, only one new
 //! capability can be transfered at a time - the actual situation is somewhat more complex. Refer
 //! to §4.2.2 ("Capability Transfer") of the seL4 Reference Manual. The slot where the received
-//! capability will be stored is global state not tied to any particular endpoint.
+//! capability will be stored is global state not tied to any particular endpoint. `CapReceiver`
+//! manages it, giving each receive a fresh slot.
+//!
+//! A thread which sends with `call` waits for a reply. The receiver can answer it with `reply` or
+//! `Endpoint::reply_recv`, which use an implicit reply capability that is lost on the next
+//! receive. To answer later, save the caller into a slot with `Reply::save`.
 //!
 //! Do note that `Endpoint` does not also attempt to model notification objects, instead leaving
 //! that to the `Notification` type.
 
 use sel4_sys::*;
 
+use {CapRights, DecodeResult, FromCap, IpcDecode, IpcEncode, MessageBuilder, MessageReader,
+     ObjectRights, SlotRef, ToCap, TypedCallError, TypedSlot, encode_message};
+
 cap_wrapper!{ ()
     /// An endpoint for message passing
     Endpoint = seL4_EndpointObject |_| 16,
 }
 
+impl ObjectRights for Endpoint {
+    /// Read allows receiving, write allows sending, and grant allows transferring capabilities.
+    #[inline(always)]
+    fn meaningful_rights() -> CapRights {
+        CapRights::RWG
+    }
+}
+
 /// The result of a successful receive.
 ///
 /// Contains "sender information", which is the badge of the endpoint which was invoked to send a
 /// message.
 ///
 /// Also contains the decoded message information.
+#[derive(Debug)]
 pub struct RecvToken {
     pub badge: seL4_Word,
     pub label: seL4_Word,
     caps_unwrapped: seL4_Word,
+    extra_caps: seL4_Word,
     len: seL4_Word,
+    received: Option<SlotRef>,
 }
 
 impl RecvToken {
     fn from_raw(sender: seL4_Word, message_info: seL4_MessageInfo) -> RecvToken {
+        let extra_caps = message_info.get_extraCaps();
+        let caps_unwrapped = message_info.get_capsUnwrapped();
+        // Every capability which was not unwrapped went into the receive slot. Only one can.
+        let all = (1 << extra_caps) - 1;
+        let received = if caps_unwrapped & all != all {
+            Some(::get_cap_destination())
+        } else {
+            None
+        };
         RecvToken {
             badge: sender,
             label: message_info.get_label(),
-            caps_unwrapped: message_info.get_capsUnwrapped(),
+            caps_unwrapped: caps_unwrapped,
+            extra_caps: extra_caps,
             len: message_info.get_length(),
+            received: received,
         }
     }
 
@@ -105,6 +135,66 @@ impl RecvToken {
     pub fn words_transferred(&self) -> seL4_Word {
         self.len
     }
+
+    /// Number of capabilities sent along with the message, whether transferred or unwrapped.
+    pub fn extra_caps(&self) -> seL4_Word {
+        self.extra_caps
+    }
+
+    /// The receive slot, if a capability was transferred into it with this message.
+    ///
+    /// This is `None` if no capability was sent, or if every capability sent was unwrapped.
+    #[inline(always)]
+    pub fn received_slot(&self) -> Option<SlotRef> {
+        self.received
+    }
+
+    /// The receive slot, if a capability was transferred into it, as holding a `T`.
+    ///
+    /// The kernel does not say what type of capability was sent; the protocol must.
+    #[inline(always)]
+    pub fn received_cap<T: ToCap + FromCap>(&self) -> Option<TypedSlot<T>> {
+        self.received.map(TypedSlot::new)
+    }
+
+    /// Decode a value sent with `Endpoint::send_typed` or `Endpoint::call_typed`.
+    ///
+    /// The label is not checked; match on `label` first if an endpoint receives more than one
+    /// kind of message.
+    #[inline(always)]
+    pub fn decode<T: IpcDecode>(&self) -> DecodeResult<T> {
+        T::decode(&mut self.reader())
+    }
+
+    /// Read the message word by word, straight out of the IPC buffer.
+    #[inline(always)]
+    pub fn reader(&self) -> MessageReader {
+        MessageReader::new(self)
+    }
+}
+
+/// Copy a message into the IPC buffer, returning the message info describing it.
+fn load_message(data: &[seL4_Word], caps: &[seL4_CPtr]) -> Result<seL4_MessageInfo, ::Error> {
+    if data.len() > seL4_MsgMaxLength {
+        return Err(::Error(::GoOn::TooMuchData));
+    }
+    if caps.len() > seL4_MsgMaxExtraCaps {
+        return Err(::Error(::GoOn::TooManyCaps));
+    }
+    unsafe {
+        let buf = seL4_GetIPCBuffer();
+        ::core::ptr::copy_nonoverlapping(
+            data.as_ptr(),
+            (&mut (*buf).msg).as_mut_ptr(),
+            data.len(),
+        );
+        ::core::ptr::copy_nonoverlapping(
+            caps.as_ptr(),
+            (&mut (*buf).caps_or_badges).as_mut_ptr(),
+            caps.len(),
+        );
+    }
+    Ok(seL4_MessageInfo::new(0, 0, caps.len(), data.len()))
 }
 
 impl Endpoint {
@@ -129,29 +219,28 @@ impl Endpoint {
     /// This is `seL4_Send` in its full generality.
     #[inline(always)]
     pub fn send_message(&self, data: &[seL4_Word], caps: &[seL4_CPtr]) -> ::Result {
-        if data.len() > seL4_MsgMaxLength {
-            return Err(::Error(::GoOn::TooMuchData));
-        }
-        if caps.len() > seL4_MsgMaxExtraCaps {
-            return Err(::Error(::GoOn::TooManyCaps));
+        let info = load_message(data, caps)?;
+        unsafe {
+            seL4_Send(self.cptr, info);
+            unsafe_as_result!(@ (*seL4_GetIPCBuffer()).tag.get_label())
         }
+    }
+
+    /// Send a message built in the IPC buffer with `MessageBuilder`.
+    #[inline(always)]
+    pub fn send_built(&self, msg: MessageBuilder) -> ::Result {
         unsafe {
-            let buf = seL4_GetIPCBuffer();
-            ::core::ptr::copy_nonoverlapping(
-                data.as_ptr(),
-                (&mut (*buf).msg).as_mut_ptr(),
-                data.len(),
-            );
-            ::core::ptr::copy_nonoverlapping(
-                caps.as_ptr(),
-                (&mut (*buf).caps_or_badges).as_mut_ptr(),
-                caps.len()
-            );
-            seL4_Send(self.cptr, seL4_MessageInfo::new(0, 0, caps.len(), data.len()));
-            unsafe_as_result!(@ (*buf).tag.get_label())
+            seL4_Send(self.cptr, msg.info());
+            unsafe_as_result!(@ (*seL4_GetIPCBuffer()).tag.get_label())
         }
     }
 
+    /// Encode `value` and send it with the given label.
+    #[inline(always)]
+    pub fn send_typed<T: IpcEncode>(&self, label: seL4_Word, value: &T) -> ::Result {
+        self.send_built(encode_message(label, value)?)
+    }
+
     /// Raw send, using data already in the IPC buffer
     #[inline(always)]
     pub fn send(&self, data: seL4_Word, caps: seL4_Word) -> ::Result {
@@ -163,6 +252,9 @@ impl Endpoint {
     }
 
     /// Raw non-blocking send, using data already in the IPC buffer
+    ///
+    /// If no thread is waiting to receive, the message is silently dropped. The kernel does not
+    /// tell the sender whether this happened, so `Ok` only means the endpoint could be invoked.
     #[inline(always)]
     pub fn try_send(&self, data: seL4_Word, caps: seL4_Word) -> ::Result {
         unsafe {
@@ -172,29 +264,26 @@ impl Endpoint {
         }
     }
 
-    /// Try to send a message, returning no indication of failure if the message could not be sent.
+    /// Try to send a message, without blocking.
+    ///
+    /// As with `try_send`, a message nobody is waiting for is dropped without any indication.
     #[inline(always)]
     pub fn try_send_message(&self, data: &[seL4_Word], caps: &[seL4_CPtr]) -> ::Result {
-        if data.len() > seL4_MsgMaxLength {
-            return Err(::Error(::GoOn::TooMuchData));
-        }
-        if caps.len() > seL4_MsgMaxExtraCaps {
-            return Err(::Error(::GoOn::TooManyCaps));
+        let info = load_message(data, caps)?;
+        unsafe {
+            seL4_NBSend(self.cptr, info);
+            unsafe_as_result!(@ (*seL4_GetIPCBuffer()).tag.get_label())
         }
+    }
+
+    /// Try to send a message built with `MessageBuilder`, without blocking.
+    ///
+    /// As with `try_send`, a message nobody is waiting for is dropped without any indication.
+    #[inline(always)]
+    pub fn try_send_built(&self, msg: MessageBuilder) -> ::Result {
         unsafe {
-            let buf = seL4_GetIPCBuffer();
-            ::core::ptr::copy_nonoverlapping(
-                data.as_ptr(),
-                (&mut (*buf).msg).as_mut_ptr(),
-                data.len(),
-            );
-            ::core::ptr::copy_nonoverlapping(
-                caps.as_ptr(),
-                (&mut (*buf).caps_or_badges).as_mut_ptr(),
-                caps.len(),
-            );
-            seL4_NBSend(self.cptr, seL4_MessageInfo::new(0, 0, caps.len(), data.len()));
-            unsafe_as_result!(@ (*buf).tag.get_label())
+            seL4_NBSend(self.cptr, msg.info());
+            unsafe_as_result!(@ (*seL4_GetIPCBuffer()).tag.get_label())
         }
     }
 
@@ -206,15 +295,21 @@ impl Endpoint {
         RecvToken::from_raw(sender, msginfo)
     }
 
-    /// Try to receive a message.
+    /// Try to receive a message, failing with `WouldBlock` if none is waiting.
     ///
-    /// If there is no message immediately available in the queue, the badge in `RecvToken` will be
-    /// `0`. This is the only way to determine if a message was available.
+    /// When nothing is received, the kernel reports a zero badge and an empty message info. A
+    /// message with label 0 and no words or capabilities, sent through an unbadged capability,
+    /// looks exactly the same and is also reported as `WouldBlock`. Servers which poll should
+    /// only hand out badged capabilities, or give every message a label or contents.
     #[inline(always)]
-    pub fn try_recv(&self) -> RecvToken {
+    pub fn try_recv(&self) -> Result<RecvToken, ::Error> {
         let mut sender = 0;
         let msginfo = unsafe { seL4_NBRecv(self.cptr, &mut sender) };
-        RecvToken::from_raw(sender, msginfo)
+        let token = RecvToken::from_raw(sender, msginfo);
+        if token.badge == 0 && token.label == 0 && token.len == 0 && token.extra_caps == 0 {
+            return Err(::Error(::GoOn::WouldBlock));
+        }
+        Ok(token)
     }
 
     /// Raw call, using data already in the IPC buffer
@@ -226,4 +321,163 @@ impl Endpoint {
             unsafe_as_result!(@ (*buf).tag.get_label()).map(|()| msg)
         }
     }
+
+    /// Encode `value` and call with the given label, decoding the reply as an `R`.
+    ///
+    /// The reply must have label 0, and any other label fails with `UnexpectedLabel`. Unlike
+    /// other invocations, a call through an invalid endpoint faults the caller instead of
+    /// returning a kernel error, so the label always comes from the server. A reply which does
+    /// not decode fails with `Malformed`.
+    #[inline(always)]
+    pub fn call_typed<T: IpcEncode, R: IpcDecode>(&self, label: seL4_Word, value: &T)
+                                                  -> Result<R, TypedCallError> {
+        let msg = encode_message(label, value)?;
+        let reply = self.call_built(msg);
+        if reply.label != 0 {
+            return Err(TypedCallError::UnexpectedLabel(reply.label));
+        }
+        reply.decode().map_err(TypedCallError::from)
+    }
+
+    /// Call with a message, blocking until the reply arrives.
+    ///
+    /// Like `send_message`, fails with `TooMuchData` or `TooManyCaps` without sending anything if
+    /// the message does not fit. The reply's label, data and capabilities are read through the
+    /// returned token. Its badge is always 0, and its label is not checked.
+    ///
+    /// seL4 has no general combined send and receive. `call` sends and waits for the reply on the
+    /// same endpoint, and `reply_recv` is the server's counterpart.
+    #[inline(always)]
+    pub fn call_message(&self, data: &[seL4_Word], caps: &[seL4_CPtr])
+                        -> Result<RecvToken, ::Error> {
+        let info = load_message(data, caps)?;
+        let info = unsafe { seL4_Call(self.cptr, info) };
+        Ok(RecvToken::from_raw(0, info))
+    }
+
+    /// Call with a message built with `MessageBuilder`, returning the reply.
+    ///
+    /// The reply's label is not checked, so any label can be used in replies.
+    #[inline(always)]
+    pub fn call_built(&self, msg: MessageBuilder) -> RecvToken {
+        let info = unsafe { seL4_Call(self.cptr, msg.info()) };
+        RecvToken::from_raw(0, info)
+    }
+
+    /// Reply to the thread which most recently called us, then block until a message is received
+    /// on this endpoint.
+    ///
+    /// This is `seL4_ReplyRecv`, the usual way for a server to answer one request and wait for the
+    /// next in a single system call. Fails only if `data` or `caps` is too long, like
+    /// `send_message`.
+    #[inline(always)]
+    pub fn reply_recv(&self, data: &[seL4_Word], caps: &[seL4_CPtr])
+                      -> Result<RecvToken, ::Error> {
+        let info = load_message(data, caps)?;
+        let mut sender = 0;
+        let msginfo = unsafe { seL4_ReplyRecv(self.cptr, info, &mut sender) };
+        Ok(RecvToken::from_raw(sender, msginfo))
+    }
+
+    /// Reply with a message built with `MessageBuilder`, then block until a message is received on
+    /// this endpoint.
+    #[inline(always)]
+    pub fn reply_recv_built(&self, msg: MessageBuilder) -> RecvToken {
+        let mut sender = 0;
+        let msginfo = unsafe { seL4_ReplyRecv(self.cptr, msg.info(), &mut sender) };
+        RecvToken::from_raw(sender, msginfo)
+    }
+
+    /// Serve requests on this endpoint until `handler` asks to stop.
+    ///
+    /// Each message received is passed to `handler`, along with a buffer for its reply. The
+    /// returned `ServeAction` says whether to reply with the start of that buffer, to leave the
+    /// caller waiting (after saving it with `Reply::save`, say), or to return. Replying and
+    /// receiving the next message is done with one `reply_recv`.
+    pub fn serve<F>(&self, mut handler: F) -> ::Result
+        where F: FnMut(&RecvToken, &mut [seL4_Word]) -> ServeAction
+    {
+        let mut reply = [0; seL4_MsgMaxLength];
+        let mut token = self.recv();
+        loop {
+            token = match handler(&token, &mut reply) {
+                ServeAction::Reply(len) => {
+                    match reply.get(..len) {
+                        Some(data) => self.reply_recv(data, &[])?,
+                        None => return Err(::Error(::GoOn::TooMuchData)),
+                    }
+                }
+                ServeAction::Defer => self.recv(),
+                ServeAction::Stop => return Ok(()),
+            };
+        }
+    }
+}
+
+/// What `Endpoint::serve` should do after its handler has dealt with a message.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ServeAction {
+    /// Reply with this many words from the start of the reply buffer, then wait for the next
+    /// message.
+    Reply(usize),
+    /// Wait for the next message without replying.
+    ///
+    /// Receiving again discards the implicit reply capability, so a caller which should still get
+    /// an answer must be saved with `Reply::save` first.
+    Defer,
+    /// Return from `serve` without replying.
+    Stop,
+}
+
+/// Reply to the thread which most recently called us.
+///
+/// Does nothing if there is no such thread, or it has already been replied to. Fails only if
+/// `data` or `caps` is too long, like `Endpoint::send_message`.
+#[inline(always)]
+pub fn reply(data: &[seL4_Word], caps: &[seL4_CPtr]) -> ::Result {
+    let info = load_message(data, caps)?;
+    unsafe {
+        seL4_Reply(info);
+    }
+    Ok(())
+}
+
+/// Reply to the thread which most recently called us with a message built with
+/// `MessageBuilder`.
+#[inline(always)]
+pub fn reply_built(msg: MessageBuilder) -> ::Result {
+    unsafe {
+        seL4_Reply(msg.info());
+    }
+    Ok(())
+}
+
+cap_wrapper!{ ()
+    /// A saved reply capability, for answering a caller after receiving other messages
+    Reply,
+}
+
+impl Reply {
+    /// Save the thread which most recently called us into `slot`.
+    ///
+    /// Like `SlotRef::save_caller`, which this uses. If there is no caller to save the slot is left
+    /// empty, and replying will fail. The returned capability uses the slot's CPtr directly, so
+    /// `slot` must be addressed at full depth from the root of our CSpace.
+    #[inline(always)]
+    pub fn save(slot: SlotRef) -> Result<Reply, ::Error> {
+        slot.save_caller().map(|()| Reply::from_cap(slot.cptr))
+    }
+
+    /// Reply to the saved caller.
+    ///
+    /// The kernel deletes a reply capability once it has been used, so this consumes the `Reply`
+    /// and leaves its slot empty.
+    #[inline(always)]
+    pub fn send(self, data: &[seL4_Word], caps: &[seL4_CPtr]) -> ::Result {
+        let info = load_message(data, caps)?;
+        unsafe {
+            seL4_Send(self.cptr, info);
+            unsafe_as_result!(@ (*seL4_GetIPCBuffer()).tag.get_label())
+        }
+    }
 }
@@ -9,8 +9,7 @@
 
 use sel4_sys::*;
 
-use Error;
-use GoOn;
+use {DecodeError, Error, GoOn};
 
 /// Detailed information about an error extracted from the message registers.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -39,7 +38,13 @@ pub enum ErrorDetails {
     },
     TooMuchData,
     TooManyCaps,
-//  WouldBlock,
+    MalformedMessage {
+        reason: DecodeError,
+    },
+    RejectedMessage {
+        reason: DecodeError,
+    },
+    WouldBlock,
 }
 
 impl ::core::fmt::Display for ErrorDetails {
@@ -72,9 +77,13 @@ impl ::core::fmt::Display for ErrorDetails {
             TooMuchData =>
                 write!(f, "tried to send more data than can fit in the IPC buffer"),
             TooManyCaps =>
-                write!(f, "tried to send more capabilities than can fit in the IPC buffer")
-//          WouldBlock =>
-//             write!(f, "tried to perform an operation on an endpoint that would have blocked"),
+                write!(f, "tried to send more capabilities than can fit in the IPC buffer"),
+            MalformedMessage { reason } =>
+                write!(f, "a received message could not be decoded: {}", reason),
+            RejectedMessage { reason } =>
+                write!(f, "the server could not decode our message: {}", reason),
+            WouldBlock =>
+                write!(f, "tried to perform an operation on an endpoint that would have blocked"),
         }
     }
 }
@@ -87,12 +96,14 @@ impl Error {
         match self.0 {
             GoOn::TooMuchData => Some(TooMuchData),
             GoOn::TooManyCaps => Some(TooManyCaps),
-//          GoOn::WouldBlock => Some(WouldBlock),
+            GoOn::Malformed(reason) => Some(MalformedMessage { reason: reason }),
+            GoOn::Rejected(reason) => Some(RejectedMessage { reason: reason }),
+            GoOn::WouldBlock => Some(WouldBlock),
             GoOn::CheckIPCBuf => {
                 unsafe {
                     let ipcbuf = seL4_GetIPCBuffer();
                     let label = (*ipcbuf).tag.get_label();
-                    assert!(label > seL4_NotEnoughMemory as seL4_Word, "Unknown error type");
+                    assert!(label <= seL4_NotEnoughMemory as seL4_Word, "Unknown error type");
 
                     // unsafe: transmute could be replaced with an enum_from_primitive!()
                     match ::core::mem::transmute::<_, seL4_Error>(label) {
@@ -178,7 +189,7 @@ impl LookupFailureKind {
         use LookupFailureKind::*;
 
         let kind = (*ipcbuf).msg[type_idx];
-        assert!(kind > seL4_GuardMismatch as seL4_Word, "Unknown lookup failure type");
+        assert!(kind <= seL4_GuardMismatch as seL4_Word, "Unknown lookup failure type");
 
         // unsafe: transmute could be replaced with an enum_from_primitive!()
         match ::core::mem::transmute::<_, seL4_LookupFailureType>(kind) {
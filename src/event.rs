@@ -0,0 +1,173 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Waiting on many event sources with one notification
+//!
+//! Signalling a badged notification capability ORs its badge into the notification word, and
+//! waiting returns and clears the word. Giving each event source a capability whose badge is a
+//! single bit lets one thread wait for all of them and tell which fired. `EventMux` mints those
+//! capabilities, and calls the handler registered for every bit set after a wait.
+
+use sel4_sys::seL4_Word;
+
+use {Badge, CapRights, IRQHandler, Notification, SlotRef};
+
+/// Number of badge bits, and so event sources, usable on every architecture.
+pub const EVENT_BITS: usize = 28;
+
+/// Errors from managing event sources with an `EventMux`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EventError {
+    /// The storage given has room for more than `EVENT_BITS` sources.
+    TooManySources,
+    /// Every bit is in use.
+    Exhausted,
+    /// No source is registered for the bit.
+    NotRegistered,
+    /// Invoking the kernel failed.
+    Kernel(::Error),
+}
+
+/// Something which handles an event.
+pub trait EventHandler {
+    fn handle(&mut self);
+}
+
+impl<'a> EventHandler for &'a mut dyn FnMut() {
+    #[inline(always)]
+    fn handle(&mut self) {
+        self()
+    }
+}
+
+/// An event source registered with an `EventMux`.
+#[derive(Debug)]
+pub struct EventSource<T> {
+    /// The slot holding the badged notification capability given to the source.
+    pub slot: SlotRef,
+    /// Called when the source signals.
+    pub handler: T,
+    /// The interrupt delivered to the notification, if registered with `register_irq`.
+    pub irq: Option<IRQHandler>,
+}
+
+/// Dispatches the bits of a notification word to a handler per bit.
+pub struct EventMux<'a, T: EventHandler + 'a> {
+    slot: SlotRef,
+    sources: &'a mut [Option<EventSource<T>>],
+}
+
+impl<'a, T: EventHandler> EventMux<'a, T> {
+    /// Create a multiplexer for the unbadged notification capability in `slot`.
+    ///
+    /// Source `i` is given bit `i`, so `storage` must have at most `EVENT_BITS` entries.
+    pub fn new(slot: SlotRef, storage: &'a mut [Option<EventSource<T>>])
+               -> Result<EventMux<'a, T>, EventError> {
+        if storage.len() > EVENT_BITS {
+            return Err(EventError::TooManySources);
+        }
+        for source in storage.iter_mut() {
+            *source = None;
+        }
+        Ok(EventMux {
+            slot: slot,
+            sources: storage,
+        })
+    }
+
+    /// The notification waited on.
+    #[inline(always)]
+    pub fn notification(&self) -> Notification {
+        Notification::from_cap(self.slot.cptr)
+    }
+
+    /// Register `handler`, minting a capability to the notification with a fresh bit as its
+    /// badge into `dest`.
+    ///
+    /// Returns the bit. Give the capability in `dest` to the event source, for signalling.
+    pub fn register(&mut self, dest: SlotRef, handler: T) -> Result<usize, EventError> {
+        let bit = match self.sources.iter().position(|s| s.is_none()) {
+            Some(bit) => bit,
+            None => return Err(EventError::Exhausted),
+        };
+        self.slot
+            .mint(dest, CapRights::W, Badge::new(1 << bit))
+            .map_err(EventError::Kernel)?;
+        self.sources[bit] = Some(EventSource {
+            slot: dest,
+            handler: handler,
+            irq: None,
+        });
+        Ok(bit)
+    }
+
+    /// Register `handler` for interrupts delivered by `irq`.
+    ///
+    /// The handler should acknowledge the interrupt once it has been dealt with.
+    pub fn register_irq(&mut self, irq: IRQHandler, dest: SlotRef, handler: T)
+                        -> Result<usize, EventError> {
+        let bit = self.register(dest, handler)?;
+        if let Err(err) = irq.set_notification(Notification::from_cap(dest.cptr)) {
+            let _ = self.unregister(bit);
+            return Err(EventError::Kernel(err));
+        }
+        if let Some(ref mut source) = self.sources[bit] {
+            source.irq = Some(irq);
+        }
+        Ok(bit)
+    }
+
+    /// Remove the source for `bit`, returning its handler.
+    ///
+    /// An interrupt source stops delivering to the notification. Every copy of the source's
+    /// capability is revoked, and then it is deleted.
+    pub fn unregister(&mut self, bit: usize) -> Result<T, EventError> {
+        let (slot, irq) = match self.sources.get(bit) {
+            Some(&Some(ref source)) => (source.slot, source.irq),
+            _ => return Err(EventError::NotRegistered),
+        };
+        if let Some(irq) = irq {
+            irq.clear_notification().map_err(EventError::Kernel)?;
+        }
+        slot.revoke().map_err(EventError::Kernel)?;
+        slot.delete().map_err(EventError::Kernel)?;
+        Ok(self.sources[bit].take().unwrap().handler)
+    }
+
+    /// Call the handler of every bit set in `word`, lowest first.
+    ///
+    /// Returns how many handlers were called. Bits with no source are ignored.
+    pub fn dispatch(&mut self, word: seL4_Word) -> usize {
+        let mut handled = 0;
+        for (bit, source) in self.sources.iter_mut().enumerate() {
+            if word & (1 << bit) == 0 {
+                continue;
+            }
+            if let Some(ref mut source) = *source {
+                source.handler.handle();
+                handled += 1;
+            }
+        }
+        handled
+    }
+
+    /// Block until some source signals, then dispatch.
+    #[inline(always)]
+    pub fn wait(&mut self) -> usize {
+        let word = self.notification().wait();
+        self.dispatch(word)
+    }
+
+    /// Dispatch whatever has been signalled since the last wait, without blocking.
+    #[inline(always)]
+    pub fn poll(&mut self) -> usize {
+        let word = self.notification().poll();
+        self.dispatch(word)
+    }
+}
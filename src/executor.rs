@@ -0,0 +1,532 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Running futures on one thread, woken by notifications
+//!
+//! A `Reactor` hands out bits of one notification's word, minting a capability badged with each.
+//! Every task spawned on an `Executor` gets a bit, and its waker signals the notification through
+//! that task's capability, so it can be woken from any thread sharing the CSpace. Event sources,
+//! such as other threads signalling or interrupts arriving, get bits too, and futures from the
+//! reactor complete when their source's bit is set.
+//!
+//! When no task can make progress, the executor blocks on the notification. If a task is waiting
+//! to receive from an endpoint, it blocks on the endpoint instead, and the notification must be
+//! bound to the thread (see `Reactor::bind`) for signals to still wake it. The kernel then
+//! delivers the notification word as the badge of the receive, so every endpoint capability used
+//! to send to the thread must have `ENDPOINT_BADGE` set in its badge. Messages sent through an
+//! unbadged capability cannot be told apart from nothing at all when polling, and are ignored.
+//!
+//! A task's wakers signal through its capability, which is only deleted once the task has
+//! completed and the last of them has been dropped. Until then, the capability's slot stays in
+//! use. The wakers point into the `Reactor`, which must outlive them.
+
+use core::cell::Cell;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+use sel4_sys::{seL4_CPtr, seL4_GetIPCBuffer, seL4_MsgMaxExtraCaps, seL4_MsgMaxLength, seL4_Signal,
+               seL4_Word};
+
+use {Badge, CapRights, EVENT_BITS, Endpoint, IRQHandler, Notification, RecvToken, SlotRef,
+     Thread};
+
+/// Set in the badge of endpoint capabilities, to tell messages from notification words.
+///
+/// A `Reactor` never uses this bit itself.
+pub const ENDPOINT_BADGE: seL4_Word = 1 << (EVENT_BITS - 1);
+
+/// Errors from managing tasks and event sources.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ExecutorError {
+    /// Every bit of the notification word is in use.
+    Exhausted,
+    /// The executor's storage is full.
+    TooManyTasks,
+    /// Invoking the kernel failed.
+    Kernel(::Error),
+}
+
+/// The notification shared by an `Executor` and the futures it runs.
+pub struct Reactor {
+    slot: SlotRef,
+    /// Bits given to tasks and sources.
+    used: Cell<seL4_Word>,
+    /// Bits given to sources.
+    sources: Cell<seL4_Word>,
+    /// Source bits set since their futures last looked.
+    fired: Cell<seL4_Word>,
+    /// Task bits set since the executor last polled those tasks.
+    woken: Cell<seL4_Word>,
+    /// The waker of the task awaiting each source bit.
+    wakers: [Cell<Option<Waker>>; EVENT_BITS],
+    /// What the wakers of the task given each bit signal through.
+    tasks: [TaskCap; EVENT_BITS],
+    /// Bits of completed tasks whose capabilities are kept for wakers still alive.
+    retired: Cell<seL4_Word>,
+    /// The endpoint a task is receiving from, with its waker.
+    receiver: Cell<Option<(Endpoint, Waker)>>,
+    /// A message received while blocked on that endpoint.
+    message: Cell<Option<SavedMessage>>,
+}
+
+impl Reactor {
+    /// Create a reactor for the unbadged notification capability in `slot`.
+    pub fn new(slot: SlotRef) -> Reactor {
+        Reactor {
+            slot: slot,
+            used: Cell::new(0),
+            sources: Cell::new(0),
+            fired: Cell::new(0),
+            woken: Cell::new(0),
+            wakers: Default::default(),
+            tasks: Default::default(),
+            retired: Cell::new(0),
+            receiver: Cell::new(None),
+            message: Cell::new(None),
+        }
+    }
+
+    /// The notification waited on.
+    #[inline(always)]
+    pub fn notification(&self) -> Notification {
+        Notification::from_cap(self.slot.cptr)
+    }
+
+    /// Bind the notification to `thread`, which should be the thread running the executor.
+    #[inline(always)]
+    pub fn bind(&self, thread: Thread) -> ::Result {
+        thread.bind_notification(self.notification())
+    }
+
+    /// Mint a capability to the notification with a fresh bit as its badge into `dest`.
+    fn allocate(&self, dest: SlotRef) -> Result<usize, ExecutorError> {
+        let used = self.used.get();
+        let bit = match (0..EVENT_BITS - 1).find(|&bit| used & (1 << bit) == 0) {
+            Some(bit) => bit,
+            None => return Err(ExecutorError::Exhausted),
+        };
+        self.slot
+            .mint(dest, CapRights::W, Badge::new(1 << bit))
+            .map_err(ExecutorError::Kernel)?;
+        self.used.set(used | 1 << bit);
+        Ok(bit)
+    }
+
+    /// Revoke and delete the capability for `bit` in `slot`, and free the bit.
+    fn release(&self, bit: usize, slot: SlotRef) -> Result<(), ExecutorError> {
+        slot.revoke().map_err(ExecutorError::Kernel)?;
+        slot.delete().map_err(ExecutorError::Kernel)?;
+        let mask = !(1 << bit);
+        self.used.set(self.used.get() & mask);
+        self.sources.set(self.sources.get() & mask);
+        self.fired.set(self.fired.get() & mask);
+        self.woken.set(self.woken.get() & mask);
+        self.wakers[bit].set(None);
+        Ok(())
+    }
+
+    /// A new waker for the task given `bit`.
+    fn task_waker(&self, bit: usize) -> Waker {
+        let cap = &self.tasks[bit];
+        cap.wakers.fetch_add(1, Ordering::SeqCst);
+        unsafe { Waker::from_raw(RawWaker::new(cap as *const TaskCap as *const (), &TASK_WAKER)) }
+    }
+
+    /// Release the bit of a completed task, once none of its wakers are left.
+    fn retire(&self, bit: usize) -> Result<(), ExecutorError> {
+        self.retired.set(self.retired.get() | 1 << bit);
+        self.collect()
+    }
+
+    /// Release the bits of retired tasks whose wakers have all been dropped.
+    fn collect(&self) -> Result<(), ExecutorError> {
+        for (bit, cap) in self.tasks.iter().enumerate() {
+            if self.retired.get() & (1 << bit) != 0 && cap.wakers.load(Ordering::SeqCst) == 0 {
+                self.retired.set(self.retired.get() & !(1 << bit));
+                if let Some(slot) = cap.slot.take() {
+                    self.release(bit, slot)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Add a source, minting the capability it should signal into `dest`.
+    pub fn source<'r>(&'r self, dest: SlotRef) -> Result<Source<'r>, ExecutorError> {
+        let bit = self.allocate(dest)?;
+        self.sources.set(self.sources.get() | 1 << bit);
+        Ok(Source {
+            reactor: self,
+            bit: bit,
+            slot: dest,
+        })
+    }
+
+    /// Add a source for interrupts delivered by `irq`, using `dest` for its capability.
+    pub fn interrupt<'r>(&'r self, irq: IRQHandler, dest: SlotRef)
+                         -> Result<Interrupt<'r>, ExecutorError> {
+        let source = self.source(dest)?;
+        if let Err(err) = irq.set_notification(Notification::from_cap(dest.cptr)) {
+            let _ = source.close();
+            return Err(ExecutorError::Kernel(err));
+        }
+        Ok(Interrupt {
+            source: source,
+            irq: irq,
+        })
+    }
+
+    /// A future receiving the next message from `ep`.
+    ///
+    /// Only one task should receive at a time.
+    #[inline(always)]
+    pub fn recv<'r>(&'r self, ep: Endpoint) -> Receive<'r> {
+        Receive {
+            reactor: self,
+            ep: ep,
+        }
+    }
+
+    /// Record the bits set in a notification word, waking tasks awaiting sources.
+    fn deliver(&self, word: seL4_Word) {
+        let sources = word & self.sources.get();
+        self.fired.set(self.fired.get() | sources);
+        self.woken.set(self.woken.get() | (word & !sources));
+        for (bit, waker) in self.wakers.iter().enumerate() {
+            if sources & (1 << bit) != 0 {
+                if let Some(waker) = waker.take() {
+                    waker.wake();
+                }
+            }
+        }
+    }
+
+    /// Whether `token` is a message, rather than a notification word from the bound notification.
+    #[inline(always)]
+    fn is_message(&self, token: &RecvToken) -> bool {
+        token.badge & ENDPOINT_BADGE != 0
+    }
+
+    /// Drop every waker stored here which wakes the same task as `waker`.
+    fn forget(&self, waker: &Waker) {
+        for stored in self.wakers.iter() {
+            match stored.take() {
+                Some(ref w) if w.will_wake(waker) => {}
+                other => stored.set(other),
+            }
+        }
+        match self.receiver.take() {
+            Some((_, ref w)) if w.will_wake(waker) => {}
+            other => self.receiver.set(other),
+        }
+    }
+
+    /// Block until something happens.
+    fn idle(&self) {
+        match self.receiver.take() {
+            Some((ep, waker)) => {
+                let token = ep.recv();
+                if self.is_message(&token) {
+                    self.message.set(Some(SavedMessage::new(token)));
+                    waker.wake();
+                } else {
+                    self.receiver.set(Some((ep, waker)));
+                    if token.badge != 0 {
+                        self.deliver(token.badge);
+                    }
+                }
+            }
+            None => self.deliver(self.notification().wait()),
+        }
+    }
+}
+
+/// A message received by the executor for a task, with the IPC buffer contents it was received
+/// with.
+///
+/// Other tasks may be polled, and make calls of their own, before the receiving task is.
+struct SavedMessage {
+    token: RecvToken,
+    data: [seL4_Word; seL4_MsgMaxLength],
+    badges: [seL4_Word; seL4_MsgMaxExtraCaps],
+}
+
+impl SavedMessage {
+    /// Copy the message for `token` out of the IPC buffer.
+    fn new(token: RecvToken) -> SavedMessage {
+        let buf = unsafe { &*seL4_GetIPCBuffer() };
+        SavedMessage {
+            token: token,
+            data: buf.msg,
+            badges: buf.caps_or_badges,
+        }
+    }
+
+    /// Put the message back in the IPC buffer, where the token reads it from.
+    fn restore(self) -> RecvToken {
+        let buf = unsafe { &mut *seL4_GetIPCBuffer() };
+        buf.msg = self.data;
+        buf.caps_or_badges = self.badges;
+        self.token
+    }
+}
+
+/// A source of events, which signals its own bit of a `Reactor`'s notification.
+pub struct Source<'r> {
+    reactor: &'r Reactor,
+    bit: usize,
+    slot: SlotRef,
+}
+
+impl<'r> Source<'r> {
+    /// The bit of the notification word used.
+    #[inline(always)]
+    pub fn bit(&self) -> usize {
+        self.bit
+    }
+
+    /// The slot holding the capability to give to whoever signals.
+    #[inline(always)]
+    pub fn slot(&self) -> SlotRef {
+        self.slot
+    }
+
+    /// A future completing when the source next signals.
+    ///
+    /// Signals since the source was last awaited are merged, and complete it straight away.
+    #[inline(always)]
+    pub fn signalled(&self) -> Signalled<'r> {
+        Signalled {
+            reactor: self.reactor,
+            bit: self.bit,
+        }
+    }
+
+    /// Remove the source, revoking and deleting its capability.
+    pub fn close(self) -> Result<(), ExecutorError> {
+        self.reactor.release(self.bit, self.slot)
+    }
+}
+
+/// A source of interrupts.
+pub struct Interrupt<'r> {
+    source: Source<'r>,
+    irq: IRQHandler,
+}
+
+impl<'r> Interrupt<'r> {
+    /// A future completing when the next interrupt arrives.
+    ///
+    /// No more are delivered until the interrupt is acknowledged.
+    #[inline(always)]
+    pub fn arrived(&self) -> Signalled<'r> {
+        self.source.signalled()
+    }
+
+    /// Acknowledge the interrupt, once it has been dealt with.
+    #[inline(always)]
+    pub fn acknowledge(&self) -> ::Result {
+        self.irq.acknowledge()
+    }
+
+    /// Stop delivering interrupts to the reactor, and remove the source.
+    pub fn close(self) -> Result<(), ExecutorError> {
+        self.irq.clear_notification().map_err(ExecutorError::Kernel)?;
+        self.source.close()
+    }
+}
+
+/// Future returned by `Source::signalled` and `Interrupt::arrived`.
+pub struct Signalled<'r> {
+    reactor: &'r Reactor,
+    bit: usize,
+}
+
+impl<'r> Future for Signalled<'r> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let fired = self.reactor.fired.get();
+        if fired & (1 << self.bit) != 0 {
+            self.reactor.fired.set(fired & !(1 << self.bit));
+            return Poll::Ready(());
+        }
+        self.reactor.wakers[self.bit].set(Some(cx.waker().clone()));
+        Poll::Pending
+    }
+}
+
+/// Future returned by `Reactor::recv`.
+pub struct Receive<'r> {
+    reactor: &'r Reactor,
+    ep: Endpoint,
+}
+
+impl<'r> Future for Receive<'r> {
+    type Output = RecvToken;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<RecvToken> {
+        let reactor = self.reactor;
+        if let Some(message) = reactor.message.take() {
+            return Poll::Ready(message.restore());
+        }
+        // Polling the endpoint also takes any signals of the bound notification. Nothing
+        // received looks like an unbadged message, which is why those are not allowed.
+        while let Ok(token) = self.ep.try_recv() {
+            if reactor.is_message(&token) {
+                return Poll::Ready(token);
+            }
+            reactor.deliver(token.badge);
+        }
+        reactor.receiver.set(Some((self.ep, cx.waker().clone())));
+        Poll::Pending
+    }
+}
+
+impl<'r> Drop for Receive<'r> {
+    fn drop(&mut self) {
+        let reactor = self.reactor;
+        match reactor.receiver.take() {
+            Some((ep, _)) if ep == self.ep => {}
+            other => reactor.receiver.set(other),
+        }
+    }
+}
+
+/// The capability a task's wakers signal, and how many of them there are.
+#[derive(Default)]
+struct TaskCap {
+    cptr: AtomicUsize,
+    wakers: AtomicUsize,
+    slot: Cell<Option<SlotRef>>,
+}
+
+static TASK_WAKER: RawWakerVTable = RawWakerVTable::new(clone_task_waker,
+                                                         wake_task,
+                                                         wake_task_by_ref,
+                                                         drop_task_waker);
+
+// The data of a task's waker points to its `TaskCap`.
+
+unsafe fn clone_task_waker(data: *const ()) -> RawWaker {
+    (*(data as *const TaskCap)).wakers.fetch_add(1, Ordering::SeqCst);
+    RawWaker::new(data, &TASK_WAKER)
+}
+
+unsafe fn wake_task(data: *const ()) {
+    wake_task_by_ref(data);
+    drop_task_waker(data);
+}
+
+unsafe fn wake_task_by_ref(data: *const ()) {
+    seL4_Signal((*(data as *const TaskCap)).cptr.load(Ordering::SeqCst) as seL4_CPtr)
+}
+
+unsafe fn drop_task_waker(data: *const ()) {
+    (*(data as *const TaskCap)).wakers.fetch_sub(1, Ordering::SeqCst);
+}
+
+/// A future spawned on an `Executor`.
+pub struct Task<'a> {
+    future: Pin<&'a mut (dyn Future<Output = ()> + 'a)>,
+    bit: usize,
+}
+
+/// Runs tasks until they complete, blocking when none can make progress.
+pub struct Executor<'a> {
+    reactor: &'a Reactor,
+    tasks: &'a mut [Option<Task<'a>>],
+}
+
+impl<'a> Executor<'a> {
+    /// Create an executor for the tasks of `reactor`, with room for as many as `storage` holds.
+    pub fn new(reactor: &'a Reactor, storage: &'a mut [Option<Task<'a>>]) -> Executor<'a> {
+        for task in storage.iter_mut() {
+            *task = None;
+        }
+        Executor {
+            reactor: reactor,
+            tasks: storage,
+        }
+    }
+
+    /// Add a task, minting the capability its waker signals into `dest`.
+    ///
+    /// It is first polled by the next `run`. When it completes, any of its wakers the reactor
+    /// holds are dropped, and the capability is deleted once no others are left.
+    pub fn spawn<F>(&mut self, future: Pin<&'a mut F>, dest: SlotRef) -> Result<(), ExecutorError>
+        where F: Future<Output = ()> + 'a
+    {
+        let index = match self.tasks.iter().position(|t| t.is_none()) {
+            Some(index) => index,
+            None => return Err(ExecutorError::TooManyTasks),
+        };
+        let bit = self.reactor.allocate(dest)?;
+        let cap = &self.reactor.tasks[bit];
+        cap.cptr.store(dest.cptr as usize, Ordering::SeqCst);
+        cap.slot.set(Some(dest));
+        self.reactor.woken.set(self.reactor.woken.get() | 1 << bit);
+        self.tasks[index] = Some(Task {
+            future: future,
+            bit: bit,
+        });
+        Ok(())
+    }
+
+    /// Number of tasks which have not completed.
+    #[inline(always)]
+    pub fn tasks(&self) -> usize {
+        self.tasks.iter().filter(|t| t.is_some()).count()
+    }
+
+    /// Poll every task which has been woken, removing those which complete.
+    fn poll_woken(&mut self) -> Result<(), ExecutorError> {
+        let woken = self.reactor.woken.replace(0);
+        for entry in self.tasks.iter_mut() {
+            let done = match *entry {
+                Some(ref mut task) if woken & (1 << task.bit) != 0 => {
+                    let waker = self.reactor.task_waker(task.bit);
+                    let mut cx = Context::from_waker(&waker);
+                    task.future.as_mut().poll(&mut cx).is_ready()
+                }
+                _ => false,
+            };
+            if done {
+                let task = entry.take().unwrap();
+                // Those wakers could be kept forever, holding on to the capability.
+                self.reactor.forget(&self.reactor.task_waker(task.bit));
+                self.reactor.retire(task.bit)?;
+            }
+        }
+        self.reactor.collect()
+    }
+
+    /// Run until every task has completed.
+    pub fn run(&mut self) -> Result<(), ExecutorError> {
+        loop {
+            self.poll_woken()?;
+            if self.tasks() == 0 {
+                return Ok(());
+            }
+            // Waking the tasks awaiting sources signals again, so poll until nothing is left.
+            while self.reactor.woken.get() == 0 {
+                let word = self.reactor.notification().poll();
+                if word == 0 {
+                    break;
+                }
+                self.reactor.deliver(word);
+            }
+            if self.reactor.woken.get() == 0 {
+                self.reactor.idle();
+            }
+        }
+    }
+}
@@ -0,0 +1,155 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Declaring RPC interfaces over endpoints
+//!
+//! `sel4_interface!` takes a list of methods, each with typed arguments, a return type and the
+//! message label which selects it, and generates:
+//!
+//! - a server trait with those methods, plus `handle` and `dispatch` methods which decode a
+//!   received request, call the method named by its label, and build or send the reply;
+//! - a client struct wrapping an `Endpoint`, with a method for each which encodes the arguments,
+//!   calls the endpoint and decodes the result.
+//!
+//! ```ignore
+//! sel4_interface! {
+//!     /// A terminal.
+//!     pub trait Console, client ConsoleClient {
+//!         /// Write some text, returning how much was written.
+//!         fn write(text: Bytes<[u8; 64]>) -> usize = 1;
+//!         fn set_colour(fg: u8, bg: u8) -> () = 2;
+//!     }
+//! }
+//! ```
+//!
+//! Arguments and return values are encoded with `IpcEncode`. A reply with label `REPLY_OK`
+//! carries the return value. If the server cannot decode a request, or does not know its label,
+//! it replies with label `REPLY_REJECTED` and the `DecodeError`, and the client's call fails with
+//! `Rejected`. A reply the client cannot decode fails with `Malformed`.
+
+use sel4_sys::seL4_Word;
+
+use {DecodeError, Endpoint, IpcDecode, IpcEncode, MessageBuilder, encode_message};
+
+/// Label of a reply carrying a method's return value.
+pub const REPLY_OK: seL4_Word = 0;
+
+/// Label of a reply sent when the server could not decode a request.
+///
+/// This is the largest label which fits in a message on every architecture, well clear of the
+/// kernel's error codes, so a rejection is never mistaken for a failed invocation.
+pub const REPLY_REJECTED: seL4_Word = 0xf_ffff;
+
+/// Result of calling a method through an interface client.
+pub type CallResult<T> = ::core::result::Result<T, ::Error>;
+
+/// Call `ep` with `args`, and decode the reply. Used by interface clients.
+#[doc(hidden)]
+#[inline(always)]
+pub fn interface_call<A: IpcEncode, R: IpcDecode>(ep: Endpoint, label: seL4_Word, args: &A)
+                                                  -> CallResult<R> {
+    let token = ep.call_built(encode_message(label, args)?);
+    match token.label {
+        REPLY_OK => token.decode().map_err(::Error::from),
+        REPLY_REJECTED => {
+            let reason = token.decode()?;
+            Err(::Error(::GoOn::Rejected(reason)))
+        }
+        _ => Err(::Error(::GoOn::Malformed(DecodeError::UnknownLabel))),
+    }
+}
+
+/// Build the reply carrying a method's return value. Used by interface servers.
+#[doc(hidden)]
+#[inline(always)]
+pub fn interface_reply<R: IpcEncode>(ret: &R) -> MessageBuilder {
+    match encode_message(REPLY_OK, ret) {
+        Ok(msg) => msg,
+        Err(_) => interface_reject(DecodeError::InvalidValue),
+    }
+}
+
+/// Build the reply rejecting a request. Used by interface servers.
+#[doc(hidden)]
+#[inline(always)]
+pub fn interface_reject(reason: DecodeError) -> MessageBuilder {
+    let mut msg = MessageBuilder::new(REPLY_REJECTED);
+    let _ = reason.encode(&mut msg);
+    msg
+}
+
+/// Declare an RPC interface, generating a server trait and a client.
+///
+/// See the `interface` module documentation for the generated items and the wire format.
+#[macro_export]
+macro_rules! sel4_interface {
+    (
+        $(#[$attr:meta])*
+        pub trait $server:ident, client $client:ident {
+            $(
+                $(#[$mattr:meta])*
+                fn $method:ident($($arg:ident: $ty:ty),*) -> $ret:ty = $label:expr;
+            )*
+        }
+    ) => {
+        $(#[$attr])*
+        pub trait $server {
+            $(
+                $(#[$mattr])*
+                fn $method(&mut self, $($arg: $ty),*) -> $ret;
+            )*
+
+            /// Decode the request in `token`, call the method its label names, and build the
+            /// reply.
+            fn handle(&mut self, token: &$crate::RecvToken) -> $crate::MessageBuilder {
+                $(
+                    if token.label == $label {
+                        return match token.decode::<($($ty,)*)>() {
+                            Ok(($($arg,)*)) => {
+                                $crate::interface_reply(&self.$method($($arg),*))
+                            }
+                            Err(err) => $crate::interface_reject(err),
+                        };
+                    }
+                )*
+                $crate::interface_reject($crate::DecodeError::UnknownLabel)
+            }
+
+            /// Handle the request in `token` and reply to the caller.
+            fn dispatch(&mut self, token: &$crate::RecvToken) -> $crate::Result {
+                $crate::reply_built(self.handle(token))
+            }
+        }
+
+        $(#[$attr])*
+        #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+        pub struct $client {
+            ep: $crate::Endpoint,
+        }
+
+        impl $client {
+            /// Make calls through `ep`.
+            pub fn new(ep: $crate::Endpoint) -> $client {
+                $client { ep: ep }
+            }
+
+            /// The endpoint calls are made through.
+            pub fn endpoint(&self) -> $crate::Endpoint {
+                self.ep
+            }
+
+            $(
+                $(#[$mattr])*
+                pub fn $method(&self, $($arg: $ty),*) -> $crate::CallResult<$ret> {
+                    $crate::interface_call(self.ep, $label, &($($arg,)*))
+                }
+            )*
+        }
+    };
+}
@@ -0,0 +1,574 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Encoding Rust values into IPC messages
+//!
+//! `IpcEncode` writes a value into the message registers and extra caps of a `MessageBuilder`,
+//! and `IpcDecode` reads it back out of a `MessageReader`. They are implemented for integers,
+//! `bool`, `char`, `Option`, tuples, fixed-size arrays, byte strings (`Bytes`) and every
+//! capability type. Structs and enums built from those can derive both traits with the
+//! `sel4-derive` crate:
+//!
+//! ```ignore
+//! #[macro_use]
+//! extern crate sel4_derive;
+//!
+//! #[derive(IpcEncode, IpcDecode)]
+//! struct Open {
+//!     path: sel4::Bytes<[u8; 64]>,
+//!     flags: u32,
+//!     reply_to: sel4::Endpoint,
+//! }
+//! ```
+//!
+//! Each integer takes a whole message register, as does the variant index of an enum. Fields
+//! are encoded in the order they are declared. Capabilities are sent as extra caps, and since the
+//! kernel transfers at most one capability per message, at most one can be decoded.
+//!
+//! `WORDS` and `CAPS` give the most space a type can take in a message. `Endpoint::send_typed`
+//! and `Endpoint::call_typed` check these against `seL4_MsgMaxLength` and `MAX_TYPED_CAPS` when
+//! they are instantiated, so sending a type which can never fit is a compile-time error.
+
+use core::marker::PhantomData;
+
+use sel4_sys::*;
+
+use {MessageBuilder, MessageReader};
+
+#[cfg(target_pointer_width = "32")]
+const WORD_BYTES: usize = 4;
+#[cfg(target_pointer_width = "64")]
+const WORD_BYTES: usize = 8;
+
+/// Why a message could not be decoded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The message ended before the value did.
+    Truncated,
+    /// A capability was expected but none was received.
+    MissingCap,
+    /// A word did not hold a valid value for its type, such as an out of range integer or an
+    /// unknown enum variant.
+    InvalidValue,
+    /// The message label did not name any known operation.
+    UnknownLabel,
+}
+
+impl ::core::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        match *self {
+            DecodeError::Truncated => write!(f, "the message was too short"),
+            DecodeError::MissingCap => write!(f, "a capability was not received"),
+            DecodeError::InvalidValue => write!(f, "a value was out of range"),
+            DecodeError::UnknownLabel => write!(f, "the label was not recognised"),
+        }
+    }
+}
+
+impl From<DecodeError> for ::Error {
+    #[inline(always)]
+    fn from(err: DecodeError) -> ::Error {
+        ::Error(::GoOn::Malformed(err))
+    }
+}
+
+/// Errors from `Endpoint::call_typed`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TypedCallError {
+    /// The reply's label was not 0. The label is the server's, not a kernel error code.
+    UnexpectedLabel(seL4_Word),
+    /// The reply did not decode.
+    Malformed(DecodeError),
+    /// The request could not be encoded.
+    Kernel(::Error),
+}
+
+impl From<::Error> for TypedCallError {
+    #[inline(always)]
+    fn from(err: ::Error) -> TypedCallError {
+        TypedCallError::Kernel(err)
+    }
+}
+
+impl From<DecodeError> for TypedCallError {
+    #[inline(always)]
+    fn from(err: DecodeError) -> TypedCallError {
+        TypedCallError::Malformed(err)
+    }
+}
+
+impl IpcEncode for DecodeError {
+    const WORDS: usize = 1;
+    const CAPS: usize = 0;
+
+    #[inline(always)]
+    fn encode(&self, msg: &mut MessageBuilder) -> ::Result {
+        let code = match *self {
+            DecodeError::Truncated => 0,
+            DecodeError::MissingCap => 1,
+            DecodeError::InvalidValue => 2,
+            DecodeError::UnknownLabel => 3,
+        };
+        msg.push_word(code).map(|_| ())
+    }
+}
+
+impl IpcDecode for DecodeError {
+    #[inline(always)]
+    fn decode(msg: &mut MessageReader) -> DecodeResult<DecodeError> {
+        match msg.read_word() {
+            Some(0) => Ok(DecodeError::Truncated),
+            Some(1) => Ok(DecodeError::MissingCap),
+            Some(2) => Ok(DecodeError::InvalidValue),
+            Some(3) => Ok(DecodeError::UnknownLabel),
+            Some(_) => Err(DecodeError::InvalidValue),
+            None => Err(DecodeError::Truncated),
+        }
+    }
+}
+
+/// Result of decoding a message.
+pub type DecodeResult<T> = ::core::result::Result<T, DecodeError>;
+
+/// A type which can be written into an IPC message.
+pub trait IpcEncode {
+    /// Most message registers an encoded value can take.
+    const WORDS: usize;
+    /// Most capabilities an encoded value can take.
+    const CAPS: usize;
+
+    /// Append this value to `msg`.
+    fn encode(&self, msg: &mut MessageBuilder) -> ::Result;
+}
+
+/// A type which can be read from an IPC message.
+pub trait IpcDecode: Sized {
+    /// Read a value from `msg`, starting where the last value left off.
+    fn decode(msg: &mut MessageReader) -> DecodeResult<Self>;
+}
+
+/// The larger of two sizes, for the `WORDS` of enums.
+#[doc(hidden)]
+#[inline(always)]
+pub const fn max_words(a: usize, b: usize) -> usize {
+    if a > b { a } else { b }
+}
+
+/// Most capabilities a typed message can carry.
+///
+/// The kernel sends up to `seL4_MsgMaxExtraCaps`, but the receiver only has one receive slot, and
+/// `MessageReader::read_cap` can only return what is stored there.
+pub const MAX_TYPED_CAPS: usize = 1;
+
+struct Fits<T>(PhantomData<T>);
+
+impl<T: IpcEncode> Fits<T> {
+    // These overflow, failing to compile, if `T` does not fit in a message.
+    const WORDS_LEFT: usize = seL4_MsgMaxLength - T::WORDS;
+    const CAPS_LEFT: usize = MAX_TYPED_CAPS - T::CAPS;
+}
+
+/// Fail to compile unless every value of `T` fits in a single message.
+#[doc(hidden)]
+#[inline(always)]
+pub fn assert_fits<T: IpcEncode>() {
+    let _ = Fits::<T>::WORDS_LEFT;
+    let _ = Fits::<T>::CAPS_LEFT;
+}
+
+/// Encode `value` into a new message with the given label.
+#[inline(always)]
+pub fn encode_message<T: IpcEncode>(label: seL4_Word, value: &T)
+                                    -> ::core::result::Result<MessageBuilder, ::Error> {
+    assert_fits::<T>();
+    let mut msg = MessageBuilder::new(label);
+    value.encode(&mut msg)?;
+    Ok(msg)
+}
+
+impl IpcEncode for () {
+    const WORDS: usize = 0;
+    const CAPS: usize = 0;
+
+    #[inline(always)]
+    fn encode(&self, _: &mut MessageBuilder) -> ::Result {
+        Ok(())
+    }
+}
+
+impl IpcDecode for () {
+    #[inline(always)]
+    fn decode(_: &mut MessageReader) -> DecodeResult<()> {
+        Ok(())
+    }
+}
+
+macro_rules! word_impls {
+    ($($t:ty)*) => {
+        $(
+            impl IpcEncode for $t {
+                const WORDS: usize = 1;
+                const CAPS: usize = 0;
+
+                #[inline(always)]
+                fn encode(&self, msg: &mut MessageBuilder) -> ::Result {
+                    msg.push_word(*self as seL4_Word).map(|_| ())
+                }
+            }
+
+            impl IpcDecode for $t {
+                #[inline(always)]
+                fn decode(msg: &mut MessageReader) -> DecodeResult<$t> {
+                    let word = msg.read_word().ok_or(DecodeError::Truncated)?;
+                    let val = word as $t;
+                    if val as seL4_Word == word {
+                        Ok(val)
+                    } else {
+                        Err(DecodeError::InvalidValue)
+                    }
+                }
+            }
+        )*
+    }
+}
+
+word_impls!(u8 u16 u32 usize i8 i16 i32 isize);
+#[cfg(target_pointer_width = "64")]
+word_impls!(u64 i64);
+
+#[cfg(target_pointer_width = "32")]
+macro_rules! double_word_impls {
+    ($($t:ty)*) => {
+        $(
+            impl IpcEncode for $t {
+                const WORDS: usize = 2;
+                const CAPS: usize = 0;
+
+                #[inline(always)]
+                fn encode(&self, msg: &mut MessageBuilder) -> ::Result {
+                    msg.push_words(&[*self as seL4_Word, (*self >> 32) as seL4_Word]).map(|_| ())
+                }
+            }
+
+            impl IpcDecode for $t {
+                #[inline(always)]
+                fn decode(msg: &mut MessageReader) -> DecodeResult<$t> {
+                    let mut words = [0; 2];
+                    msg.read_words(&mut words).map_err(|()| DecodeError::Truncated)?;
+                    Ok((words[0] as u64 | (words[1] as u64) << 32) as $t)
+                }
+            }
+        )*
+    }
+}
+
+#[cfg(target_pointer_width = "32")]
+double_word_impls!(u64 i64);
+
+impl IpcEncode for bool {
+    const WORDS: usize = 1;
+    const CAPS: usize = 0;
+
+    #[inline(always)]
+    fn encode(&self, msg: &mut MessageBuilder) -> ::Result {
+        msg.push_word(*self as seL4_Word).map(|_| ())
+    }
+}
+
+impl IpcDecode for bool {
+    #[inline(always)]
+    fn decode(msg: &mut MessageReader) -> DecodeResult<bool> {
+        match msg.read_word() {
+            Some(0) => Ok(false),
+            Some(1) => Ok(true),
+            Some(_) => Err(DecodeError::InvalidValue),
+            None => Err(DecodeError::Truncated),
+        }
+    }
+}
+
+impl IpcEncode for char {
+    const WORDS: usize = 1;
+    const CAPS: usize = 0;
+
+    #[inline(always)]
+    fn encode(&self, msg: &mut MessageBuilder) -> ::Result {
+        msg.push_word(*self as seL4_Word).map(|_| ())
+    }
+}
+
+impl IpcDecode for char {
+    #[inline(always)]
+    fn decode(msg: &mut MessageReader) -> DecodeResult<char> {
+        let val = u32::decode(msg)?;
+        ::core::char::from_u32(val).ok_or(DecodeError::InvalidValue)
+    }
+}
+
+macro_rules! array_impls {
+    ($($n:expr => ($($elem:ident)*))*) => {
+        $(
+            impl<T: IpcEncode> IpcEncode for [T; $n] {
+                const WORDS: usize = T::WORDS * $n;
+                const CAPS: usize = T::CAPS * $n;
+
+                #[inline(always)]
+                fn encode(&self, msg: &mut MessageBuilder) -> ::Result {
+                    for elem in self.iter() {
+                        elem.encode(msg)?;
+                    }
+                    Ok(())
+                }
+            }
+
+            impl<T: IpcDecode> IpcDecode for [T; $n] {
+                #[inline(always)]
+                #[allow(unused_variables)]
+                fn decode(msg: &mut MessageReader) -> DecodeResult<[T; $n]> {
+                    Ok([$({
+                        let $elem = T::decode(msg)?;
+                        $elem
+                    }),*])
+                }
+            }
+        )*
+    }
+}
+
+array_impls! {
+    0 => ()
+    1 => (a)
+    2 => (a b)
+    3 => (a b c)
+    4 => (a b c d)
+    5 => (a b c d e)
+    6 => (a b c d e f)
+    7 => (a b c d e f g)
+    8 => (a b c d e f g h)
+    9 => (a b c d e f g h i)
+    10 => (a b c d e f g h i j)
+    11 => (a b c d e f g h i j k)
+    12 => (a b c d e f g h i j k l)
+    13 => (a b c d e f g h i j k l m)
+    14 => (a b c d e f g h i j k l m n)
+    15 => (a b c d e f g h i j k l m n o)
+    16 => (a b c d e f g h i j k l m n o p)
+    17 => (a b c d e f g h i j k l m n o p q)
+    18 => (a b c d e f g h i j k l m n o p q r)
+    19 => (a b c d e f g h i j k l m n o p q r s)
+    20 => (a b c d e f g h i j k l m n o p q r s t)
+    21 => (a b c d e f g h i j k l m n o p q r s t u)
+    22 => (a b c d e f g h i j k l m n o p q r s t u v)
+    23 => (a b c d e f g h i j k l m n o p q r s t u v w)
+    24 => (a b c d e f g h i j k l m n o p q r s t u v w x)
+    25 => (a b c d e f g h i j k l m n o p q r s t u v w x y)
+    26 => (a b c d e f g h i j k l m n o p q r s t u v w x y z)
+    27 => (a b c d e f g h i j k l m n o p q r s t u v w x y z aa)
+    28 => (a b c d e f g h i j k l m n o p q r s t u v w x y z aa bb)
+    29 => (a b c d e f g h i j k l m n o p q r s t u v w x y z aa bb cc)
+    30 => (a b c d e f g h i j k l m n o p q r s t u v w x y z aa bb cc dd)
+    31 => (a b c d e f g h i j k l m n o p q r s t u v w x y z aa bb cc dd ee)
+    32 => (a b c d e f g h i j k l m n o p q r s t u v w x y z aa bb cc dd ee ff)
+}
+
+impl<T: IpcEncode> IpcEncode for Option<T> {
+    const WORDS: usize = 1 + T::WORDS;
+    const CAPS: usize = T::CAPS;
+
+    #[inline(always)]
+    fn encode(&self, msg: &mut MessageBuilder) -> ::Result {
+        match *self {
+            None => msg.push_word(0).map(|_| ()),
+            Some(ref val) => {
+                msg.push_word(1)?;
+                val.encode(msg)
+            }
+        }
+    }
+}
+
+impl<T: IpcDecode> IpcDecode for Option<T> {
+    #[inline(always)]
+    fn decode(msg: &mut MessageReader) -> DecodeResult<Option<T>> {
+        match msg.read_word() {
+            Some(0) => Ok(None),
+            Some(1) => T::decode(msg).map(Some),
+            Some(_) => Err(DecodeError::InvalidValue),
+            None => Err(DecodeError::Truncated),
+        }
+    }
+}
+
+macro_rules! tuple_impls {
+    ($(($($t:ident)+))*) => {
+        $(
+            impl<$($t: IpcEncode),+> IpcEncode for ($($t,)+) {
+                const WORDS: usize = 0 $(+ $t::WORDS)+;
+                const CAPS: usize = 0 $(+ $t::CAPS)+;
+
+                #[inline(always)]
+                #[allow(non_snake_case)]
+                fn encode(&self, msg: &mut MessageBuilder) -> ::Result {
+                    let ($(ref $t,)+) = *self;
+                    $($t.encode(msg)?;)+
+                    Ok(())
+                }
+            }
+
+            impl<$($t: IpcDecode),+> IpcDecode for ($($t,)+) {
+                #[inline(always)]
+                fn decode(msg: &mut MessageReader) -> DecodeResult<($($t,)+)> {
+                    Ok(($($t::decode(msg)?,)+))
+                }
+            }
+        )*
+    }
+}
+
+tuple_impls! {
+    (A)
+    (A B)
+    (A B C)
+    (A B C D)
+    (A B C D E)
+    (A B C D E F)
+    (A B C D E F G)
+    (A B C D E F G H)
+}
+
+/// Fixed-size storage for the contents of a `Bytes`.
+pub trait ByteArray: Copy {
+    /// Number of bytes which can be stored.
+    const CAPACITY: usize;
+
+    fn zeroed() -> Self;
+    fn as_slice(&self) -> &[u8];
+    fn as_mut_slice(&mut self) -> &mut [u8];
+}
+
+macro_rules! byte_array_impls {
+    ($($n:expr)*) => {
+        $(
+            impl ByteArray for [u8; $n] {
+                const CAPACITY: usize = $n;
+
+                #[inline(always)]
+                fn zeroed() -> [u8; $n] {
+                    [0; $n]
+                }
+
+                #[inline(always)]
+                fn as_slice(&self) -> &[u8] {
+                    self
+                }
+
+                #[inline(always)]
+                fn as_mut_slice(&mut self) -> &mut [u8] {
+                    self
+                }
+            }
+        )*
+    }
+}
+
+byte_array_impls!(1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27 28 29
+                  30 31 32 48 64 96 128 192 256 384 512);
+
+/// A byte string of up to `A::CAPACITY` bytes, stored inline.
+///
+/// It is encoded as its length followed by the bytes, packed into as few words as possible.
+#[derive(Copy, Clone)]
+pub struct Bytes<A: ByteArray> {
+    len: usize,
+    buf: A,
+}
+
+impl<A: ByteArray> Bytes<A> {
+    /// An empty byte string.
+    #[inline(always)]
+    pub fn new() -> Bytes<A> {
+        Bytes {
+            len: 0,
+            buf: A::zeroed(),
+        }
+    }
+
+    /// Copy `bytes` into a new byte string, or `None` if it is longer than the capacity.
+    #[inline(always)]
+    pub fn from_slice(bytes: &[u8]) -> Option<Bytes<A>> {
+        if bytes.len() > A::CAPACITY {
+            return None;
+        }
+        let mut new: Bytes<A> = Bytes::new();
+        new.buf.as_mut_slice()[..bytes.len()].copy_from_slice(bytes);
+        new.len = bytes.len();
+        Some(new)
+    }
+
+    /// The contents of the byte string.
+    #[inline(always)]
+    pub fn as_slice(&self) -> &[u8] {
+        &self.buf.as_slice()[..self.len]
+    }
+
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<A: ByteArray> Default for Bytes<A> {
+    #[inline(always)]
+    fn default() -> Bytes<A> {
+        Bytes::new()
+    }
+}
+
+impl<A: ByteArray> PartialEq for Bytes<A> {
+    fn eq(&self, other: &Bytes<A>) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl<A: ByteArray> Eq for Bytes<A> {}
+
+impl<A: ByteArray> ::core::fmt::Debug for Bytes<A> {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        ::core::fmt::Debug::fmt(self.as_slice(), f)
+    }
+}
+
+impl<A: ByteArray> IpcEncode for Bytes<A> {
+    const WORDS: usize = 1 + (A::CAPACITY + WORD_BYTES - 1) / WORD_BYTES;
+    const CAPS: usize = 0;
+
+    #[inline(always)]
+    fn encode(&self, msg: &mut MessageBuilder) -> ::Result {
+        msg.push_word(self.len)?.push_bytes(self.as_slice()).map(|_| ())
+    }
+}
+
+impl<A: ByteArray> IpcDecode for Bytes<A> {
+    #[inline(always)]
+    fn decode(msg: &mut MessageReader) -> DecodeResult<Bytes<A>> {
+        let len = msg.read_word().ok_or(DecodeError::Truncated)?;
+        if len > A::CAPACITY {
+            return Err(DecodeError::InvalidValue);
+        }
+        let mut new: Bytes<A> = Bytes::new();
+        msg.read_bytes(&mut new.buf.as_mut_slice()[..len]).map_err(|()| DecodeError::Truncated)?;
+        new.len = len;
+        Ok(new)
+    }
+}
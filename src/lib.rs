@@ -17,12 +17,21 @@
 //! **Note**: when method documentation says "this", it refers to the receiver of the thread, not
 //! any global state.
 
-#![no_std]
+#![cfg_attr(not(feature = "mock"), no_std)]
 #![allow(stable_features, unused_features)]
-#![feature(no_std, core_slice_ext, const_fn)]
+#![feature(no_std, core_slice_ext, const_fn, associated_consts)]
 #![doc(html_root_url = "https://doc.robigalia.org/")]
 
+#[cfg(feature = "mock")]
+extern crate core;
+#[cfg(not(feature = "mock"))]
 extern crate sel4_sys;
+#[cfg(feature = "mock")]
+extern crate sel4_sys as raw_sel4_sys;
+
+#[cfg(feature = "mock")]
+#[path = "mock/mod.rs"]
+mod sel4_sys;
 use sel4_sys::{seL4_CPtr, seL4_GetIPCBuffer, seL4_Word, seL4_Yield};
 
 #[macro_use]
@@ -30,23 +39,68 @@ mod macros;
 
 mod alloc;
 mod arch;
+mod badge;
+mod bootinfo;
+mod bulk;
+mod context;
 mod cspace;
 mod domain;
 mod endpoint;
 mod error;
+mod event;
+mod executor;
+mod interface;
+mod ipc;
 mod irq;
+mod message;
 mod notification;
+mod owned;
+mod receive;
+mod ring;
+mod spawn;
+mod sync;
 mod thread;
+mod untyped;
 
-pub use alloc::ObjectAllocator;
+pub use alloc::{Allocation, BitmapSlotAllocator, BuddyAllocator, BuddyBlock, BuddyStats,
+                MAX_SLOT_WINDOWS, MIN_BLOCK_BITS, ObjectAllocator, SizedObjectAllocator,
+                SlotAllocError, SlotAllocator, UntypedAllocError, UntypedAllocator, UntypedRegion,
+                bitmap_words};
 pub use arch::*;
-pub use cspace::{Badge, CNode, CNodeInfo, SlotRef, Window};
+pub use badge::{BadgeAllocator, BadgeDispatcher, BadgeError, BadgeHandler, BadgedClient,
+                MAX_BADGE};
+pub use bootinfo::{BootInfo, UntypedIter};
+pub use bulk::{BulkError, BulkReceiver, BulkSender, bulk_inline_bytes};
+pub use context::{ARG_REGISTERS, RegisterSet, UserContext};
+pub use cspace::{Badge, CNode, CNodeInfo, CSpace, CSpaceError, CSpaceNode, CapRights, ObjectRights,
+                 SlotRef, TypedSlot, Window};
 pub use domain::DomainSet;
-pub use endpoint::{Endpoint, RecvToken};
+pub use endpoint::{Endpoint, RecvToken, Reply, ServeAction, reply, reply_built};
 pub use error::{ErrorDetails, LookupFailureKind};
+pub use event::{EVENT_BITS, EventError, EventHandler, EventMux, EventSource};
+pub use executor::{ENDPOINT_BADGE, Executor, ExecutorError, Interrupt, Reactor, Receive, Signalled,
+                   Source, Task};
+pub use interface::{CallResult, REPLY_OK, REPLY_REJECTED};
+#[doc(hidden)]
+pub use interface::{interface_call, interface_reject, interface_reply};
+pub use ipc::{ByteArray, Bytes, DecodeError, DecodeResult, IpcDecode, IpcEncode, MAX_TYPED_CAPS,
+              TypedCallError, encode_message};
+#[doc(hidden)]
+pub use ipc::{assert_fits, max_words};
 pub use irq::{IRQControl, IRQHandler};
+pub use message::{MessageBuilder, MessageReader, words_for_bytes};
 pub use notification::Notification;
+pub use owned::Owned;
+pub use receive::{CapReceiveError, CapReceiver};
+pub use ring::{RingConsumer, RingError, RingProducer, ring_bytes, ring_init, ring_pages};
+pub use spawn::{JoinHandle, SpawnError, ThreadBuilder, ThreadMemory};
+pub use sync::{Condvar, Mutex, MutexGuard, Semaphore, SemaphoreGuard};
 pub use thread::{Thread, ThreadConfiguration};
+pub use untyped::{Untyped, UntypedDescriptor};
+
+/// Setting up and inspecting the simulated kernel used when the `mock` feature is enabled.
+#[cfg(feature = "mock")]
+pub use sel4_sys::kernel as mock;
 
 
 // TODO: This should be a configuration option pulled from sel4 kernel config
@@ -60,12 +114,17 @@ pub trait ToCap {
     fn to_cap(&self) -> seL4_CPtr;
 }
 
-pub trait Allocatable {
+pub trait FromCap {
+    /// Wrap a raw capability pointer into this object.
+    fn from_cap(cptr: seL4_CPtr) -> Self;
+}
+
+pub trait Allocatable: ToCap + FromCap {
     /// Allocate an object, using memory from the untyped memory object and storing the capability
     /// into `Window`.
     ///
     /// The number of objects to create is the `num_slots` field on the `Window`.
-    fn create(untyped_memory: seL4_CPtr, dest: Window, size_bits: seL4_Word) -> Result;
+    fn create(untyped_memory: Untyped, dest: Window, size_bits: seL4_Word) -> Result;
     fn object_size(size_bits: seL4_Word) -> isize;
 }
 
@@ -89,7 +148,9 @@ pub enum GoOn {
     CheckIPCBuf,
     TooMuchData,
     TooManyCaps,
-//  WouldBlock,
+    Malformed(DecodeError),
+    Rejected(DecodeError),
+    WouldBlock,
 }
 
 impl core::fmt::Debug for Error {
@@ -103,6 +164,9 @@ impl core::fmt::Debug for Error {
             }
             GoOn::TooMuchData => f.write_str("TooMuchData"),
             GoOn::TooManyCaps => f.write_str("TooManyCaps"),
+            GoOn::Malformed(err) => write!(f, "Malformed({:?})", err),
+            GoOn::Rejected(err) => write!(f, "Rejected({:?})", err),
+            GoOn::WouldBlock => f.write_str("WouldBlock"),
         }
     }
 }
@@ -33,6 +33,20 @@ macro_rules! unsafe_as_result {
     }
 }
 
+/// Frames can be mapped readable and writable; grant has no effect.
+macro_rules! frame_rights {
+    ($($name:ident),*) => {
+        $(
+            impl ::ObjectRights for $name {
+                #[inline(always)]
+                fn meaningful_rights() -> ::CapRights {
+                    ::CapRights::RW
+                }
+            }
+        )*
+    }
+}
+
 macro_rules! cap_wrapper {
     (()) => {};
     (($($attrs:tt)*) #[$meta:meta] $($tail:tt)*) => {
@@ -52,6 +66,30 @@ macro_rules! cap_wrapper {
             }
         }
 
+        impl ::FromCap for $name {
+            #[inline(always)]
+            fn from_cap(cptr: ::sel4_sys::seL4_CPtr) -> Self {
+                $name { cptr: cptr }
+            }
+        }
+
+        impl ::IpcEncode for $name {
+            const WORDS: usize = 0;
+            const CAPS: usize = 1;
+
+            #[inline(always)]
+            fn encode(&self, msg: &mut ::MessageBuilder) -> ::Result {
+                msg.push_cap(self.cptr).map(|_| ())
+            }
+        }
+
+        impl ::IpcDecode for $name {
+            #[inline(always)]
+            fn decode(msg: &mut ::MessageReader) -> ::DecodeResult<Self> {
+                msg.read_cap().ok_or(::DecodeError::MissingCap)
+            }
+        }
+
         impl $name {
             #[inline(always)]
             pub const fn from_cap(cptr: ::sel4_sys::seL4_CPtr) -> Self {
@@ -61,42 +99,10 @@ macro_rules! cap_wrapper {
 
         $(
             impl ::Allocatable for $name {
-                fn create(untyped_memory: ::sel4_sys::seL4_CPtr, mut dest: ::cspace::Window,
+                fn create(untyped_memory: ::Untyped, dest: ::cspace::Window,
                           size_bits: ::sel4_sys::seL4_Word) -> ::Result
                 {
-                    use ::ToCap;
-                    use ::CONFIG_RETYPE_FAN_OUT_LIMIT;
-
-                    // Most we can create in one syscall is CONFIG_RETYPE_FAN_OUT_LIMIT (256)
-                    while dest.num_slots > CONFIG_RETYPE_FAN_OUT_LIMIT {
-                        unsafe_as_result!(::sel4_sys::seL4_Untyped_Retype(
-                            untyped_memory,
-                            $objtag as seL4_Word,
-                            size_bits,
-                            dest.cnode.root.to_cap(),
-                            dest.cnode.cptr,
-                            dest.cnode.depth as seL4_Word,
-                            dest.first_slot_idx,
-                            CONFIG_RETYPE_FAN_OUT_LIMIT,
-                        ))?;
-                        dest.first_slot_idx += CONFIG_RETYPE_FAN_OUT_LIMIT;
-                        dest.num_slots -= CONFIG_RETYPE_FAN_OUT_LIMIT;
-                    }
-
-                    if dest.num_slots > 0 {
-                        unsafe_as_result!(::sel4_sys::seL4_Untyped_Retype(
-                            untyped_memory,
-                            $objtag as seL4_Word,
-                            size_bits,
-                            dest.cnode.root.to_cap(),
-                            dest.cnode.cptr,
-                            dest.cnode.depth as seL4_Word,
-                            dest.first_slot_idx,
-                            dest.num_slots,
-                        ))?;
-                    }
-
-                    Ok(())
+                    untyped_memory.retype_raw($objtag as seL4_Word, size_bits, dest)
                 }
 
                 fn object_size(size_bits: seL4_Word) -> isize {
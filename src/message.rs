@@ -0,0 +1,275 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Building and reading messages in place in the IPC buffer
+//!
+//! `Endpoint::send_message` and `RecvToken::get_data` copy whole slices in and out of the IPC
+//! buffer. `MessageBuilder` instead writes each message register as it is pushed, and
+//! `MessageReader` reads them back out one at a time, checking that the sender actually
+//! transferred as much as is being read.
+//!
+//! Both work directly on the current thread's IPC buffer. Any system call made between building a
+//! message and sending it, or between receiving one and reading it, may overwrite its contents.
+
+use core::mem;
+
+use sel4_sys::*;
+
+use {FromCap, RecvToken, ToCap};
+
+#[inline(always)]
+fn word_bytes() -> usize {
+    mem::size_of::<seL4_Word>()
+}
+
+/// Number of message registers needed to hold `len` bytes.
+#[inline(always)]
+pub fn words_for_bytes(len: usize) -> usize {
+    (len + word_bytes() - 1) / word_bytes()
+}
+
+/// A message being written into the IPC buffer.
+///
+/// Send it with `Endpoint::send_built` or `Endpoint::try_send_built`.
+#[derive(Debug)]
+pub struct MessageBuilder {
+    label: seL4_Word,
+    len: usize,
+    caps: usize,
+}
+
+impl MessageBuilder {
+    /// Start an empty message with the given label.
+    ///
+    /// The label is not interpreted by the kernel, and is usually used to say which operation a
+    /// message is requesting.
+    #[inline(always)]
+    pub fn new(label: seL4_Word) -> MessageBuilder {
+        MessageBuilder {
+            label: label,
+            len: 0,
+            caps: 0,
+        }
+    }
+
+    /// Change the label of the message.
+    #[inline(always)]
+    pub fn set_label(&mut self, label: seL4_Word) -> &mut MessageBuilder {
+        self.label = label;
+        self
+    }
+
+    /// Append a word to the message.
+    ///
+    /// Fails with `TooMuchData` if all `seL4_MsgMaxLength` message registers are in use.
+    #[inline(always)]
+    pub fn push_word(&mut self, word: seL4_Word) -> Result<&mut MessageBuilder, ::Error> {
+        if self.len >= seL4_MsgMaxLength {
+            return Err(::Error(::GoOn::TooMuchData));
+        }
+        unsafe {
+            (*seL4_GetIPCBuffer()).msg[self.len] = word;
+        }
+        self.len += 1;
+        Ok(self)
+    }
+
+    /// Append several words to the message.
+    ///
+    /// If they do not all fit, nothing is appended and `TooMuchData` is returned.
+    #[inline(always)]
+    pub fn push_words(&mut self, words: &[seL4_Word]) -> Result<&mut MessageBuilder, ::Error> {
+        if words.len() > seL4_MsgMaxLength - self.len {
+            return Err(::Error(::GoOn::TooMuchData));
+        }
+        unsafe {
+            let buf = seL4_GetIPCBuffer();
+            (&mut (*buf).msg)[self.len..self.len + words.len()].copy_from_slice(words);
+        }
+        self.len += words.len();
+        Ok(self)
+    }
+
+    /// Append bytes to the message, packed into words in native byte order.
+    ///
+    /// The last word is padded with zeroes. The length is not recorded, so the receiver must know
+    /// it in advance or be sent it separately.
+    pub fn push_bytes(&mut self, bytes: &[u8]) -> Result<&mut MessageBuilder, ::Error> {
+        if words_for_bytes(bytes.len()) > seL4_MsgMaxLength - self.len {
+            return Err(::Error(::GoOn::TooMuchData));
+        }
+        for chunk in bytes.chunks(word_bytes()) {
+            let mut word: seL4_Word = 0;
+            unsafe {
+                ::core::ptr::copy_nonoverlapping(chunk.as_ptr(),
+                                                 &mut word as *mut seL4_Word as *mut u8,
+                                                 chunk.len());
+            }
+            self.push_word(word)?;
+        }
+        Ok(self)
+    }
+
+    /// Append a capability to be transferred with the message.
+    ///
+    /// Fails with `TooManyCaps` if `seL4_MsgMaxExtraCaps` capabilities have already been added.
+    #[inline(always)]
+    pub fn push_cap<T: ToCap>(&mut self, cap: T) -> Result<&mut MessageBuilder, ::Error> {
+        if self.caps >= seL4_MsgMaxExtraCaps {
+            return Err(::Error(::GoOn::TooManyCaps));
+        }
+        unsafe {
+            (*seL4_GetIPCBuffer()).caps_or_badges[self.caps] = cap.to_cap();
+        }
+        self.caps += 1;
+        Ok(self)
+    }
+
+    /// The label of the message.
+    #[inline(always)]
+    pub fn label(&self) -> seL4_Word {
+        self.label
+    }
+
+    /// Number of message registers written so far.
+    #[inline(always)]
+    pub fn words(&self) -> usize {
+        self.len
+    }
+
+    /// Number of capabilities added so far.
+    #[inline(always)]
+    pub fn caps(&self) -> usize {
+        self.caps
+    }
+
+    /// The message info describing this message, to pass to the kernel.
+    #[inline(always)]
+    pub fn info(&self) -> seL4_MessageInfo {
+        seL4_MessageInfo::new(self.label, 0, self.caps, self.len)
+    }
+}
+
+/// Reads a received message out of the IPC buffer, front to back.
+///
+/// Get one with `RecvToken::reader`.
+#[derive(Debug)]
+pub struct MessageReader {
+    label: seL4_Word,
+    pos: usize,
+    len: usize,
+    extra_caps: usize,
+    caps_unwrapped: seL4_Word,
+    next_cap: usize,
+}
+
+impl MessageReader {
+    /// Start reading the message described by `token`.
+    #[inline(always)]
+    pub fn new(token: &RecvToken) -> MessageReader {
+        MessageReader {
+            label: token.label,
+            pos: 0,
+            len: token.words_transferred(),
+            extra_caps: token.extra_caps(),
+            caps_unwrapped: token.caps_unwrapped(),
+            next_cap: 0,
+        }
+    }
+
+    /// The label the message was sent with.
+    #[inline(always)]
+    pub fn label(&self) -> seL4_Word {
+        self.label
+    }
+
+    /// Number of words which have not been read yet.
+    #[inline(always)]
+    pub fn remaining(&self) -> usize {
+        self.len - self.pos
+    }
+
+    /// Read the next word, or `None` if the whole message has been read.
+    #[inline(always)]
+    pub fn read_word(&mut self) -> Option<seL4_Word> {
+        if self.pos >= self.len {
+            return None;
+        }
+        let word = unsafe { (*seL4_GetIPCBuffer()).msg[self.pos] };
+        self.pos += 1;
+        Some(word)
+    }
+
+    /// Fill `words` from the message.
+    ///
+    /// Returns `Err` without reading anything if fewer than `words.len()` words remain.
+    #[inline(always)]
+    pub fn read_words(&mut self, words: &mut [seL4_Word]) -> Result<(), ()> {
+        if words.len() > self.remaining() {
+            return Err(());
+        }
+        unsafe {
+            let buf = seL4_GetIPCBuffer();
+            words.copy_from_slice(&(&(*buf).msg)[self.pos..self.pos + words.len()]);
+        }
+        self.pos += words.len();
+        Ok(())
+    }
+
+    /// Fill `bytes` from the message, reading words packed by `MessageBuilder::push_bytes`.
+    ///
+    /// Returns `Err` without reading anything if the message is too short.
+    pub fn read_bytes(&mut self, bytes: &mut [u8]) -> Result<(), ()> {
+        if words_for_bytes(bytes.len()) > self.remaining() {
+            return Err(());
+        }
+        for chunk in bytes.chunks_mut(word_bytes()) {
+            let word = self.read_word().unwrap();
+            unsafe {
+                ::core::ptr::copy_nonoverlapping(&word as *const seL4_Word as *const u8,
+                                                 chunk.as_mut_ptr(),
+                                                 chunk.len());
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of capabilities sent along with the message.
+    #[inline(always)]
+    pub fn extra_caps(&self) -> usize {
+        self.extra_caps
+    }
+
+    /// Take the next capability sent with the message, which the kernel stored in the receive slot.
+    ///
+    /// Returns `None` if there are no more capabilities, or if the next one was unwrapped instead
+    /// (see `badge`). The capability uses the receive slot's CPtr directly, so that slot must be
+    /// addressed at full depth from the root of our CSpace.
+    pub fn read_cap<T: FromCap>(&mut self) -> Option<T> {
+        let i = self.next_cap;
+        if i >= self.extra_caps || self.caps_unwrapped & (1 << i) != 0 {
+            return None;
+        }
+        self.next_cap += 1;
+        Some(T::from_cap(::get_cap_destination().cptr))
+    }
+
+    /// The badge of the `i`th capability sent with the message.
+    ///
+    /// This is `None` unless the kernel unwrapped that capability, which it does when it refers to
+    /// the endpoint the message was received on. Only the badge is delivered in that case, and
+    /// nothing is stored in the receive slot.
+    #[inline(always)]
+    pub fn badge(&self, i: usize) -> Option<seL4_Word> {
+        if i >= self.extra_caps || self.caps_unwrapped & (1 << i) == 0 {
+            return None;
+        }
+        Some(unsafe { (*seL4_GetIPCBuffer()).caps_or_badges[i] })
+    }
+}
@@ -0,0 +1,1150 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! State of the simulated kernel, and functions for tests to set it up and inspect it.
+//!
+//! Every thread gets its own kernel and IPC buffer, so tests running in parallel do not interfere
+//! with each other. Call `boot` at the start of each test.
+
+use std::cell::{RefCell, UnsafeCell};
+use std::collections::{HashMap, VecDeque};
+use std::mem;
+
+use raw_sel4_sys::*;
+
+use UserContext;
+
+fn word_bits() -> usize {
+    mem::size_of::<seL4_Word>() * 8
+}
+
+/// Number of words in `seL4_UserContext`.
+#[doc(hidden)]
+pub fn context_words() -> usize {
+    mem::size_of::<seL4_UserContext>() / mem::size_of::<seL4_Word>()
+}
+
+/// Number of frame registers, which come before the others in `seL4_UserContext`.
+///
+/// Only the TLS base, which is last, is not one.
+#[doc(hidden)]
+pub fn frame_words() -> usize {
+    context_words() - 1
+}
+
+fn mask(bits: usize) -> seL4_Word {
+    if bits >= word_bits() {
+        !0
+    } else {
+        ((1 as seL4_Word) << bits) - 1
+    }
+}
+
+fn shr(val: seL4_Word, bits: usize) -> seL4_Word {
+    val.checked_shr(bits as u32).unwrap_or(0)
+}
+
+/// The kinds of object the simulated kernel knows about.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ObjectKind {
+    Untyped,
+    CNode,
+    Endpoint,
+    Notification,
+    Thread,
+    Frame,
+    /// A reply capability saved with `seL4_CNode_SaveCaller`.
+    Reply,
+    /// Any other object, which can be created and moved around but not invoked.
+    Other,
+}
+
+enum ObjectData {
+    Untyped {
+        size_bits: u8,
+        paddr: seL4_Word,
+        watermark: seL4_Word,
+    },
+    CNode {
+        radix_bits: u8,
+        slots: Vec<Option<Cap>>,
+    },
+    Endpoint {
+        queue: VecDeque<Message>,
+    },
+    Notification {
+        word: Option<seL4_Word>,
+    },
+    Reply {
+        call: usize,
+    },
+    Thread(ThreadState),
+    Plain(ObjectKind),
+}
+
+/// What a simulated thread has been set up with.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ThreadState {
+    /// The fault endpoint given with `seL4_TCB_SetSpace`.
+    pub fault_endpoint: seL4_CPtr,
+    /// The CSpace root given with `seL4_TCB_SetSpace`.
+    pub cspace_root: seL4_CPtr,
+    /// The VSpace root given with `seL4_TCB_SetSpace`.
+    pub vspace_root: seL4_CPtr,
+    /// The address of the IPC buffer.
+    pub ipc_buffer: seL4_Word,
+    /// The frame backing the IPC buffer.
+    pub ipc_buffer_frame: seL4_CPtr,
+    pub priority: u8,
+    /// The words of the `seL4_UserContext` last written.
+    pub registers: Vec<seL4_Word>,
+    /// Whether the thread has been resumed, and not suspended since.
+    pub running: bool,
+}
+
+/// A capability stored in a simulated CNode slot.
+#[doc(hidden)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Cap {
+    id: u64,
+    object: usize,
+    badge: seL4_Word,
+    read: bool,
+    write: bool,
+    grant: bool,
+    guard_val: seL4_Word,
+    guard_bits: u8,
+}
+
+/// A message sent to a simulated endpoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    /// Badge of the capability the message was sent through.
+    pub badge: seL4_Word,
+    /// The message label.
+    pub label: seL4_Word,
+    /// Contents of the message registers.
+    pub data: Vec<seL4_Word>,
+    caps: Vec<Cap>,
+    /// The call waiting for a reply to this message, if it was sent with `queue_call`.
+    call: Option<usize>,
+}
+
+impl Message {
+    /// Create a message with no capabilities, for replying from a call handler.
+    pub fn new(label: seL4_Word, data: &[seL4_Word]) -> Message {
+        Message {
+            badge: 0,
+            label: label,
+            data: data.to_vec(),
+            caps: Vec::new(),
+            call: None,
+        }
+    }
+
+    /// Number of capabilities sent along with the message.
+    pub fn caps(&self) -> usize {
+        self.caps.len()
+    }
+}
+
+/// Ways capability lookup can fail, mirroring `seL4_LookupFailureType`.
+#[doc(hidden)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Lookup {
+    InvalidRoot,
+    MissingCapability(seL4_Word),
+    DepthMismatch(seL4_Word, seL4_Word),
+    GuardMismatch(seL4_Word, seL4_Word, seL4_Word),
+}
+
+/// Errors a simulated invocation can report, mirroring `seL4_Error`.
+#[doc(hidden)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Fault {
+    InvalidArgument(seL4_Word),
+    InvalidCapability(seL4_Word),
+    IllegalOperation,
+    RangeError(seL4_Word, seL4_Word),
+    FailedLookup(bool, Lookup),
+    DeleteFirst,
+    RevokeFirst,
+    NotEnoughMemory(seL4_Word),
+}
+
+impl Fault {
+    /// Write this error into the IPC buffer the way the kernel does, returning the error code.
+    pub fn report(self) -> isize {
+        use self::Fault::*;
+
+        let mut regs: Vec<seL4_Word> = Vec::new();
+        let code = match self {
+            InvalidArgument(which) => {
+                regs.push(which);
+                seL4_InvalidArgument
+            }
+            InvalidCapability(which) => {
+                regs.push(which);
+                seL4_InvalidCapability
+            }
+            IllegalOperation => seL4_IllegalOperation,
+            RangeError(min, max) => {
+                regs.push(min);
+                regs.push(max);
+                seL4_RangeError
+            }
+            FailedLookup(source, lookup) => {
+                regs.push(source as seL4_Word);
+                match lookup {
+                    Lookup::InvalidRoot => regs.push(seL4_InvalidRoot as seL4_Word),
+                    Lookup::MissingCapability(remaining) => {
+                        regs.push(seL4_MissingCapability as seL4_Word);
+                        regs.push(remaining);
+                    }
+                    Lookup::DepthMismatch(remaining, resolved) => {
+                        regs.push(seL4_DepthMismatch as seL4_Word);
+                        regs.push(remaining);
+                        regs.push(resolved);
+                    }
+                    Lookup::GuardMismatch(remaining, guard, size) => {
+                        regs.push(seL4_GuardMismatch as seL4_Word);
+                        regs.push(remaining);
+                        regs.push(guard);
+                        regs.push(size);
+                    }
+                }
+                seL4_FailedLookup
+            }
+            DeleteFirst => seL4_DeleteFirst,
+            RevokeFirst => seL4_RevokeFirst,
+            NotEnoughMemory(available) => {
+                regs.push(available);
+                seL4_NotEnoughMemory
+            }
+        } as seL4_Word;
+
+        let buf = unsafe { &mut *ipc_buffer() };
+        buf.msg[..regs.len()].copy_from_slice(&regs);
+        buf.tag = seL4_MessageInfo::new(code as _, 0, 0, regs.len() as _);
+        code as isize
+    }
+}
+
+/// Convert the result of a simulated invocation into the kernel's return value.
+#[doc(hidden)]
+pub fn status(res: Result<(), Fault>) -> isize {
+    match res {
+        Ok(()) => {
+            unsafe {
+                (*ipc_buffer()).tag = seL4_MessageInfo::new(0, 0, 0, 0);
+            }
+            0
+        }
+        Err(fault) => fault.report(),
+    }
+}
+
+/// The state of a simulated kernel.
+#[doc(hidden)]
+pub struct Kernel {
+    objects: Vec<ObjectData>,
+    /// The current thread's CSpace root.
+    root: Cap,
+    next_cap_id: u64,
+    /// Parent of every capability ever created, for revocation.
+    parents: HashMap<u64, u64>,
+    /// Call handlers, by endpoint object.
+    handlers: HashMap<usize, Box<dyn FnMut(Message) -> Message>>,
+    /// Handlers run instead of blocking, by endpoint or notification object.
+    blockers: HashMap<usize, Box<dyn FnMut()>>,
+    /// The call the current thread's implicit reply capability answers.
+    caller: Option<usize>,
+    next_call: usize,
+    /// Replies to queued calls which have not been collected yet.
+    replies: HashMap<usize, Message>,
+    debug_output: Vec<u8>,
+}
+
+thread_local! {
+    static KERNEL: RefCell<Option<Kernel>> = RefCell::new(None);
+    static IPC_BUFFER: UnsafeCell<seL4_IPCBuffer> = UnsafeCell::new(unsafe { mem::zeroed() });
+}
+
+/// The current thread's simulated IPC buffer.
+#[doc(hidden)]
+pub fn ipc_buffer() -> *mut seL4_IPCBuffer {
+    IPC_BUFFER.with(|buf| buf.get())
+}
+
+/// Run `f` with the current thread's simulated kernel.
+#[doc(hidden)]
+pub fn with_kernel<R, F: FnOnce(&mut Kernel) -> R>(f: F) -> R {
+    KERNEL.with(|k| {
+        let mut k = k.borrow_mut();
+        f(k.as_mut().expect("mock kernel not booted; call sel4::mock::boot() first"))
+    })
+}
+
+/// Size in bytes of an object created by retyping with `objtype` and `size_bits`.
+fn object_size(objtype: seL4_Word, size_bits: seL4_Word) -> Option<(ObjectKind, seL4_Word)> {
+    let t = objtype;
+    if t == seL4_UntypedObject as seL4_Word {
+        Some((ObjectKind::Untyped, (1 as seL4_Word) << size_bits))
+    } else if t == seL4_TCBObject as seL4_Word {
+        Some((ObjectKind::Thread, 1 << 11))
+    } else if t == seL4_EndpointObject as seL4_Word {
+        Some((ObjectKind::Endpoint, 16))
+    } else if t == seL4_NotificationObject as seL4_Word {
+        Some((ObjectKind::Notification, 16))
+    } else if t == seL4_CapTableObject as seL4_Word {
+        Some((ObjectKind::CNode, (1 as seL4_Word) << (size_bits + seL4_SlotBits as seL4_Word)))
+    } else if t == seL4_X86_4K as seL4_Word {
+        Some((ObjectKind::Frame, 1 << 12))
+    } else if t == seL4_X86_LargePageObject as seL4_Word {
+        Some((ObjectKind::Frame, 1 << 21))
+    } else {
+        None
+    }
+}
+
+impl Kernel {
+    fn new(radix_bits: u8) -> Kernel {
+        let mut k = Kernel {
+            objects: Vec::new(),
+            root: Cap {
+                id: 0,
+                object: 0,
+                badge: 0,
+                read: true,
+                write: true,
+                grant: true,
+                guard_val: 0,
+                guard_bits: 0,
+            },
+            next_cap_id: 1,
+            parents: HashMap::new(),
+            handlers: HashMap::new(),
+            blockers: HashMap::new(),
+            caller: None,
+            next_call: 1,
+            replies: HashMap::new(),
+            debug_output: Vec::new(),
+        };
+
+        let root = k.new_object(ObjectData::CNode {
+            radix_bits: radix_bits,
+            slots: vec![None; 1 << radix_bits],
+        });
+        let mut cap = k.new_cap(root, None);
+        cap.guard_bits = (word_bits() - radix_bits as usize) as u8;
+        k.root = cap;
+        k
+    }
+
+    fn new_object(&mut self, data: ObjectData) -> usize {
+        self.objects.push(data);
+        self.objects.len() - 1
+    }
+
+    fn new_cap(&mut self, object: usize, parent: Option<u64>) -> Cap {
+        let id = self.next_cap_id;
+        self.next_cap_id += 1;
+        if let Some(parent) = parent {
+            self.parents.insert(id, parent);
+        }
+        Cap {
+            id: id,
+            object: object,
+            badge: 0,
+            read: true,
+            write: true,
+            grant: true,
+            guard_val: 0,
+            guard_bits: 0,
+        }
+    }
+
+    /// Derive a new capability from `cap`, as a child in the derivation tree.
+    fn derive(&mut self, cap: Cap) -> Cap {
+        let mut new = self.new_cap(cap.object, Some(cap.id));
+        new.badge = cap.badge;
+        new.read = cap.read;
+        new.write = cap.write;
+        new.grant = cap.grant;
+        new.guard_val = cap.guard_val;
+        new.guard_bits = cap.guard_bits;
+        new
+    }
+
+    fn kind(&self, object: usize) -> ObjectKind {
+        match self.objects[object] {
+            ObjectData::Untyped { .. } => ObjectKind::Untyped,
+            ObjectData::CNode { .. } => ObjectKind::CNode,
+            ObjectData::Endpoint { .. } => ObjectKind::Endpoint,
+            ObjectData::Notification { .. } => ObjectKind::Notification,
+            ObjectData::Reply { .. } => ObjectKind::Reply,
+            ObjectData::Thread(_) => ObjectKind::Thread,
+            ObjectData::Plain(kind) => kind,
+        }
+    }
+
+    fn slot(&self, addr: (usize, usize)) -> Option<Cap> {
+        match self.objects[addr.0] {
+            ObjectData::CNode { ref slots, .. } => slots[addr.1],
+            _ => None,
+        }
+    }
+
+    fn set_slot(&mut self, addr: (usize, usize), cap: Option<Cap>) {
+        if let ObjectData::CNode { ref mut slots, .. } = self.objects[addr.0] {
+            slots[addr.1] = cap;
+        }
+    }
+
+    /// Every occupied slot in every CNode.
+    fn all_slots(&self) -> Vec<((usize, usize), Cap)> {
+        let mut all = Vec::new();
+        for (obj, data) in self.objects.iter().enumerate() {
+            if let ObjectData::CNode { ref slots, .. } = *data {
+                for (idx, slot) in slots.iter().enumerate() {
+                    if let Some(cap) = *slot {
+                        all.push(((obj, idx), cap));
+                    }
+                }
+            }
+        }
+        all
+    }
+
+    fn is_descendant(&self, mut id: u64, ancestor: u64) -> bool {
+        while let Some(&parent) = self.parents.get(&id) {
+            if parent == ancestor {
+                return true;
+            }
+            id = parent;
+        }
+        false
+    }
+
+    fn has_children(&self, cap: Cap) -> bool {
+        self.all_slots().iter().any(|&(_, c)| self.is_descendant(c.id, cap.id))
+    }
+
+    /// Walk the CSpace starting at `root`, following the kernel's guard and radix rules.
+    ///
+    /// If `partial` is set, resolution may stop early at a slot which does not hold a CNode, as
+    /// is done when looking up a capability to invoke.
+    fn resolve(&self, root: Cap, cptr: seL4_Word, depth: usize, partial: bool)
+               -> Result<(usize, usize), Lookup> {
+        let mut cap = root;
+        let mut bits = depth;
+        loop {
+            let radix = match self.objects[cap.object] {
+                ObjectData::CNode { radix_bits, .. } => radix_bits as usize,
+                _ => return Err(Lookup::InvalidRoot),
+            };
+            let guard_bits = cap.guard_bits as usize;
+            let level = radix + guard_bits;
+            if level > bits {
+                return Err(Lookup::DepthMismatch(bits as seL4_Word, level as seL4_Word));
+            }
+
+            let guard = shr(cptr, bits - guard_bits) & mask(guard_bits);
+            if guard != cap.guard_val {
+                return Err(Lookup::GuardMismatch(bits as seL4_Word, cap.guard_val,
+                                                  guard_bits as seL4_Word));
+            }
+
+            let idx = (shr(cptr, bits - level) & mask(radix)) as usize;
+            bits -= level;
+            if bits == 0 {
+                return Ok((cap.object, idx));
+            }
+
+            match self.slot((cap.object, idx)) {
+                Some(next) if self.kind(next.object) == ObjectKind::CNode => cap = next,
+                Some(_) if partial => return Ok((cap.object, idx)),
+                Some(_) => {
+                    return Err(Lookup::DepthMismatch(bits as seL4_Word, level as seL4_Word))
+                }
+                None => return Err(Lookup::MissingCapability(bits as seL4_Word)),
+            }
+        }
+    }
+
+    /// Look up the capability `cptr` in the current thread's CSpace, for invocation.
+    fn lookup_cap(&self, cptr: seL4_CPtr) -> Result<Cap, Fault> {
+        self.lookup_addr(cptr).map(|(_, cap)| cap)
+    }
+
+    /// Like `lookup_cap`, also returning the slot the capability is in.
+    fn lookup_addr(&self, cptr: seL4_CPtr) -> Result<((usize, usize), Cap), Fault> {
+        let root = self.root;
+        let addr = self.resolve(root, cptr, word_bits(), true).ok();
+        match addr.and_then(|a| self.slot(a).map(|cap| (a, cap))) {
+            Some(found) => Ok(found),
+            None => Err(Fault::InvalidCapability(0)),
+        }
+    }
+
+    /// Look up a slot given a CNode to start from, an index, and a depth.
+    fn lookup_slot(&self, root: seL4_CPtr, index: seL4_Word, depth: usize, source: bool)
+                   -> Result<(usize, usize), Fault> {
+        let root = match self.lookup_cap(root) {
+            Ok(cap) => cap,
+            Err(_) => return Err(Fault::FailedLookup(source, Lookup::InvalidRoot)),
+        };
+        if depth == 0 || depth > word_bits() {
+            return Err(Fault::RangeError(1, word_bits() as seL4_Word));
+        }
+        self.resolve(root, index, depth, false).map_err(|l| Fault::FailedLookup(source, l))
+    }
+
+    fn full_slot(&self, addr: (usize, usize), source: bool) -> Result<Cap, Fault> {
+        match self.slot(addr) {
+            Some(cap) => Ok(cap),
+            None => Err(Fault::FailedLookup(source, Lookup::MissingCapability(0))),
+        }
+    }
+
+    fn empty_slot(&self, addr: (usize, usize)) -> Result<(), Fault> {
+        match self.slot(addr) {
+            Some(_) => Err(Fault::DeleteFirst),
+            None => Ok(()),
+        }
+    }
+
+    fn apply_data(&self, cap: &mut Cap, data: seL4_CapData) {
+        match self.kind(cap.object) {
+            ObjectKind::CNode => {
+                cap.guard_val = data.get_GuardBits() as seL4_Word;
+                cap.guard_bits = data.get_GuardSize() as u8;
+            }
+            ObjectKind::Endpoint | ObjectKind::Notification => {
+                if cap.badge == 0 {
+                    cap.badge = data.get_Badge() as seL4_Word;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn apply_rights(&self, cap: &mut Cap, rights: seL4_CapRights) {
+        cap.read = cap.read && rights.get_capAllowRead() != 0;
+        cap.write = cap.write && rights.get_capAllowWrite() != 0;
+        cap.grant = cap.grant && rights.get_capAllowGrant() != 0;
+    }
+
+    #[doc(hidden)]
+    pub fn copy(&mut self, dest: (seL4_CPtr, seL4_Word, u8), src: (seL4_CPtr, seL4_Word, u8),
+                rights: Option<seL4_CapRights>, data: Option<seL4_CapData>)
+                -> Result<(), Fault> {
+        let dest_addr = self.lookup_slot(dest.0, dest.1, dest.2 as usize, false)?;
+        self.empty_slot(dest_addr)?;
+        let src_addr = self.lookup_slot(src.0, src.1, src.2 as usize, true)?;
+        let src_cap = self.full_slot(src_addr, true)?;
+
+        let mut cap = self.derive(src_cap);
+        if let Some(rights) = rights {
+            self.apply_rights(&mut cap, rights);
+        }
+        if let Some(data) = data {
+            self.apply_data(&mut cap, data);
+        }
+        self.set_slot(dest_addr, Some(cap));
+        Ok(())
+    }
+
+    #[doc(hidden)]
+    pub fn move_(&mut self, dest: (seL4_CPtr, seL4_Word, u8), src: (seL4_CPtr, seL4_Word, u8),
+                 data: Option<seL4_CapData>)
+                 -> Result<(), Fault> {
+        let dest_addr = self.lookup_slot(dest.0, dest.1, dest.2 as usize, false)?;
+        self.empty_slot(dest_addr)?;
+        let src_addr = self.lookup_slot(src.0, src.1, src.2 as usize, true)?;
+        let mut cap = self.full_slot(src_addr, true)?;
+
+        if let Some(data) = data {
+            self.apply_data(&mut cap, data);
+        }
+        self.set_slot(src_addr, None);
+        self.set_slot(dest_addr, Some(cap));
+        Ok(())
+    }
+
+    #[doc(hidden)]
+    pub fn delete(&mut self, slot: (seL4_CPtr, seL4_Word, u8)) -> Result<(), Fault> {
+        let addr = self.lookup_slot(slot.0, slot.1, slot.2 as usize, true)?;
+        self.set_slot(addr, None);
+        Ok(())
+    }
+
+    #[doc(hidden)]
+    pub fn revoke(&mut self, slot: (seL4_CPtr, seL4_Word, u8)) -> Result<(), Fault> {
+        let addr = self.lookup_slot(slot.0, slot.1, slot.2 as usize, true)?;
+        let cap = match self.slot(addr) {
+            Some(cap) => cap,
+            None => return Ok(()),
+        };
+
+        for (child_addr, child) in self.all_slots() {
+            if self.is_descendant(child.id, cap.id) {
+                self.set_slot(child_addr, None);
+            }
+        }
+        if let ObjectData::Untyped { ref mut watermark, .. } = self.objects[cap.object] {
+            *watermark = 0;
+        }
+        Ok(())
+    }
+
+    #[doc(hidden)]
+    pub fn cancel_badged_sends(&mut self, slot: (seL4_CPtr, seL4_Word, u8))
+                               -> Result<(), Fault> {
+        let addr = self.lookup_slot(slot.0, slot.1, slot.2 as usize, true)?;
+        let cap = self.full_slot(addr, true)?;
+        if let ObjectData::Endpoint { ref mut queue } = self.objects[cap.object] {
+            if cap.badge != 0 {
+                queue.retain(|m| m.badge != cap.badge);
+            }
+        }
+        Ok(())
+    }
+
+    #[doc(hidden)]
+    pub fn retype(&mut self, service: seL4_CPtr, objtype: seL4_Word, size_bits: seL4_Word,
+                  root: seL4_CPtr, node_index: seL4_Word, node_depth: seL4_Word,
+                  node_offset: seL4_Word, num_objects: seL4_Word)
+                  -> Result<(), Fault> {
+        let untyped = self.lookup_cap(service)?;
+        let (ut_bits, ut_paddr) = match self.objects[untyped.object] {
+            ObjectData::Untyped { size_bits, paddr, .. } => (size_bits, paddr),
+            _ => return Err(Fault::InvalidCapability(0)),
+        };
+        let (kind, size) = match object_size(objtype, size_bits) {
+            Some(obj) => obj,
+            None => return Err(Fault::InvalidArgument(1)),
+        };
+
+        let cnode = if node_depth == 0 {
+            self.lookup_cap(root).map_err(|_| Fault::FailedLookup(false, Lookup::InvalidRoot))?
+        } else {
+            let addr = self.lookup_slot(root, node_index, node_depth as usize, false)?;
+            self.full_slot(addr, false)?
+        };
+        let num_slots = match self.objects[cnode.object] {
+            ObjectData::CNode { ref slots, .. } => slots.len(),
+            _ => return Err(Fault::FailedLookup(false, Lookup::InvalidRoot)),
+        };
+        if node_offset + num_objects > num_slots {
+            return Err(Fault::RangeError(0, num_slots.saturating_sub(num_objects) as seL4_Word));
+        }
+        for i in node_offset..node_offset + num_objects {
+            self.empty_slot((cnode.object, i))?;
+        }
+
+        if !self.has_children(untyped) {
+            if let ObjectData::Untyped { ref mut watermark, .. } = self.objects[untyped.object] {
+                *watermark = 0;
+            }
+        }
+        let watermark = match self.objects[untyped.object] {
+            ObjectData::Untyped { watermark, .. } => watermark,
+            _ => unreachable!(),
+        };
+        let start = (watermark + size - 1) & !(size - 1);
+        let end = start + size * num_objects as seL4_Word;
+        let total = (1 as seL4_Word) << ut_bits;
+        if end > total {
+            return Err(Fault::NotEnoughMemory(total - watermark));
+        }
+
+        for i in 0..num_objects {
+            let data = match kind {
+                ObjectKind::Untyped => {
+                    ObjectData::Untyped {
+                        size_bits: size_bits as u8,
+                        paddr: ut_paddr + start + size * i as seL4_Word,
+                        watermark: 0,
+                    }
+                }
+                ObjectKind::CNode => {
+                    ObjectData::CNode {
+                        radix_bits: size_bits as u8,
+                        slots: vec![None; 1 << size_bits],
+                    }
+                }
+                ObjectKind::Endpoint => ObjectData::Endpoint { queue: VecDeque::new() },
+                ObjectKind::Notification => ObjectData::Notification { word: None },
+                ObjectKind::Thread => ObjectData::Thread(ThreadState::default()),
+                kind => ObjectData::Plain(kind),
+            };
+            let obj = self.new_object(data);
+            let cap = self.new_cap(obj, Some(untyped.id));
+            self.set_slot((cnode.object, node_offset + i), Some(cap));
+        }
+
+        if let ObjectData::Untyped { ref mut watermark, .. } = self.objects[untyped.object] {
+            *watermark = end;
+        }
+        Ok(())
+    }
+
+    /// Queue a message on an endpoint, or signal a notification.
+    #[doc(hidden)]
+    pub fn send(&mut self, dest: seL4_CPtr, label: seL4_Word, data: &[seL4_Word],
+                caps: &[seL4_CPtr], blocking: bool)
+                -> Result<(), Fault> {
+        let (addr, cap) = self.lookup_addr(dest)?;
+        if self.kind(cap.object) == ObjectKind::Notification {
+            return self.signal(dest);
+        }
+
+        let mut transferred = Vec::new();
+        if cap.grant {
+            for &cptr in caps {
+                if let Ok(c) = self.lookup_cap(cptr) {
+                    transferred.push(c);
+                }
+            }
+        }
+
+        // With a single thread there is never a receiver waiting, so a non-blocking send is
+        // always dropped.
+        if !blocking {
+            return Ok(());
+        }
+
+        let msg = Message {
+            badge: cap.badge,
+            label: label,
+            data: data.to_vec(),
+            caps: transferred,
+            call: None,
+        };
+        match self.objects[cap.object] {
+            ObjectData::Endpoint { ref mut queue } => {
+                queue.push_back(msg);
+                return Ok(());
+            }
+            ObjectData::Reply { call } => {
+                self.replies.insert(call, msg);
+            }
+            _ => return Err(Fault::InvalidCapability(0)),
+        }
+        // A reply capability can only be used once.
+        self.set_slot(addr, None);
+        Ok(())
+    }
+
+    /// Dequeue a message from an endpoint, or the word from a notification.
+    ///
+    /// Returns `None` if nothing is pending.
+    #[doc(hidden)]
+    pub fn recv(&mut self, src: seL4_CPtr) -> Result<Option<Message>, Fault> {
+        let cap = self.lookup_cap(src)?;
+        match self.objects[cap.object] {
+            ObjectData::Endpoint { ref mut queue } => {
+                let msg = queue.pop_front();
+                self.caller = msg.as_ref().and_then(|m| m.call);
+                Ok(msg)
+            }
+            ObjectData::Notification { ref mut word } => {
+                Ok(word.take().map(|w| {
+                    Message {
+                        badge: w,
+                        label: 0,
+                        data: Vec::new(),
+                        caps: Vec::new(),
+                        call: None,
+                    }
+                }))
+            }
+            _ => Err(Fault::InvalidCapability(0)),
+        }
+    }
+
+    /// Store the first capability of a received message into the receive slot, if possible.
+    ///
+    /// Returns the number of capabilities transferred.
+    #[doc(hidden)]
+    pub fn transfer_caps(&mut self, msg: &Message, dest: (seL4_CPtr, seL4_Word, u8)) -> usize {
+        let cap = match msg.caps.first() {
+            Some(&cap) => cap,
+            None => return 0,
+        };
+        let addr = match self.lookup_slot(dest.0, dest.1, dest.2 as usize, false) {
+            Ok(addr) => addr,
+            Err(_) => return 0,
+        };
+        if self.slot(addr).is_some() {
+            return 0;
+        }
+        let new = self.derive(cap);
+        self.set_slot(addr, Some(new));
+        1
+    }
+
+    #[doc(hidden)]
+    pub fn signal(&mut self, dest: seL4_CPtr) -> Result<(), Fault> {
+        let cap = self.lookup_cap(dest)?;
+        match self.objects[cap.object] {
+            ObjectData::Notification { ref mut word } => {
+                *word = Some(word.unwrap_or(0) | cap.badge);
+                Ok(())
+            }
+            _ => Err(Fault::InvalidCapability(0)),
+        }
+    }
+
+    /// The state of the thread `cptr` refers to.
+    #[doc(hidden)]
+    pub fn thread(&mut self, cptr: seL4_CPtr) -> Result<&mut ThreadState, Fault> {
+        let cap = self.lookup_cap(cptr)?;
+        match self.objects[cap.object] {
+            ObjectData::Thread(ref mut thread) => Ok(thread),
+            _ => Err(Fault::InvalidCapability(0)),
+        }
+    }
+
+    /// Answer the call the implicit reply capability refers to, if any.
+    #[doc(hidden)]
+    pub fn reply(&mut self, label: seL4_Word, data: &[seL4_Word], caps: &[seL4_CPtr]) {
+        if let Some(call) = self.caller.take() {
+            let mut transferred = Vec::new();
+            for &cptr in caps {
+                if let Ok(c) = self.lookup_cap(cptr) {
+                    transferred.push(c);
+                }
+            }
+            let mut msg = Message::new(label, data);
+            msg.caps = transferred;
+            self.replies.insert(call, msg);
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn save_caller(&mut self, slot: (seL4_CPtr, seL4_Word, u8)) -> Result<(), Fault> {
+        let addr = self.lookup_slot(slot.0, slot.1, slot.2 as usize, false)?;
+        self.empty_slot(addr)?;
+        if let Some(call) = self.caller.take() {
+            let obj = self.new_object(ObjectData::Reply { call: call });
+            let cap = self.new_cap(obj, None);
+            self.set_slot(addr, Some(cap));
+        }
+        Ok(())
+    }
+
+    #[doc(hidden)]
+    pub fn badge_of(&self, cptr: seL4_CPtr) -> Result<seL4_Word, Fault> {
+        self.lookup_cap(cptr).map(|cap| cap.badge)
+    }
+
+    #[doc(hidden)]
+    pub fn debug_put_char(&mut self, c: u8) {
+        self.debug_output.push(c);
+    }
+}
+
+/// Pass a message to the handler registered for the endpoint `dest`, returning its reply.
+///
+/// The kernel is not borrowed while the handler runs, so it may make system calls of its own.
+#[doc(hidden)]
+pub fn call(dest: seL4_CPtr, label: seL4_Word, data: &[seL4_Word], caps: &[seL4_CPtr])
+            -> Result<Message, Fault> {
+    let (msg, object, mut handler) = with_kernel(|k| -> Result<_, Fault> {
+        let cap = k.lookup_cap(dest)?;
+        if k.kind(cap.object) != ObjectKind::Endpoint {
+            return Err(Fault::InvalidCapability(0));
+        }
+        let mut transferred = Vec::new();
+        if cap.grant {
+            for &cptr in caps {
+                if let Ok(c) = k.lookup_cap(cptr) {
+                    transferred.push(c);
+                }
+            }
+        }
+        let handler = match k.handlers.remove(&cap.object) {
+            Some(handler) => handler,
+            None => panic!("mock: seL4_Call on an endpoint with no handler would block forever"),
+        };
+        let msg = Message {
+            badge: cap.badge,
+            label: label,
+            data: data.to_vec(),
+            caps: transferred,
+            call: None,
+        };
+        Ok((msg, cap.object, handler))
+    })?;
+
+    let reply = handler(msg);
+    with_kernel(|k| k.handlers.insert(object, handler));
+    Ok(reply)
+}
+
+/// Run the handler registered for `src` with `set_block_handler`, as the current thread is about
+/// to block receiving from it.
+///
+/// Returns whether there was a handler. The kernel is not borrowed while the handler runs.
+#[doc(hidden)]
+pub fn block(src: seL4_CPtr) -> Result<bool, Fault> {
+    let (object, mut handler) = match with_kernel(|k| -> Result<_, Fault> {
+        let object = k.lookup_cap(src)?.object;
+        Ok(k.blockers.remove(&object).map(|handler| (object, handler)))
+    })? {
+        Some(blocker) => blocker,
+        None => return Ok(false),
+    };
+    handler();
+    with_kernel(|k| {
+        k.blockers.entry(object).or_insert(handler);
+    });
+    Ok(true)
+}
+
+/// Start a fresh simulated kernel for the current thread.
+///
+/// The root CNode has 2^`radix_bits` slots, and one untyped object of 2^`bits` bytes is created
+/// for each entry of `untyped_bits`. The initial capabilities are placed in the standard slots
+/// (`seL4_CapInitThreadTCB` and so on), and the returned bootinfo describes the untyped and empty
+/// slots. The bootinfo is leaked so that it lives as long as the test.
+pub fn boot_with(radix_bits: u8, untyped_bits: &[u8]) -> *const seL4_BootInfo {
+    let mut k = Kernel::new(radix_bits);
+    let root = k.root;
+    let root_obj = root.object;
+
+    let initial = [(seL4_CapInitThreadTCB, ObjectKind::Thread),
+                   (seL4_CapInitThreadVSpace, ObjectKind::Other),
+                   (seL4_CapIRQControl, ObjectKind::Other),
+                   (seL4_CapASIDControl, ObjectKind::Other),
+                   (seL4_CapInitThreadASIDPool, ObjectKind::Other),
+                   (seL4_CapIOPort, ObjectKind::Other),
+                   (seL4_CapIOSpace, ObjectKind::Other),
+                   (seL4_CapBootInfoFrame, ObjectKind::Frame),
+                   (seL4_CapInitThreadIPCBuffer, ObjectKind::Frame),
+                   (seL4_CapDomain, ObjectKind::Other)];
+    for &(slot, kind) in initial.iter() {
+        let obj = k.new_object(match kind {
+            ObjectKind::Thread => {
+                ObjectData::Thread(ThreadState {
+                    running: true,
+                    ..ThreadState::default()
+                })
+            }
+            kind => ObjectData::Plain(kind),
+        });
+        let cap = k.new_cap(obj, None);
+        k.set_slot((root_obj, slot as usize), Some(cap));
+    }
+    let root_copy = k.derive(root);
+    k.set_slot((root_obj, seL4_CapInitThreadCNode as usize), Some(root_copy));
+
+    unsafe {
+        *ipc_buffer() = mem::zeroed();
+    }
+    let mut bi: seL4_BootInfo = unsafe { mem::zeroed() };
+    bi.nodeID = 0 as _;
+    bi.numNodes = 1;
+    bi.ipcBuffer = ipc_buffer();
+    bi.initThreadCNodeSizeBits = radix_bits as _;
+
+    let first_untyped: usize = 16;
+    let mut paddr: seL4_Word = 0x100000;
+    for (i, &bits) in untyped_bits.iter().enumerate() {
+        let size = (1 as seL4_Word) << bits;
+        paddr = (paddr + size - 1) & !(size - 1);
+        let obj = k.new_object(ObjectData::Untyped {
+            size_bits: bits,
+            paddr: paddr,
+            watermark: 0,
+        });
+        let cap = k.new_cap(obj, None);
+        k.set_slot((root_obj, first_untyped + i), Some(cap));
+
+        bi.untypedList[i].paddr = paddr as _;
+        bi.untypedList[i].sizeBits = bits as _;
+        bi.untypedList[i].isDevice = 0;
+        paddr += size;
+    }
+
+    let first_empty = first_untyped + untyped_bits.len();
+    bi.untyped = seL4_SlotRegion {
+        start: first_untyped as _,
+        end: first_empty as _,
+    };
+    bi.empty = seL4_SlotRegion {
+        start: first_empty as _,
+        end: (1usize << radix_bits) as _,
+    };
+
+    KERNEL.with(|kernel| *kernel.borrow_mut() = Some(k));
+    Box::into_raw(Box::new(bi))
+}
+
+/// Start a fresh simulated kernel with a 4096-slot root CNode and two untyped objects, of 1MiB and
+/// 64KiB.
+pub fn boot() -> *const seL4_BootInfo {
+    boot_with(12, &[20, 16])
+}
+
+/// The kind of object the capability `cptr` refers to, if the slot is occupied.
+pub fn object_at(cptr: seL4_CPtr) -> Option<ObjectKind> {
+    with_kernel(|k| k.lookup_cap(cptr).ok().map(|cap| k.kind(cap.object)))
+}
+
+/// Whether the capabilities `a` and `b` refer to the same object.
+pub fn same_object(a: seL4_CPtr, b: seL4_CPtr) -> bool {
+    with_kernel(|k| {
+        match (k.lookup_cap(a), k.lookup_cap(b)) {
+            (Ok(a), Ok(b)) => a.object == b.object,
+            _ => false,
+        }
+    })
+}
+
+/// The badge of the capability `cptr`.
+pub fn badge_at(cptr: seL4_CPtr) -> Option<seL4_Word> {
+    with_kernel(|k| k.badge_of(cptr).ok())
+}
+
+/// The read, write, and grant rights of the capability `cptr`.
+pub fn rights_at(cptr: seL4_CPtr) -> Option<(bool, bool, bool)> {
+    with_kernel(|k| k.lookup_cap(cptr).ok().map(|cap| (cap.read, cap.write, cap.grant)))
+}
+
+/// The guard value and guard size of the CNode capability `cptr`.
+pub fn guard_at(cptr: seL4_CPtr) -> Option<(seL4_Word, u8)> {
+    with_kernel(|k| k.lookup_cap(cptr).ok().map(|cap| (cap.guard_val, cap.guard_bits)))
+}
+
+/// Number of messages queued on the endpoint `cptr`.
+pub fn pending_messages(cptr: seL4_CPtr) -> usize {
+    with_kernel(|k| {
+        match k.lookup_cap(cptr) {
+            Ok(cap) => {
+                match k.objects[cap.object] {
+                    ObjectData::Endpoint { ref queue } => queue.len(),
+                    _ => 0,
+                }
+            }
+            Err(_) => 0,
+        }
+    })
+}
+
+/// The pending word of the notification `cptr`, if it has been signalled.
+pub fn notification_word(cptr: seL4_CPtr) -> Option<seL4_Word> {
+    with_kernel(|k| {
+        k.lookup_cap(cptr).ok().and_then(|cap| {
+            match k.objects[cap.object] {
+                ObjectData::Notification { word } => word,
+                _ => None,
+            }
+        })
+    })
+}
+
+/// What the thread `cptr` has been set up with, if it is a thread.
+pub fn thread_state(cptr: seL4_CPtr) -> Option<ThreadState> {
+    with_kernel(|k| k.thread(cptr).ok().map(|thread| thread.clone()))
+}
+
+/// The registers last written to the thread `cptr`, if it is a thread.
+pub fn registers(cptr: seL4_CPtr) -> Option<UserContext> {
+    thread_state(cptr).map(|thread| {
+        let mut regs = UserContext::new();
+        let len = thread.registers.len().min(context_words());
+        unsafe {
+            let words = regs.raw_mut() as *mut seL4_UserContext as *mut seL4_Word;
+            ::std::ptr::copy_nonoverlapping(thread.registers.as_ptr(), words, len);
+        }
+        regs
+    })
+}
+
+/// Run the thread `cptr` as if the kernel had scheduled it, until its entry function returns.
+///
+/// The entry function is called with its first argument register, on the current host thread and
+/// stack. This is unsafe because the thread's registers must describe an `extern "C"` function
+/// taking one word, and the thread must be running.
+#[cfg(target_arch = "x86_64")]
+pub unsafe fn run_thread(cptr: seL4_CPtr) {
+    let running = thread_state(cptr).map_or(false, |thread| thread.running);
+    assert!(running, "mock: run_thread on a thread which is not running");
+    let regs = registers(cptr).unwrap();
+    let entry: extern "C" fn(seL4_Word) = mem::transmute(regs.pc() as usize);
+    entry(regs.arg(0))
+}
+
+/// Offset of the first unused byte in the untyped object `cptr`.
+pub fn untyped_watermark(cptr: seL4_CPtr) -> Option<seL4_Word> {
+    with_kernel(|k| {
+        k.lookup_cap(cptr).ok().and_then(|cap| {
+            match k.objects[cap.object] {
+                ObjectData::Untyped { watermark, .. } => Some(watermark),
+                _ => None,
+            }
+        })
+    })
+}
+
+/// Act as a server on the endpoint `cptr`: every `seL4_Call` on it is answered by `handler`.
+pub fn set_call_handler<F>(cptr: seL4_CPtr, handler: F)
+    where F: FnMut(Message) -> Message + 'static
+{
+    with_kernel(|k| {
+        let object = k.lookup_cap(cptr).expect("call handler on an invalid capability").object;
+        k.handlers.insert(object, Box::new(handler));
+    })
+}
+
+/// Stand in for other threads while the current one waits on the endpoint or notification `cptr`.
+///
+/// Whenever a blocking receive from `cptr` finds nothing pending, `handler` is run first, as if
+/// another thread ran while this one was blocked. It should send or signal something, or the
+/// receive panics as usual.
+pub fn set_block_handler<F>(cptr: seL4_CPtr, handler: F)
+    where F: FnMut() + 'static
+{
+    with_kernel(|k| {
+        let object = k.lookup_cap(cptr).expect("block handler on an invalid capability").object;
+        k.blockers.insert(object, Box::new(handler));
+    })
+}
+
+/// Queue `msg` on the endpoint `cptr` as if another thread had sent it with `seL4_Call`.
+///
+/// The badge of `cptr` is applied to the message. Whoever receives it can reply, and the reply is
+/// collected with `take_reply` using the returned call number.
+pub fn queue_call(cptr: seL4_CPtr, mut msg: Message) -> usize {
+    with_kernel(|k| {
+        let cap = k.lookup_cap(cptr).expect("call on an invalid capability");
+        let call = k.next_call;
+        k.next_call += 1;
+        msg.badge = cap.badge;
+        msg.call = Some(call);
+        match k.objects[cap.object] {
+            ObjectData::Endpoint { ref mut queue } => queue.push_back(msg),
+            _ => panic!("call on a capability which is not an endpoint"),
+        }
+        call
+    })
+}
+
+/// The reply to a call queued with `queue_call`, if it has been answered.
+pub fn take_reply(call: usize) -> Option<Message> {
+    with_kernel(|k| k.replies.remove(&call))
+}
+
+/// Everything written with `seL4_DebugPutChar` so far.
+pub fn debug_output() -> String {
+    with_kernel(|k| String::from_utf8_lossy(&k.debug_output).into_owned())
+}
@@ -0,0 +1,330 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! A simulated kernel, for testing this crate on an ordinary host.
+//!
+//! With the `mock` feature enabled, this module takes the place of the `sel4_sys` crate. Types and
+//! constants are re-exported from the real `sel4_sys`, but the system calls below are replaced by
+//! functions operating on an in-memory model of CNodes, untyped memory, endpoints and
+//! notifications. Errors are reported through the IPC buffer just as the kernel reports them.
+//!
+//! Since there is only ever one thread, an operation which would block forever (receiving from an
+//! empty endpoint, or calling an endpoint nobody serves) panics instead. Servers are simulated by
+//! registering a handler with `mock::set_call_handler`, clients by queueing calls with
+//! `mock::queue_call`, and other threads running while this one blocks with
+//! `mock::set_block_handler`. Threads only record what they are set up with, and never run unless
+//! a test runs one with `mock::run_thread`. Invocations which are not modelled here (IRQs, paging
+//! structures, and some TCB invocations) fall through to the real `sel4_sys` and must not be used.
+
+#![allow(non_snake_case)]
+
+pub use raw_sel4_sys::*;
+
+pub mod kernel;
+
+use core::ptr;
+
+use self::kernel::{Message, status, with_kernel};
+
+/// Read the outgoing message described by `info` from the IPC buffer.
+unsafe fn outgoing(info: seL4_MessageInfo) -> (seL4_Word, Vec<seL4_Word>, Vec<seL4_CPtr>) {
+    let buf = &*seL4_GetIPCBuffer();
+    let len = (info.get_length() as usize).min(seL4_MsgMaxLength);
+    let caps = (info.get_extraCaps() as usize).min(seL4_MsgMaxExtraCaps);
+    let label = info.get_label() as seL4_Word;
+    (label, buf.msg[..len].to_vec(), buf.caps_or_badges[..caps].to_vec())
+}
+
+/// Write a received message into the IPC buffer, returning its message info.
+unsafe fn deliver(msg: Option<Message>, sender: *mut seL4_Word) -> seL4_MessageInfo {
+    let buf = &mut *seL4_GetIPCBuffer();
+    let info = match msg {
+        Some(msg) => {
+            let dest = (buf.receiveCNode, buf.receiveIndex, buf.receiveDepth as u8);
+            let caps = with_kernel(|k| k.transfer_caps(&msg, dest));
+            buf.msg[..msg.data.len()].copy_from_slice(&msg.data);
+            if !sender.is_null() {
+                *sender = msg.badge;
+            }
+            seL4_MessageInfo::new(msg.label as _, 0, caps as _, msg.data.len() as _)
+        }
+        None => {
+            if !sender.is_null() {
+                *sender = 0;
+            }
+            seL4_MessageInfo::new(0, 0, 0, 0)
+        }
+    };
+    buf.tag = info;
+    info
+}
+
+unsafe fn send(dest: seL4_CPtr, info: seL4_MessageInfo, blocking: bool) {
+    let (label, data, caps) = outgoing(info);
+    // The kernel would fault the sender on an invalid capability. The mock reports an error in the
+    // IPC buffer instead, so that tests can observe it.
+    status(with_kernel(|k| k.send(dest, label, &data, &caps, blocking)));
+}
+
+pub unsafe fn seL4_GetIPCBuffer() -> *mut seL4_IPCBuffer {
+    kernel::ipc_buffer()
+}
+
+pub unsafe fn seL4_Yield() {}
+
+pub unsafe fn seL4_DebugPutChar(c: u8) {
+    with_kernel(|k| k.debug_put_char(c))
+}
+
+pub unsafe fn seL4_Send(dest: seL4_CPtr, info: seL4_MessageInfo) {
+    send(dest, info, true)
+}
+
+pub unsafe fn seL4_NBSend(dest: seL4_CPtr, info: seL4_MessageInfo) {
+    send(dest, info, false)
+}
+
+pub unsafe fn seL4_Signal(dest: seL4_CPtr) {
+    status(with_kernel(|k| k.signal(dest)));
+}
+
+pub unsafe fn seL4_Recv(src: seL4_CPtr, sender: *mut seL4_Word) -> seL4_MessageInfo {
+    let mut blocked = false;
+    loop {
+        match with_kernel(|k| k.recv(src)) {
+            Ok(Some(msg)) => return deliver(Some(msg), sender),
+            Ok(None) if !blocked && kernel::block(src) == Ok(true) => blocked = true,
+            Ok(None) => panic!("mock: seL4_Recv with nothing pending would block forever"),
+            Err(fault) => {
+                fault.report();
+                return (*seL4_GetIPCBuffer()).tag;
+            }
+        }
+    }
+}
+
+pub unsafe fn seL4_NBRecv(src: seL4_CPtr, sender: *mut seL4_Word) -> seL4_MessageInfo {
+    match with_kernel(|k| k.recv(src)) {
+        Ok(msg) => deliver(msg, sender),
+        Err(fault) => {
+            fault.report();
+            (*seL4_GetIPCBuffer()).tag
+        }
+    }
+}
+
+pub unsafe fn seL4_Call(dest: seL4_CPtr, info: seL4_MessageInfo) -> seL4_MessageInfo {
+    let (label, data, caps) = outgoing(info);
+    match kernel::call(dest, label, &data, &caps) {
+        Ok(reply) => deliver(Some(reply), ptr::null_mut()),
+        Err(fault) => {
+            fault.report();
+            (*seL4_GetIPCBuffer()).tag
+        }
+    }
+}
+
+pub unsafe fn seL4_Reply(info: seL4_MessageInfo) {
+    let (label, data, caps) = outgoing(info);
+    with_kernel(|k| k.reply(label, &data, &caps));
+}
+
+pub unsafe fn seL4_ReplyRecv(src: seL4_CPtr, info: seL4_MessageInfo, sender: *mut seL4_Word)
+                             -> seL4_MessageInfo {
+    seL4_Reply(info);
+    seL4_Recv(src, sender)
+}
+
+pub unsafe fn seL4_CNode_Copy(dest_root: seL4_CPtr, dest_index: seL4_Word, dest_depth: u8,
+                              src_root: seL4_CPtr, src_index: seL4_Word, src_depth: u8,
+                              rights: seL4_CapRights)
+                              -> isize {
+    status(with_kernel(|k| {
+        k.copy((dest_root, dest_index, dest_depth),
+               (src_root, src_index, src_depth),
+               Some(rights),
+               None)
+    }))
+}
+
+pub unsafe fn seL4_CNode_Mint(dest_root: seL4_CPtr, dest_index: seL4_Word, dest_depth: u8,
+                              src_root: seL4_CPtr, src_index: seL4_Word, src_depth: u8,
+                              rights: seL4_CapRights, badge: seL4_CapData)
+                              -> isize {
+    status(with_kernel(|k| {
+        k.copy((dest_root, dest_index, dest_depth),
+               (src_root, src_index, src_depth),
+               Some(rights),
+               Some(badge))
+    }))
+}
+
+pub unsafe fn seL4_CNode_Move(dest_root: seL4_CPtr, dest_index: seL4_Word, dest_depth: u8,
+                              src_root: seL4_CPtr, src_index: seL4_Word, src_depth: u8)
+                              -> isize {
+    status(with_kernel(|k| {
+        k.move_((dest_root, dest_index, dest_depth), (src_root, src_index, src_depth), None)
+    }))
+}
+
+pub unsafe fn seL4_CNode_Mutate(dest_root: seL4_CPtr, dest_index: seL4_Word, dest_depth: u8,
+                                src_root: seL4_CPtr, src_index: seL4_Word, src_depth: u8,
+                                badge: seL4_CapData)
+                                -> isize {
+    status(with_kernel(|k| {
+        k.move_((dest_root, dest_index, dest_depth),
+                (src_root, src_index, src_depth),
+                Some(badge))
+    }))
+}
+
+/// Modelled as two moves, so unlike the kernel `dest` and `src` may not be the same slot.
+pub unsafe fn seL4_CNode_Rotate(dest_root: seL4_CPtr, dest_index: seL4_Word, dest_depth: u8,
+                                dest_badge: seL4_CapData, pivot_root: seL4_CPtr,
+                                pivot_index: seL4_Word, pivot_depth: u8,
+                                pivot_badge: seL4_CapData, src_root: seL4_CPtr,
+                                src_index: seL4_Word, src_depth: u8)
+                                -> isize {
+    let dest = (dest_root, dest_index, dest_depth);
+    let pivot = (pivot_root, pivot_index, pivot_depth);
+    let src = (src_root, src_index, src_depth);
+    status(with_kernel(|k| {
+        k.move_(dest, pivot, Some(dest_badge))?;
+        k.move_(pivot, src, Some(pivot_badge))
+    }))
+}
+
+pub unsafe fn seL4_CNode_Delete(root: seL4_CPtr, index: seL4_Word, depth: u8) -> isize {
+    status(with_kernel(|k| k.delete((root, index, depth))))
+}
+
+pub unsafe fn seL4_CNode_Revoke(root: seL4_CPtr, index: seL4_Word, depth: u8) -> isize {
+    status(with_kernel(|k| k.revoke((root, index, depth))))
+}
+
+pub unsafe fn seL4_CNode_CancelBadgedSends(root: seL4_CPtr, index: seL4_Word, depth: u8)
+                                           -> isize {
+    status(with_kernel(|k| k.cancel_badged_sends((root, index, depth))))
+}
+
+pub unsafe fn seL4_CNode_SaveCaller(root: seL4_CPtr, index: seL4_Word, depth: u8) -> isize {
+    status(with_kernel(|k| k.save_caller((root, index, depth))))
+}
+
+pub unsafe fn seL4_Untyped_Retype(service: seL4_CPtr, objtype: seL4_Word, size_bits: seL4_Word,
+                                  root: seL4_CPtr, node_index: seL4_Word, node_depth: seL4_Word,
+                                  node_offset: seL4_Word, num_objects: seL4_Word)
+                                  -> isize {
+    status(with_kernel(|k| {
+        k.retype(service,
+                 objtype,
+                 size_bits,
+                 root,
+                 node_index,
+                 node_depth,
+                 node_offset,
+                 num_objects)
+    }))
+}
+
+pub unsafe fn seL4_TCB_SetSpace(service: seL4_CPtr, fault_ep: seL4_CPtr, cspace_root: seL4_CPtr,
+                                _cspace_root_data: seL4_CapData, vspace_root: seL4_CPtr,
+                                _vspace_root_data: seL4_CapData)
+                                -> isize {
+    status(with_kernel(|k| {
+        let thread = k.thread(service)?;
+        thread.fault_endpoint = fault_ep;
+        thread.cspace_root = cspace_root;
+        thread.vspace_root = vspace_root;
+        Ok(())
+    }))
+}
+
+pub unsafe fn seL4_TCB_SetIPCBuffer(service: seL4_CPtr, buffer: seL4_Word, frame: seL4_CPtr)
+                                    -> isize {
+    status(with_kernel(|k| {
+        let thread = k.thread(service)?;
+        thread.ipc_buffer = buffer;
+        thread.ipc_buffer_frame = frame;
+        Ok(())
+    }))
+}
+
+pub unsafe fn seL4_TCB_SetPriority(service: seL4_CPtr, priority: u8) -> isize {
+    status(with_kernel(|k| k.thread(service).map(|thread| thread.priority = priority)))
+}
+
+pub unsafe fn seL4_TCB_Resume(service: seL4_CPtr) -> isize {
+    status(with_kernel(|k| k.thread(service).map(|thread| thread.running = true)))
+}
+
+pub unsafe fn seL4_TCB_Suspend(service: seL4_CPtr) -> isize {
+    status(with_kernel(|k| k.thread(service).map(|thread| thread.running = false)))
+}
+
+pub unsafe fn seL4_TCB_WriteRegisters(service: seL4_CPtr, resume_target: u8, _arch_flags: u8,
+                                      count: seL4_Word, regs: *mut seL4_UserContext)
+                                      -> isize {
+    let count = (count as usize).min(kernel::context_words());
+    let words = ::std::slice::from_raw_parts(regs as *const seL4_Word, count).to_vec();
+    status(with_kernel(|k| {
+        let thread = k.thread(service)?;
+        thread.registers = words;
+        if resume_target != 0 {
+            thread.running = true;
+        }
+        Ok(())
+    }))
+}
+
+pub unsafe fn seL4_TCB_ReadRegisters(service: seL4_CPtr, suspend_source: u8, _arch_flags: u8,
+                                     count: seL4_Word, regs: *mut seL4_UserContext)
+                                     -> isize {
+    let count = (count as usize).min(kernel::context_words());
+    let res = with_kernel(|k| {
+        let thread = k.thread(service)?;
+        if suspend_source != 0 {
+            thread.running = false;
+        }
+        Ok(thread.registers.clone())
+    });
+    status(res.map(|mut words| {
+        words.resize(count, 0);
+        ptr::copy_nonoverlapping(words.as_ptr(), regs as *mut seL4_Word, count);
+    }))
+}
+
+pub unsafe fn seL4_TCB_CopyRegisters(service: seL4_CPtr, source: seL4_CPtr, suspend_source: u8,
+                                     resume_target: u8, transfer_frame: u8,
+                                     transfer_integer: u8, _arch_flags: u8)
+                                     -> isize {
+    status(with_kernel(|k| {
+        let mut words = {
+            let source = k.thread(source)?;
+            if suspend_source != 0 {
+                source.running = false;
+            }
+            source.registers.clone()
+        };
+        words.resize(kernel::context_words(), 0);
+        let dest = k.thread(service)?;
+        dest.registers.resize(kernel::context_words(), 0);
+        let frame = kernel::frame_words();
+        if transfer_frame != 0 {
+            dest.registers[..frame].copy_from_slice(&words[..frame]);
+        }
+        if transfer_integer != 0 {
+            dest.registers[frame..].copy_from_slice(&words[frame..]);
+        }
+        if resume_target != 0 {
+            dest.running = true;
+        }
+        Ok(())
+    }))
+}
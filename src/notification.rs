@@ -9,11 +9,21 @@
 
 use sel4_sys::{seL4_NBRecv, seL4_NotificationObject, seL4_Recv, seL4_Signal, seL4_Word};
 
+use {CapRights, ObjectRights};
+
 cap_wrapper!{ ()
     /// A notification object for signalling
     Notification = seL4_NotificationObject |_| 16,
 }
 
+impl ObjectRights for Notification {
+    /// Read allows waiting and polling, and write allows signalling.
+    #[inline(always)]
+    fn meaningful_rights() -> CapRights {
+        CapRights::RW
+    }
+}
+
 impl Notification {
     /// Signal the notification.
     #[inline(always)]
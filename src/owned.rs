@@ -0,0 +1,133 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Capabilities which clean up their slot when dropped.
+
+use core::mem;
+use core::ops::Deref;
+
+use {Allocatable, ObjectAllocator, SlotRef, ToCap};
+
+/// A capability which owns the slot it is stored in.
+///
+/// The wrappers in this crate are plain CPtrs which can be freely copied, and nothing stops a
+/// slot from being deleted twice or never being freed. An `Owned` pairs a capability with its
+/// slot and the allocator the slot came from. When it is dropped, the capability is deleted (and
+/// optionally its children revoked first) and the slot is returned to the allocator. If the object
+/// itself came from the allocator, it is freed through the allocator instead of just deleted.
+///
+/// Errors during drop are ignored. Use `into_raw` to take manual control of the slot.
+pub struct Owned<'a, T: ToCap + Copy, A: ObjectAllocator + 'a> {
+    cap: T,
+    slot: SlotRef,
+    alloc: &'a A,
+    revoke: bool,
+    free: Option<fn(&A, T)>,
+}
+
+fn free_object<A: ObjectAllocator, T: Allocatable>(alloc: &A, obj: T) {
+    let _ = alloc.free_object(obj);
+}
+
+impl<'a, T: ToCap + Copy, A: ObjectAllocator + 'a> Owned<'a, T, A> {
+    /// Take ownership of `cap`, which is stored in `slot`, a slot allocated from `alloc`.
+    #[inline(always)]
+    pub fn new(cap: T, slot: SlotRef, alloc: &'a A) -> Owned<'a, T, A> {
+        Owned {
+            cap: cap,
+            slot: slot,
+            alloc: alloc,
+            revoke: false,
+            free: None,
+        }
+    }
+
+    /// Allocate a slot and an object of type `T` from `alloc`.
+    ///
+    /// Returns `Ok(None)` if either a slot or the object could not be allocated.
+    pub fn allocate(alloc: &'a A) -> Result<Option<Owned<'a, T, A>>, A::ObjectAllocError>
+        where T: Allocatable
+    {
+        let slot = match alloc.allocate_slot() {
+            Some(slot) => slot,
+            None => return Ok(None),
+        };
+
+        match alloc.allocate_object::<T>(slot) {
+            Ok(Some(cap)) => {
+                Ok(Some(Owned {
+                    cap: cap,
+                    slot: slot,
+                    alloc: alloc,
+                    revoke: false,
+                    free: Some(free_object::<A, T>),
+                }))
+            }
+            Ok(None) => {
+                let _ = alloc.free_slot(slot);
+                Ok(None)
+            }
+            Err(e) => {
+                let _ = alloc.free_slot(slot);
+                Err(e)
+            }
+        }
+    }
+
+    /// Choose whether to revoke all children of the capability before it is deleted.
+    #[inline(always)]
+    pub fn set_revoke_on_drop(&mut self, revoke: bool) {
+        self.revoke = revoke;
+    }
+
+    /// The slot the capability is stored in.
+    #[inline(always)]
+    pub fn slot(&self) -> SlotRef {
+        self.slot
+    }
+
+    /// Give up ownership, returning the capability and its slot without deleting anything.
+    #[inline(always)]
+    pub fn into_raw(self) -> (T, SlotRef) {
+        let raw = (self.cap, self.slot);
+        mem::forget(self);
+        raw
+    }
+}
+
+impl<'a, T: ToCap + Copy, A: ObjectAllocator + 'a> Deref for Owned<'a, T, A> {
+    type Target = T;
+
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        &self.cap
+    }
+}
+
+impl<'a, T: ToCap + Copy, A: ObjectAllocator + 'a> ToCap for Owned<'a, T, A> {
+    #[inline(always)]
+    fn to_cap(&self) -> ::sel4_sys::seL4_CPtr {
+        self.cap.to_cap()
+    }
+}
+
+impl<'a, T: ToCap + Copy, A: ObjectAllocator + 'a> Drop for Owned<'a, T, A> {
+    fn drop(&mut self) {
+        if self.revoke {
+            let _ = self.slot.revoke();
+        }
+        match self.free {
+            Some(free) => free(self.alloc, self.cap),
+            None => {
+                let _ = self.slot.delete();
+            }
+        }
+        let _ = self.alloc.free_slot(self.slot);
+    }
+}
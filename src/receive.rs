@@ -0,0 +1,121 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Managing the slot capabilities are received into
+//!
+//! The kernel stores a capability sent with a message into the slot named by the receiver's IPC
+//! buffer, and only if that slot is empty. A server which accepts capabilities has to point it at
+//! a fresh slot before every receive, or later capabilities are silently dropped. `CapReceiver`
+//! does this with slots from an allocator. Once a capability arrives, its slot belongs to the
+//! caller, and the next receive arms a new one.
+
+use sel4_sys::{seL4_CPtr, seL4_Word};
+
+use {Endpoint, ObjectAllocator, RecvToken, SlotRef};
+
+/// Errors from receiving with a `CapReceiver`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CapReceiveError {
+    /// No slot could be allocated to receive into. Nothing was received.
+    NoSlot,
+    /// Invoking the kernel failed.
+    Kernel(::Error),
+}
+
+/// Receives messages with a fresh slot ready for any capability sent along.
+///
+/// A slot received into is reported by `RecvToken::received_slot` and `RecvToken::received_cap`.
+/// It is then the caller's responsibility, and should be returned to the allocator once its
+/// capability is deleted. The slot armed when the receiver is dropped is freed.
+pub struct CapReceiver<'a, A: ObjectAllocator + 'a> {
+    alloc: &'a A,
+    slot: Option<SlotRef>,
+}
+
+impl<'a, A: ObjectAllocator + 'a> CapReceiver<'a, A> {
+    /// Create a receiver taking slots from `alloc`. No slot is allocated until the first receive.
+    #[inline(always)]
+    pub fn new(alloc: &'a A) -> CapReceiver<'a, A> {
+        CapReceiver {
+            alloc: alloc,
+            slot: None,
+        }
+    }
+
+    /// Make an empty slot the destination for received capabilities, returning it.
+    ///
+    /// The receive methods do this themselves. It is only needed before receiving some other
+    /// way, such as with `Endpoint::call_built`, after which the token must be passed to
+    /// `consumed`.
+    pub fn arm(&mut self) -> Result<SlotRef, CapReceiveError> {
+        let slot = match self.slot {
+            Some(slot) => slot,
+            None => {
+                match self.alloc.allocate_slot() {
+                    Some(slot) => slot,
+                    None => return Err(CapReceiveError::NoSlot),
+                }
+            }
+        };
+        self.slot = Some(slot);
+        ::set_cap_destination(slot);
+        Ok(slot)
+    }
+
+    /// Note that `token` was received with the armed slot, giving up the slot if a capability
+    /// arrived in it.
+    #[inline(always)]
+    pub fn consumed(&mut self, token: &RecvToken) {
+        if token.received_slot().is_some() && token.received_slot() == self.slot {
+            self.slot = None;
+        }
+    }
+
+    /// Block until a message is received on `ep`.
+    #[inline(always)]
+    pub fn recv(&mut self, ep: Endpoint) -> Result<RecvToken, CapReceiveError> {
+        self.arm()?;
+        let token = ep.recv();
+        self.consumed(&token);
+        Ok(token)
+    }
+
+    /// Try to receive a message on `ep`, failing with `WouldBlock` if none is waiting.
+    #[inline(always)]
+    pub fn try_recv(&mut self, ep: Endpoint) -> Result<RecvToken, CapReceiveError> {
+        self.arm()?;
+        let token = ep.try_recv().map_err(CapReceiveError::Kernel)?;
+        self.consumed(&token);
+        Ok(token)
+    }
+
+    /// Reply to the last caller, then block until a message is received on `ep`.
+    #[inline(always)]
+    pub fn reply_recv(&mut self, ep: Endpoint, data: &[seL4_Word], caps: &[seL4_CPtr])
+                      -> Result<RecvToken, CapReceiveError> {
+        self.arm()?;
+        let token = ep.reply_recv(data, caps).map_err(CapReceiveError::Kernel)?;
+        self.consumed(&token);
+        Ok(token)
+    }
+
+    /// The slot which will receive the next capability, if one has been allocated.
+    #[inline(always)]
+    pub fn slot(&self) -> Option<SlotRef> {
+        self.slot
+    }
+}
+
+impl<'a, A: ObjectAllocator + 'a> Drop for CapReceiver<'a, A> {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot.take() {
+            let _ = self.alloc.free_slot(slot);
+        }
+    }
+}
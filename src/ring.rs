@@ -0,0 +1,266 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Single-producer, single-consumer rings in shared memory
+//!
+//! A ring lives in frames mapped into both the producer's and the consumer's address space, with
+//! the arch `Page` types' `map`. It starts with a small header holding the two indices, followed
+//! by the slots. Neither side makes a system call to push or pop. The only one made is a
+//! `Notification::signal` by the producer, when the consumer has said with `RingConsumer::arm`
+//! that it is about to wait for more items.
+//!
+//! Set the memory up once with `ring_init`, then attach a `RingProducer` and a `RingConsumer` to
+//! it, from whichever address spaces they run in. The consumer does not trust the header, so a
+//! misbehaving producer cannot make it read outside the ring.
+
+use core::{mem, ptr};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use sel4_sys::seL4_PageBits;
+
+use Notification;
+
+/// Errors from setting up a ring.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RingError {
+    /// The memory is too small to hold even one item.
+    TooSmall,
+    /// The memory is not aligned for the header or the items.
+    Misaligned,
+    /// The header does not describe a ring which fits in the memory.
+    Corrupt,
+}
+
+#[repr(C)]
+struct RingHeader {
+    /// Number of items ever pushed, written only by the producer.
+    head: AtomicUsize,
+    /// Number of items ever popped, written only by the consumer.
+    tail: AtomicUsize,
+    /// Nonzero if the consumer wants a signal on the next push.
+    waiting: AtomicUsize,
+    capacity: usize,
+}
+
+#[inline(always)]
+fn data_offset<T>() -> usize {
+    let align = mem::align_of::<T>();
+    (mem::size_of::<RingHeader>() + align - 1) & !(align - 1)
+}
+
+/// Number of bytes of memory needed for a ring of `capacity` items of type `T`.
+#[inline(always)]
+pub fn ring_bytes<T>(capacity: usize) -> usize {
+    data_offset::<T>() + capacity * mem::size_of::<T>()
+}
+
+/// Number of pages needed for a ring of `capacity` items of type `T`.
+#[inline(always)]
+pub fn ring_pages<T>(capacity: usize) -> usize {
+    (ring_bytes::<T>(capacity) + (1 << seL4_PageBits) - 1) >> seL4_PageBits
+}
+
+/// Check that `len` bytes at `mem` can hold a ring of `T`, returning the largest capacity.
+fn check<T>(mem: *const u8, len: usize) -> Result<usize, RingError> {
+    if mem::size_of::<T>() == 0 {
+        return Err(RingError::TooSmall);
+    }
+    let align = mem::align_of::<RingHeader>().max(mem::align_of::<T>());
+    if mem as usize & (align - 1) != 0 {
+        return Err(RingError::Misaligned);
+    }
+    let slots = len.saturating_sub(data_offset::<T>()) / mem::size_of::<T>();
+    if slots == 0 {
+        return Err(RingError::TooSmall);
+    }
+    // The capacity is a power of two, so indices can wrap around freely.
+    Ok(1 << (mem::size_of::<usize>() * 8 - 1 - slots.leading_zeros() as usize))
+}
+
+/// Lay out an empty ring of `T` in the `len` bytes at `mem`, returning its capacity.
+///
+/// This is unsafe because `mem` must be valid for writes of `len` bytes, and neither side may be
+/// using the ring.
+pub unsafe fn ring_init<T: Copy>(mem: *mut u8, len: usize) -> Result<usize, RingError> {
+    let capacity = check::<T>(mem, len)?;
+    ptr::write(mem as *mut RingHeader,
+               RingHeader {
+                   head: AtomicUsize::new(0),
+                   tail: AtomicUsize::new(0),
+                   waiting: AtomicUsize::new(0),
+                   capacity: capacity,
+               });
+    Ok(capacity)
+}
+
+/// The state shared by both halves of a ring.
+struct Ring<T> {
+    header: *const RingHeader,
+    data: *mut T,
+    capacity: usize,
+    ntfn: Notification,
+}
+
+impl<T: Copy> Ring<T> {
+    unsafe fn attach(mem: *mut u8, len: usize, ntfn: Notification) -> Result<Ring<T>, RingError> {
+        let max = check::<T>(mem, len)?;
+        let header = mem as *const RingHeader;
+        let capacity = (*header).capacity;
+        if capacity == 0 || capacity > max || capacity & (capacity - 1) != 0 {
+            return Err(RingError::Corrupt);
+        }
+        Ok(Ring {
+            header: header,
+            data: mem.offset(data_offset::<T>() as isize) as *mut T,
+            capacity: capacity,
+            ntfn: ntfn,
+        })
+    }
+
+    #[inline(always)]
+    fn header(&self) -> &RingHeader {
+        unsafe { &*self.header }
+    }
+
+    #[inline(always)]
+    fn slot(&self, index: usize) -> *mut T {
+        unsafe { self.data.offset((index & (self.capacity - 1)) as isize) }
+    }
+
+    /// Number of items in the ring, as far as can be told from the indices.
+    #[inline(always)]
+    fn len(&self) -> usize {
+        let header = self.header();
+        let head = header.head.load(Ordering::SeqCst);
+        let tail = header.tail.load(Ordering::SeqCst);
+        head.wrapping_sub(tail).min(self.capacity)
+    }
+}
+
+/// The half of a ring which pushes items.
+pub struct RingProducer<T> {
+    ring: Ring<T>,
+}
+
+impl<T: Copy> RingProducer<T> {
+    /// Attach to the ring set up by `ring_init` in the `len` bytes at `mem`.
+    ///
+    /// `ntfn` is signalled to wake the consumer. This is unsafe because `mem` must be valid for
+    /// as long as the producer is used, and there must be no other producer.
+    pub unsafe fn attach(mem: *mut u8, len: usize, ntfn: Notification)
+                         -> Result<RingProducer<T>, RingError> {
+        Ring::attach(mem, len, ntfn).map(|ring| RingProducer { ring: ring })
+    }
+
+    /// Add an item to the ring, waking the consumer if it is waiting.
+    ///
+    /// Returns the item back if the ring is full.
+    pub fn push(&mut self, item: T) -> Result<(), T> {
+        let header = self.ring.header();
+        let head = header.head.load(Ordering::Relaxed);
+        let tail = header.tail.load(Ordering::Acquire);
+        if head.wrapping_sub(tail) >= self.ring.capacity {
+            return Err(item);
+        }
+        unsafe {
+            ptr::write_volatile(self.ring.slot(head), item);
+        }
+        header.head.store(head.wrapping_add(1), Ordering::SeqCst);
+        if header.waiting.swap(0, Ordering::SeqCst) != 0 {
+            self.ring.ntfn.signal();
+        }
+        Ok(())
+    }
+
+    /// Number of items the ring can hold.
+    #[inline(always)]
+    pub fn capacity(&self) -> usize {
+        self.ring.capacity
+    }
+
+    /// Number of items waiting to be popped.
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    /// Whether the consumer has taken every item.
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+unsafe impl<T: Copy + Send> Send for RingProducer<T> {}
+
+/// The half of a ring which pops items.
+pub struct RingConsumer<T> {
+    ring: Ring<T>,
+}
+
+impl<T: Copy> RingConsumer<T> {
+    /// Attach to the ring set up by `ring_init` in the `len` bytes at `mem`.
+    ///
+    /// `ntfn` is waited on for the producer's signal. This is unsafe because `mem` must be valid
+    /// for as long as the consumer is used, and there must be no other consumer.
+    pub unsafe fn attach(mem: *mut u8, len: usize, ntfn: Notification)
+                         -> Result<RingConsumer<T>, RingError> {
+        Ring::attach(mem, len, ntfn).map(|ring| RingConsumer { ring: ring })
+    }
+
+    /// Take the oldest item from the ring, if there is one.
+    pub fn pop(&mut self) -> Option<T> {
+        let header = self.ring.header();
+        let tail = header.tail.load(Ordering::Relaxed);
+        let head = header.head.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+        let item = unsafe { ptr::read_volatile(self.ring.slot(tail)) };
+        header.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Some(item)
+    }
+
+    /// Ask the producer to signal the notification when it next pushes.
+    ///
+    /// Returns `true` if the ring is still empty, and it is safe to wait on the notification. If
+    /// it returns `false`, pop instead.
+    #[inline(always)]
+    pub fn arm(&self) -> bool {
+        let header = self.ring.header();
+        header.waiting.store(1, Ordering::SeqCst);
+        header.head.load(Ordering::SeqCst) == header.tail.load(Ordering::Relaxed)
+    }
+
+    /// Take the oldest item from the ring, waiting on the notification until there is one.
+    pub fn pop_wait(&mut self) -> T {
+        loop {
+            if let Some(item) = self.pop() {
+                return item;
+            }
+            if self.arm() {
+                self.ring.ntfn.wait();
+            }
+        }
+    }
+
+    /// Number of items waiting to be popped.
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    /// Whether there is nothing to pop.
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+unsafe impl<T: Copy + Send> Send for RingConsumer<T> {}
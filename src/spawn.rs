@@ -0,0 +1,383 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Starting threads in the current address space
+//!
+//! `ThreadBuilder` allocates a TCB and a notification, gives the TCB the CSpace and VSpace it is
+//! told about, and starts it running a closure. Each thread's stack and IPC buffer are taken from
+//! a `ThreadMemory`, a region the caller has mapped once along with the frames backing it, and
+//! are given back when the thread is joined or its handle dropped. Mapping is left to the caller
+//! because the builder has no way to pick free virtual addresses in the VSpace.
+//!
+//! The closure, and room for its result, are kept at the top of the new thread's stack. When the
+//! closure returns, the thread stores the result, marks itself finished, signals the
+//! notification and suspends itself. `JoinHandle::join` waits for that signal.
+
+use core::{mem, ptr};
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use sel4_sys::{seL4_CPtr, seL4_CapData, seL4_PageBits, seL4_Word};
+
+use {ARG_REGISTERS, CNode, Notification, ObjectAllocator, Owned, Thread, UserContext};
+
+/// Errors from spawning a thread.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SpawnError<E> {
+    /// The stack cannot hold the closure and its result, and still leave room to run.
+    StackTooSmall,
+    /// The `ThreadMemory` has no free run of pages for the stack, or none for the IPC buffer.
+    NoMemory,
+    /// The allocator has no free slots or memory for the TCB or notification.
+    OutOfMemory,
+    /// The allocator failed to create the TCB or notification.
+    Alloc(E),
+    /// Invoking the kernel failed.
+    Kernel(::Error),
+}
+
+/// Bytes in each page of a `ThreadMemory`.
+const PAGE_SIZE: usize = 1 << seL4_PageBits;
+
+/// Bytes of stack the new thread must have below the closure.
+const MIN_STACK: usize = 256;
+
+/// Bytes of stack threads get unless told otherwise.
+const DEFAULT_STACK_SIZE: usize = 4 * PAGE_SIZE;
+
+/// Mapped memory which threads' stacks and IPC buffers are taken from.
+///
+/// Stacks are runs of contiguous pages with no guard page between them, so a thread which
+/// overflows its stack corrupts its neighbour's.
+pub struct ThreadMemory<'a> {
+    base: usize,
+    frames: &'a [seL4_CPtr],
+    used: RefCell<&'a mut [bool]>,
+}
+
+impl<'a> ThreadMemory<'a> {
+    /// Manage `region`, whose `i`th page is mapped from `frames[i]`, recording in `used` which
+    /// pages are taken.
+    ///
+    /// Panics unless `region` is page-aligned, a whole number of pages long, and `frames` and
+    /// `used` have an entry for each of its pages.
+    pub fn new(region: &'static mut [u8], frames: &'a [seL4_CPtr], used: &'a mut [bool])
+               -> ThreadMemory<'a> {
+        let base = region.as_ptr() as usize;
+        let pages = region.len() / PAGE_SIZE;
+        assert!(base % PAGE_SIZE == 0 && region.len() % PAGE_SIZE == 0,
+                "thread memory is not page-aligned");
+        assert!(frames.len() >= pages && used.len() >= pages,
+                "thread memory needs a frame and a used flag per page");
+        for flag in used[..pages].iter_mut() {
+            *flag = false;
+        }
+        ThreadMemory {
+            base: base,
+            frames: &frames[..pages],
+            used: RefCell::new(&mut used[..pages]),
+        }
+    }
+
+    /// How many pages are free.
+    pub fn available(&self) -> usize {
+        self.used.borrow().iter().filter(|&&used| !used).count()
+    }
+
+    /// Take the first run of `count` free pages, returning the index of its first page.
+    fn allocate(&self, count: usize) -> Option<usize> {
+        let mut used = self.used.borrow_mut();
+        let mut run = 0;
+        let mut first = None;
+        for (i, &taken) in used.iter().enumerate() {
+            run = if taken { 0 } else { run + 1 };
+            if run == count {
+                first = Some(i + 1 - count);
+                break;
+            }
+        }
+        if let Some(first) = first {
+            for flag in used[first..first + count].iter_mut() {
+                *flag = true;
+            }
+        }
+        first
+    }
+
+    /// Give back the `count` pages starting at `first`.
+    fn free(&self, first: usize, count: usize) {
+        for flag in self.used.borrow_mut()[first..first + count].iter_mut() {
+            *flag = false;
+        }
+    }
+
+    /// The address of page `page`.
+    #[inline(always)]
+    fn address(&self, page: usize) -> usize {
+        self.base + page * PAGE_SIZE
+    }
+}
+
+/// What a spawned thread finds at the top of its stack.
+struct Start<F, T> {
+    f: Option<F>,
+    result: Option<T>,
+    finished: AtomicBool,
+    exit: Notification,
+    thread: Thread,
+}
+
+/// Drop the `Start` at `start`, with whatever closure or result it still holds.
+unsafe fn drop_start<F, T>(start: *mut ()) {
+    ptr::drop_in_place(start as *mut Start<F, T>);
+}
+
+/// Entry point of every spawned thread.
+extern "C" fn start<F: FnOnce() -> T, T>(start: *mut Start<F, T>) {
+    let start = unsafe { &mut *start };
+    let f = start.f.take().unwrap();
+    start.result = Some(f());
+    start.finished.store(true, Ordering::Release);
+    start.exit.signal();
+    // This never returns on a real kernel.
+    let _ = start.thread.suspend();
+}
+
+/// Registers which call the `extern "C"` function at `pc` with `arg`, on a stack whose top is
+/// `top`.
+///
+/// `top` must be 16-byte aligned. This is unsafe because the 32 bytes below it are written to.
+unsafe fn entry_context(pc: seL4_Word, top: usize, arg: seL4_Word) -> UserContext {
+    let mut regs = UserContext::new();
+    let mut sp = top;
+    if ARG_REGISTERS == 0 {
+        // Arguments on the stack start 16-byte aligned.
+        sp -= 16;
+        ptr::write(sp as *mut seL4_Word, arg);
+    } else {
+        regs.set_arg(0, arg);
+    }
+    // As if the function had just been called, with a return address of zero. Elsewhere the
+    // link register is already zero.
+    if cfg!(any(target_arch = "x86", target_arch = "x86_64")) {
+        sp -= mem::size_of::<seL4_Word>();
+        ptr::write(sp as *mut seL4_Word, 0);
+    }
+    regs.set_pc(pc);
+    regs.set_sp(sp as seL4_Word);
+    regs
+}
+
+/// Sets up and starts threads sharing the caller's address space.
+pub struct ThreadBuilder<'a, 'b: 'a, A: ObjectAllocator + 'a> {
+    alloc: &'a A,
+    memory: &'a ThreadMemory<'b>,
+    cspace_root: CNode,
+    cspace_root_data: seL4_CapData,
+    vspace_root: seL4_CPtr,
+    fault_endpoint: seL4_CPtr,
+    priority: u8,
+    stack_size: usize,
+}
+
+impl<'a, 'b: 'a, A: ObjectAllocator + 'a> ThreadBuilder<'a, 'b, A> {
+    /// Create a builder for threads in the given CSpace and VSpace, taking objects from `alloc`
+    /// and stacks and IPC buffers from `memory`.
+    ///
+    /// Threads have priority 0, a 16 KiB stack and no fault endpoint unless told otherwise.
+    pub fn new(alloc: &'a A,
+               memory: &'a ThreadMemory<'b>,
+               cspace_root: CNode,
+               vspace_root: seL4_CPtr)
+               -> ThreadBuilder<'a, 'b, A> {
+        ThreadBuilder {
+            alloc: alloc,
+            memory: memory,
+            cspace_root: cspace_root,
+            cspace_root_data: unsafe { mem::zeroed() },
+            vspace_root: vspace_root,
+            fault_endpoint: 0,
+            priority: 0,
+            stack_size: DEFAULT_STACK_SIZE,
+        }
+    }
+
+    /// Set the guard of the CSpace root. See `Thread::set_space`.
+    #[inline(always)]
+    pub fn cspace_root_data(&mut self, data: seL4_CapData) -> &mut ThreadBuilder<'a, 'b, A> {
+        self.cspace_root_data = data;
+        self
+    }
+
+    /// Set the fault endpoint, a CPtr interpreted in the thread's CSpace.
+    #[inline(always)]
+    pub fn fault_endpoint(&mut self, fault_endpoint: seL4_CPtr) -> &mut ThreadBuilder<'a, 'b, A> {
+        self.fault_endpoint = fault_endpoint;
+        self
+    }
+
+    /// Set the priority, which can be no higher than that of the thread spawning it.
+    #[inline(always)]
+    pub fn priority(&mut self, priority: u8) -> &mut ThreadBuilder<'a, 'b, A> {
+        self.priority = priority;
+        self
+    }
+
+    /// Set the stack size in bytes, which is rounded up to a whole number of pages.
+    #[inline(always)]
+    pub fn stack_size(&mut self, stack_size: usize) -> &mut ThreadBuilder<'a, 'b, A> {
+        self.stack_size = stack_size;
+        self
+    }
+
+    /// Start a thread running `f`.
+    pub fn spawn<F, T>(&self, f: F)
+                       -> Result<JoinHandle<'a, 'b, T, A>, SpawnError<A::ObjectAllocError>>
+        where F: FnOnce() -> T + Send + 'static,
+              T: Send + 'static
+    {
+        let stack_pages = (self.stack_size + PAGE_SIZE - 1) / PAGE_SIZE;
+        let align = mem::align_of::<Start<F, T>>().max(16);
+        let reserved = (mem::size_of::<Start<F, T>>() + align - 1) & !(align - 1);
+        if stack_pages * PAGE_SIZE < reserved + MIN_STACK {
+            return Err(SpawnError::StackTooSmall);
+        }
+
+        let thread = match Owned::<Thread, A>::allocate(self.alloc) {
+            Ok(Some(thread)) => thread,
+            Ok(None) => return Err(SpawnError::OutOfMemory),
+            Err(e) => return Err(SpawnError::Alloc(e)),
+        };
+        let exit = match Owned::<Notification, A>::allocate(self.alloc) {
+            Ok(Some(exit)) => exit,
+            Ok(None) => return Err(SpawnError::OutOfMemory),
+            Err(e) => return Err(SpawnError::Alloc(e)),
+        };
+        let ipc_page = match self.memory.allocate(1) {
+            Some(page) => page,
+            None => return Err(SpawnError::NoMemory),
+        };
+        let stack_page = match self.memory.allocate(stack_pages) {
+            Some(page) => page,
+            None => {
+                self.memory.free(ipc_page, 1);
+                return Err(SpawnError::NoMemory);
+            }
+        };
+
+        let start_addr = self.memory.address(stack_page + stack_pages) - reserved;
+        let start_ptr = start_addr as *mut Start<F, T>;
+        unsafe {
+            ptr::write(start_ptr,
+                       Start {
+                           f: Some(f),
+                           result: None,
+                           finished: AtomicBool::new(false),
+                           exit: *exit,
+                           thread: *thread,
+                       });
+        }
+        // From here on, dropping the handle on an error gives the pages back.
+        let handle = JoinHandle {
+            thread: thread,
+            exit: exit,
+            result: unsafe { &mut (*start_ptr).result },
+            finished: unsafe { &(*start_ptr).finished },
+            start: start_ptr as *mut (),
+            drop_start: drop_start::<F, T>,
+            memory: self.memory,
+            ipc_page: ipc_page,
+            stack_page: stack_page,
+            stack_pages: stack_pages,
+        };
+
+        let regs = unsafe {
+            entry_context(start::<F, T> as *const () as seL4_Word,
+                          start_addr,
+                          start_addr as seL4_Word)
+        };
+        handle.thread
+              .set_space(self.fault_endpoint,
+                         self.cspace_root,
+                         self.cspace_root_data,
+                         self.vspace_root,
+                         unsafe { mem::zeroed() })
+              .map_err(SpawnError::Kernel)?;
+        handle.thread
+              .set_ipc_buffer(self.memory.address(ipc_page) as seL4_Word,
+                              self.memory.frames[ipc_page])
+              .map_err(SpawnError::Kernel)?;
+        handle.thread.set_priority(self.priority).map_err(SpawnError::Kernel)?;
+        handle.thread.write_context(true, 0, &regs).map_err(SpawnError::Kernel)?;
+        Ok(handle)
+    }
+
+    /// Start a thread running `entry(arg)`.
+    #[inline(always)]
+    pub fn spawn_fn(&self, entry: fn(seL4_Word), arg: seL4_Word)
+                    -> Result<JoinHandle<'a, 'b, (), A>, SpawnError<A::ObjectAllocError>> {
+        self.spawn(move || entry(arg))
+    }
+}
+
+/// A thread started by a `ThreadBuilder`.
+///
+/// Dropping the handle, whether or not the thread was joined, destroys the thread, returning its
+/// TCB and notification to the allocator and its stack and IPC buffer to the `ThreadMemory`. A
+/// closure which never ran is dropped with it, as is a result which was never taken.
+pub struct JoinHandle<'a, 'b: 'a, T, A: ObjectAllocator + 'a> {
+    thread: Owned<'a, Thread, A>,
+    exit: Owned<'a, Notification, A>,
+    result: *mut Option<T>,
+    finished: *const AtomicBool,
+    start: *mut (),
+    drop_start: unsafe fn(*mut ()),
+    memory: &'a ThreadMemory<'b>,
+    ipc_page: usize,
+    stack_page: usize,
+    stack_pages: usize,
+}
+
+impl<'a, 'b: 'a, T, A: ObjectAllocator + 'a> JoinHandle<'a, 'b, T, A> {
+    /// The thread's TCB.
+    #[inline(always)]
+    pub fn thread(&self) -> Thread {
+        *self.thread
+    }
+
+    /// Wait for the thread to finish, returning what its closure returned.
+    pub fn join(self) -> T {
+        self.exit.wait();
+        self.take_result()
+    }
+
+    /// Return what the thread's closure returned if it has finished, or the handle if not.
+    pub fn try_join(self) -> Result<T, JoinHandle<'a, 'b, T, A>> {
+        if unsafe { !(*self.finished).load(Ordering::Acquire) } {
+            return Err(self);
+        }
+        Ok(self.take_result())
+    }
+
+    fn take_result(&self) -> T {
+        unsafe { (*self.result).take().expect("thread finished without a result") }
+    }
+}
+
+impl<'a, 'b: 'a, T, A: ObjectAllocator + 'a> Drop for JoinHandle<'a, 'b, T, A> {
+    fn drop(&mut self) {
+        // Stop the thread before its stack can be reused. The TCB itself is destroyed when
+        // `thread` is dropped.
+        let _ = self.thread.suspend();
+        // A closure which never ran, or a result which was never taken.
+        unsafe { (self.drop_start)(self.start) };
+        self.memory.free(self.ipc_page, 1);
+        self.memory.free(self.stack_page, self.stack_pages);
+    }
+}
@@ -0,0 +1,316 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Locks for threads sharing an address space
+//!
+//! `Mutex`, `Semaphore` and `Condvar` keep their state in atomics, and only make system calls
+//! when a thread has to block or wake another. Each blocks on a `Notification` of its own, which
+//! every thread using it must be able to signal and wait on.
+//!
+//! A notification wakes only one waiting thread per signal, and signals sent while nobody is
+//! waiting are merged into one. A thread woken this way passes the wakeup on if others are still
+//! owed one.
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use Notification;
+
+const UNLOCKED: usize = 0;
+const LOCKED: usize = 1;
+/// Locked, and some thread may be waiting for the notification.
+const CONTENDED: usize = 2;
+
+/// A lock protecting a `T`.
+pub struct Mutex<T> {
+    state: AtomicUsize,
+    ntfn: Notification,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for Mutex<T> {}
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    /// Create an unlocked mutex, blocking on `ntfn` under contention.
+    ///
+    /// `ntfn` should not be used for anything else.
+    #[inline(always)]
+    pub const fn new(ntfn: Notification, data: T) -> Mutex<T> {
+        Mutex {
+            state: AtomicUsize::new(UNLOCKED),
+            ntfn: ntfn,
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Take the lock, blocking until it is free.
+    pub fn lock<'a>(&'a self) -> MutexGuard<'a, T> {
+        if self.state.compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+                     .is_err() {
+            while self.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+                self.ntfn.wait();
+            }
+        }
+        MutexGuard { mutex: self }
+    }
+
+    /// Take the lock if it is free.
+    #[inline(always)]
+    pub fn try_lock<'a>(&'a self) -> Option<MutexGuard<'a, T>> {
+        if self.state.compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+                     .is_ok() {
+            Some(MutexGuard { mutex: self })
+        } else {
+            None
+        }
+    }
+
+    /// Access the data without locking, which is safe as the mutex is borrowed mutably.
+    #[inline(always)]
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.data.get() }
+    }
+
+    /// Consume the mutex, returning the data.
+    #[inline(always)]
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+
+    #[inline(always)]
+    fn unlock(&self) {
+        if self.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            self.ntfn.signal();
+        }
+    }
+}
+
+/// Access to the data of a locked `Mutex`. The lock is released when it is dropped.
+pub struct MutexGuard<'a, T: 'a> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<'a, T> Deref for MutexGuard<'a, T> {
+    type Target = T;
+
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for MutexGuard<'a, T> {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> Drop for MutexGuard<'a, T> {
+    #[inline(always)]
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
+
+/// A counting semaphore.
+pub struct Semaphore {
+    permits: AtomicUsize,
+    waiters: AtomicUsize,
+    ntfn: Notification,
+}
+
+impl Semaphore {
+    /// Create a semaphore with `permits` available, blocking on `ntfn` when there are none.
+    ///
+    /// `ntfn` should not be used for anything else.
+    #[inline(always)]
+    pub const fn new(ntfn: Notification, permits: usize) -> Semaphore {
+        Semaphore {
+            permits: AtomicUsize::new(permits),
+            waiters: AtomicUsize::new(0),
+            ntfn: ntfn,
+        }
+    }
+
+    /// Take a permit if one is available.
+    pub fn try_wait(&self) -> bool {
+        let mut permits = self.permits.load(Ordering::SeqCst);
+        while permits > 0 {
+            match self.permits.compare_exchange(permits,
+                                                permits - 1,
+                                                Ordering::SeqCst,
+                                                Ordering::SeqCst) {
+                Ok(_) => return true,
+                Err(old) => permits = old,
+            }
+        }
+        false
+    }
+
+    /// Take a permit, blocking until one is available.
+    pub fn wait(&self) {
+        loop {
+            if self.try_wait() {
+                break;
+            }
+            self.waiters.fetch_add(1, Ordering::SeqCst);
+            if self.permits.load(Ordering::SeqCst) == 0 {
+                self.ntfn.wait();
+            }
+            self.waiters.fetch_sub(1, Ordering::SeqCst);
+        }
+        // Permits posted together may have woken only this thread.
+        if self.permits.load(Ordering::SeqCst) > 0 && self.waiters.load(Ordering::SeqCst) > 0 {
+            self.ntfn.signal();
+        }
+    }
+
+    /// Return a permit, waking a waiting thread.
+    pub fn post(&self) {
+        self.permits.fetch_add(1, Ordering::SeqCst);
+        if self.waiters.load(Ordering::SeqCst) > 0 {
+            self.ntfn.signal();
+        }
+    }
+
+    /// Take a permit, blocking until one is available, and return it when the guard is dropped.
+    #[inline(always)]
+    pub fn access<'a>(&'a self) -> SemaphoreGuard<'a> {
+        self.wait();
+        SemaphoreGuard { sem: self }
+    }
+
+    /// Number of permits available.
+    #[inline(always)]
+    pub fn available(&self) -> usize {
+        self.permits.load(Ordering::SeqCst)
+    }
+}
+
+/// A permit taken from a `Semaphore`, which is returned when it is dropped.
+pub struct SemaphoreGuard<'a> {
+    sem: &'a Semaphore,
+}
+
+impl<'a> Drop for SemaphoreGuard<'a> {
+    #[inline(always)]
+    fn drop(&mut self) {
+        self.sem.post();
+    }
+}
+
+/// Lets threads wait for a condition on data protected by a `Mutex`.
+pub struct Condvar {
+    /// Incremented by every notify which wakes anyone.
+    generation: AtomicUsize,
+    waiters: AtomicUsize,
+    /// Number of waiters owed a wakeup.
+    pending: AtomicUsize,
+    ntfn: Notification,
+}
+
+impl Condvar {
+    /// Create a condition variable which blocks on `ntfn`.
+    ///
+    /// `ntfn` should not be used for anything else.
+    #[inline(always)]
+    pub const fn new(ntfn: Notification) -> Condvar {
+        Condvar {
+            generation: AtomicUsize::new(0),
+            waiters: AtomicUsize::new(0),
+            pending: AtomicUsize::new(0),
+            ntfn: ntfn,
+        }
+    }
+
+    /// Take one owed wakeup, if there are any, passing the signal on if more are owed.
+    fn claim(&self) -> bool {
+        let mut pending = self.pending.load(Ordering::SeqCst);
+        while pending > 0 {
+            match self.pending.compare_exchange(pending,
+                                                pending - 1,
+                                                Ordering::SeqCst,
+                                                Ordering::SeqCst) {
+                Ok(_) => {
+                    if pending > 1 {
+                        self.ntfn.signal();
+                    }
+                    return true;
+                }
+                Err(old) => pending = old,
+            }
+        }
+        false
+    }
+
+    /// Release the lock held by `guard` and block until notified, then take the lock again.
+    ///
+    /// Like any condition variable, this can return without the condition having changed, so it
+    /// should be called in a loop. See `wait_while`.
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex;
+        let generation = self.generation.load(Ordering::SeqCst);
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        drop(guard);
+        // Only a notify made since we started waiting is ours to claim.
+        while self.generation.load(Ordering::SeqCst) == generation || !self.claim() {
+            self.ntfn.wait();
+            if self.generation.load(Ordering::SeqCst) != generation && self.claim() {
+                break;
+            }
+            // The signal was sent before we started waiting, for a waiter which has not yet
+            // taken its wakeup. Pass it on, and return rather than take it back ourselves.
+            if self.pending.load(Ordering::SeqCst) > 0 {
+                self.ntfn.signal();
+                break;
+            }
+        }
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+        mutex.lock()
+    }
+
+    /// Wait until `condition` returns `false`, checking it with the lock held.
+    pub fn wait_while<'a, T, F>(&self, mut guard: MutexGuard<'a, T>, mut condition: F)
+                                -> MutexGuard<'a, T>
+        where F: FnMut(&mut T) -> bool
+    {
+        while condition(&mut *guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    /// Wake one waiting thread.
+    pub fn notify_one(&self) {
+        let waiters = self.waiters.load(Ordering::SeqCst);
+        if waiters == 0 {
+            return;
+        }
+        if waiters > self.pending.load(Ordering::SeqCst) {
+            self.pending.fetch_add(1, Ordering::SeqCst);
+        }
+        // Always move the generation on, so that a wakeup owed to a waiter which has since
+        // returned can be claimed by one waiting now.
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.ntfn.signal();
+    }
+
+    /// Wake every waiting thread.
+    pub fn notify_all(&self) {
+        let waiters = self.waiters.load(Ordering::SeqCst);
+        if waiters > 0 {
+            self.generation.fetch_add(1, Ordering::SeqCst);
+            self.pending.store(waiters, Ordering::SeqCst);
+            self.ntfn.signal();
+        }
+    }
+}
@@ -13,7 +13,7 @@ use sel4_sys::{seL4_CPtr, seL4_CapData, seL4_DomainSet_Set, seL4_PrioProps, seL4
                seL4_TCB_SetPriority, seL4_TCB_SetSpace, seL4_TCB_Suspend,
                seL4_TCB_UnbindNotification, seL4_TCB_WriteRegisters, seL4_UserContext, seL4_Word};
 
-use {CNode, Notification, ToCap};
+use {CNode, Notification, RegisterSet, ToCap, UserContext};
 
 cap_wrapper!{ ()
     /// A thread control block
@@ -99,6 +99,21 @@ impl Thread {
         ))
     }
 
+    /// Copy the registers in `registers` from this thread to `dest`.
+    ///
+    /// Like `copy_registers`, with the registers to transfer given as a `RegisterSet`.
+    #[inline(always)]
+    pub fn copy_register_set(&self, dest: Thread, suspend_source: bool, resume_dest: bool,
+                             registers: RegisterSet, arch_flags: u8)
+                             -> ::Result {
+        self.copy_registers(dest,
+                            suspend_source,
+                            resume_dest,
+                            registers.frame(),
+                            registers.integer(),
+                            arch_flags)
+    }
+
     /// Read this thread's registers.
     ///
     /// If `suspend`, suspend this thread before copying.
@@ -118,6 +133,12 @@ impl Thread {
         )).map(|()| regs)
     }
 
+    /// Read this thread's registers as a `UserContext`. See `read_registers`.
+    #[inline(always)]
+    pub fn read_context(&self, suspend: bool, arch_flags: u8) -> Result<UserContext, ::Error> {
+        self.read_registers(suspend, arch_flags).map(UserContext::from_raw)
+    }
+
     /// Resume this thread
     #[inline(always)]
     pub fn resume(&self) -> ::Result {
@@ -188,6 +209,12 @@ impl Thread {
         ))
     }
 
+    /// Write this thread's registers from a `UserContext`. See `write_registers`.
+    #[inline(always)]
+    pub fn write_context(&self, resume: bool, arch_flags: u8, regs: &UserContext) -> ::Result {
+        self.write_registers(resume, arch_flags, regs.raw())
+    }
+
     /// Set this thread's domain.
     #[inline(always)]
     pub fn set_domain(&self, domain: u8, domain_control: ::DomainSet) -> ::Result {
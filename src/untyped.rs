@@ -0,0 +1,136 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Untyped memory and retyping it into kernel objects.
+//!
+//! All memory available to userspace starts out as untyped memory. The only operation on it is
+//! "retype", which carves out new kernel objects (including smaller untyped objects) and places
+//! capabilities to them into a CNode. The new capabilities are children of the untyped capability
+//! in the capability derivation tree, so revoking the untyped capability deletes all of them and
+//! makes the memory available again.
+
+use sel4_sys::{seL4_UntypedObject, seL4_Untyped_Retype, seL4_Word};
+
+use {Allocatable, CNode, SlotRef, ToCap, Window};
+use CONFIG_RETYPE_FAN_OUT_LIMIT;
+
+cap_wrapper!{ ()
+    /// Untyped memory, which can be retyped into other kernel objects
+    Untyped = seL4_UntypedObject |i| (1 as seL4_Word) << i,
+}
+
+/// An untyped capability together with the physical memory it covers.
+///
+/// The kernel does not let userspace ask an untyped capability what memory it refers to, so this
+/// information has to be recorded when the capability is handed out (usually from the `BootInfo`)
+/// and carried along with it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct UntypedDescriptor {
+    /// The untyped capability.
+    pub cap: Untyped,
+    /// Physical address of the first byte of the region.
+    pub paddr: seL4_Word,
+    /// The region is 2^size_bits bytes long.
+    pub size_bits: u8,
+    /// Whether this region is device memory.
+    ///
+    /// Device untypeds can only be retyped into frames, and those frames are not zeroed by the
+    /// kernel.
+    pub is_device: bool,
+}
+
+impl UntypedDescriptor {
+    /// Size of the region in bytes.
+    #[inline(always)]
+    pub fn size(&self) -> seL4_Word {
+        (1 as seL4_Word) << self.size_bits
+    }
+
+    /// Physical address of the first byte past the end of the region.
+    #[inline(always)]
+    pub fn end_paddr(&self) -> seL4_Word {
+        self.paddr.wrapping_add(self.size())
+    }
+
+    /// Whether the physical address `paddr` lies inside this region.
+    #[inline(always)]
+    pub fn contains(&self, paddr: seL4_Word) -> bool {
+        paddr >= self.paddr && paddr < self.end_paddr()
+    }
+}
+
+impl Untyped {
+    /// Retype this untyped memory into objects of type `T`, storing the capabilities into `dest`.
+    ///
+    /// One object is created for each slot in `dest`. `size_bits` is only meaningful for objects
+    /// which have a variable size, such as `CNode` and `Untyped`.
+    #[inline(always)]
+    pub fn retype<T: Allocatable>(&self, dest: Window, size_bits: seL4_Word) -> ::Result {
+        T::create(*self, dest, size_bits)
+    }
+
+    /// Retype this untyped memory into objects with the raw object type `objtype`.
+    ///
+    /// This issues as many retype invocations as needed to fill `dest`.
+    pub fn retype_raw(&self, objtype: seL4_Word, size_bits: seL4_Word, mut dest: Window)
+                      -> ::Result {
+        // Most we can create in one syscall is CONFIG_RETYPE_FAN_OUT_LIMIT (256)
+        while dest.num_slots > CONFIG_RETYPE_FAN_OUT_LIMIT {
+            unsafe_as_result!(seL4_Untyped_Retype(
+                self.cptr,
+                objtype,
+                size_bits,
+                dest.cnode.root.to_cap(),
+                dest.cnode.cptr,
+                dest.cnode.depth as seL4_Word,
+                dest.first_slot_idx,
+                CONFIG_RETYPE_FAN_OUT_LIMIT,
+            ))?;
+            dest.first_slot_idx += CONFIG_RETYPE_FAN_OUT_LIMIT;
+            dest.num_slots -= CONFIG_RETYPE_FAN_OUT_LIMIT;
+        }
+
+        if dest.num_slots > 0 {
+            unsafe_as_result!(seL4_Untyped_Retype(
+                self.cptr,
+                objtype,
+                size_bits,
+                dest.cnode.root.to_cap(),
+                dest.cnode.cptr,
+                dest.cnode.depth as seL4_Word,
+                dest.first_slot_idx,
+                dest.num_slots,
+            ))?;
+        }
+
+        Ok(())
+    }
+
+    /// Attach physical memory information to this capability.
+    #[inline(always)]
+    pub fn describe(&self, paddr: seL4_Word, size_bits: u8, is_device: bool)
+                    -> UntypedDescriptor {
+        UntypedDescriptor {
+            cap: *self,
+            paddr: paddr,
+            size_bits: size_bits,
+            is_device: is_device,
+        }
+    }
+
+    /// Delete every object created from this untyped memory.
+    ///
+    /// This revokes the slot holding this capability, which is looked up in `root` at full depth.
+    /// Afterwards the whole region is available for retyping again.
+    #[inline(always)]
+    pub fn revoke_all(&self, root: CNode) -> ::Result {
+        let depth = ::core::mem::size_of::<seL4_Word>() * 8;
+        SlotRef::new(root, self.cptr, depth as u8).revoke()
+    }
+}
@@ -0,0 +1,132 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+extern crate sel4;
+extern crate sel4_sys;
+
+#[macro_use]
+mod common;
+
+use sel4::{BitmapSlotAllocator, BuddyAllocator, Endpoint, Notification, ObjectAllocator, Owned,
+           SlotAllocError, SlotAllocator, ToCap, UntypedAllocError, bitmap_words, mock};
+use sel4::mock::ObjectKind;
+
+#[test]
+fn slots_are_distinct_and_reusable() {
+    let bi = common::boot();
+    let mut bitmap = [0; 64];
+    assert!(bitmap_words(bi.empty_slots().num_slots) <= bitmap.len());
+    let slots = BitmapSlotAllocator::new(bi.empty_slots(), bi.root_cnode_info(), &mut bitmap)
+        .unwrap();
+    let total = slots.available();
+    assert_eq!(total, bi.empty_slots().num_slots);
+
+    let a = slots.allocate_slot().unwrap();
+    let b = slots.allocate_slot().unwrap();
+    assert!(a != b);
+    assert_eq!(slots.available(), total - 2);
+
+    slots.free_slot(a).unwrap();
+    assert_eq!(slots.free_slot(a), Err(SlotAllocError::NotAllocated));
+    assert_eq!(slots.allocate_slot(), Some(a));
+
+    // The same, through the trait other allocators take their slots from.
+    fn cycle<S: SlotAllocator>(slots: &S) -> bool {
+        match slots.allocate_slot() {
+            Some(slot) => slots.free_slot(slot).is_ok(),
+            None => false,
+        }
+    }
+    assert!(cycle(&slots));
+    assert_eq!(slots.available(), total - 2);
+}
+
+#[test]
+fn slot_ranges() {
+    let bi = common::boot();
+    let mut bitmap = [0; 64];
+    let slots = BitmapSlotAllocator::new(bi.empty_slots(), bi.root_cnode_info(), &mut bitmap)
+        .unwrap();
+
+    let single = slots.allocate_slot().unwrap();
+    let (window, _) = slots.allocate_range(4).unwrap();
+    assert_eq!(window.num_slots, 4);
+    assert_eq!(slots.window_for(&single).unwrap().num_slots, 1);
+
+    slots.free_range(window).unwrap();
+    assert_eq!(slots.free_range(window), Err(SlotAllocError::NotAllocated));
+}
+
+#[test]
+fn untyped_allocator_reuses_memory() {
+    let bi = common::boot();
+    untyped_allocator!(bi, slots, alloc);
+    let desc = bi.untyped().next().unwrap();
+    let total = alloc.available();
+
+    let a = alloc.allocate_slot().unwrap();
+    let b = alloc.allocate_slot().unwrap();
+    let ep: Endpoint = alloc.allocate_object(a).unwrap().unwrap();
+    let ntfn: Notification = alloc.allocate_object(b).unwrap().unwrap();
+    assert_eq!(mock::object_at(ep.to_cap()), Some(ObjectKind::Endpoint));
+    assert_eq!(mock::object_at(ntfn.to_cap()), Some(ObjectKind::Notification));
+    assert_eq!(alloc.available(), total - 32);
+
+    alloc.free_object(ep).unwrap();
+    assert_eq!(mock::object_at(a.cptr), None);
+    assert_eq!(alloc.free_object(ep), Err(UntypedAllocError::NotOwned));
+
+    alloc.free_object(ntfn).unwrap();
+    assert_eq!(alloc.available(), total);
+    assert_eq!(mock::untyped_watermark(desc.cap.to_cap()), Some(0));
+}
+
+#[test]
+fn owned_frees_on_drop() {
+    let bi = common::boot();
+    untyped_allocator!(bi, slots, alloc);
+    let total = slots.available();
+
+    let cptr = {
+        let ep = Owned::<Endpoint, _>::allocate(&alloc).unwrap().unwrap();
+        assert_eq!(slots.available(), total - 1);
+        ep.to_cap()
+    };
+    assert_eq!(mock::object_at(cptr), None);
+    assert_eq!(slots.available(), total);
+}
+
+#[test]
+fn buddy_allocator_merges_blocks() {
+    let bi = common::boot();
+    let mut bitmap = [0; 64];
+    let slots = BitmapSlotAllocator::new(bi.empty_slots(), bi.root_cnode_info(), &mut bitmap)
+        .unwrap();
+    let mut blocks = [None; 64];
+    let alloc = BuddyAllocator::new(&slots, bi.root_cnode(), &mut blocks);
+    let desc = bi.untyped().nth(1).unwrap();
+    alloc.add_untyped(desc).unwrap();
+    let free_slots = slots.available();
+
+    let slot = alloc.allocate_slot().unwrap();
+    let ep: Endpoint = alloc.allocate_object(slot).unwrap().unwrap();
+    let stats = alloc.stats();
+    assert_eq!(stats.total_bytes, desc.size());
+    assert_eq!(stats.allocated_bytes, 16);
+    assert_eq!(stats.free_bytes, desc.size() - 16);
+    assert_eq!(stats.internal_fragmentation(), 0);
+
+    alloc.free_object(ep).unwrap();
+    alloc.free_slot(slot).unwrap();
+    let stats = alloc.stats();
+    assert_eq!(stats.free_bytes, desc.size());
+    assert_eq!(stats.free_blocks, 1);
+    assert_eq!(stats.external_fragmentation(), 0);
+    assert_eq!(slots.available(), free_slots);
+}
@@ -0,0 +1,101 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+extern crate sel4;
+extern crate sel4_sys;
+
+mod common;
+
+use sel4_sys::seL4_Word;
+
+use sel4::{BadgeAllocator, BadgeDispatcher, BadgeError, BadgedClient, CapRights, Endpoint,
+           MAX_BADGE, RecvToken, ToCap, mock};
+
+#[test]
+fn mints_distinct_badges() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+    let mut storage: [Option<BadgedClient<u32>>; 2] = [None, None];
+    let mut badges = BadgeAllocator::new(common::slot(&bi, 0), 10, &mut storage).unwrap();
+
+    let a = badges.mint(common::slot(&bi, 1), CapRights::ALL, 100).unwrap();
+    let b = badges.mint(common::slot(&bi, 2), CapRights::ALL, 200).unwrap();
+    assert_eq!(mock::badge_at(common::slot(&bi, 1).cptr), Some(10));
+    assert_eq!(mock::badge_at(common::slot(&bi, 2).cptr), Some(11));
+    assert!(mock::same_object(ep.to_cap(), common::slot(&bi, 1).cptr));
+    assert_eq!(a.get_value(), 10);
+    assert_eq!(b.get_value(), 11);
+
+    assert_eq!(badges.get(11).unwrap().data, 200);
+    assert!(badges.get(12).is_none());
+    assert!(badges.get(0).is_none());
+    assert_eq!(badges.mint(common::slot(&bi, 3), CapRights::ALL, 300).unwrap_err(),
+               BadgeError::Exhausted);
+}
+
+#[test]
+fn rejects_invalid_ranges() {
+    let bi = common::boot();
+    let _: Endpoint = common::create(&bi, 0);
+    let mut storage: [Option<BadgedClient<()>>; 2] = [None, None];
+    assert_eq!(BadgeAllocator::new(common::slot(&bi, 0), 0, &mut storage).err(),
+               Some(BadgeError::InvalidRange));
+    assert_eq!(BadgeAllocator::new(common::slot(&bi, 0), MAX_BADGE, &mut storage).err(),
+               Some(BadgeError::InvalidRange));
+    assert!(BadgeAllocator::new(common::slot(&bi, 0), MAX_BADGE - 1, &mut storage).is_ok());
+}
+
+#[test]
+fn dispatch_routes_by_badge() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+    let mut first = |token: &RecvToken| token.badge * 10;
+    let mut second = |token: &RecvToken| token.badge * 100;
+    let mut storage: [Option<BadgedClient<&mut dyn FnMut(&RecvToken) -> seL4_Word>>; 2] =
+        [None, None];
+    let mut dispatcher =
+        BadgeDispatcher::new(BadgeAllocator::new(common::slot(&bi, 0), 1, &mut storage).unwrap());
+    dispatcher.add(common::slot(&bi, 1), CapRights::ALL, &mut first).unwrap();
+    dispatcher.add(common::slot(&bi, 2), CapRights::ALL, &mut second).unwrap();
+
+    Endpoint::from_cap(common::slot(&bi, 2).cptr).send_data(&[]).unwrap();
+    Endpoint::from_cap(common::slot(&bi, 1).cptr).send_data(&[]).unwrap();
+    ep.send_data(&[]).unwrap();
+
+    assert_eq!(dispatcher.dispatch(&ep.recv()), Some(200));
+    assert_eq!(dispatcher.dispatch(&ep.recv()), Some(10));
+    assert_eq!(dispatcher.dispatch(&ep.recv()), None);
+}
+
+#[test]
+fn revoke_cancels_pending_messages() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+    let mut storage: [Option<BadgedClient<&'static str>>; 2] = [None, None];
+    let mut badges = BadgeAllocator::new(common::slot(&bi, 0), 1, &mut storage).unwrap();
+    let gone = badges.mint(common::slot(&bi, 1), CapRights::ALL, "gone").unwrap();
+    badges.mint(common::slot(&bi, 2), CapRights::ALL, "stays").unwrap();
+    common::slot(&bi, 1).copy(common::slot(&bi, 3), CapRights::ALL).unwrap();
+
+    Endpoint::from_cap(common::slot(&bi, 3).cptr).send_data(&[1]).unwrap();
+    Endpoint::from_cap(common::slot(&bi, 2).cptr).send_data(&[2]).unwrap();
+    Endpoint::from_cap(common::slot(&bi, 1).cptr).send_data(&[3]).unwrap();
+    assert_eq!(mock::pending_messages(ep.to_cap()), 3);
+
+    assert_eq!(badges.revoke(gone.get_value() as seL4_Word), Ok("gone"));
+    assert_eq!(mock::pending_messages(ep.to_cap()), 1);
+    assert_eq!(mock::object_at(common::slot(&bi, 1).cptr), None);
+    assert_eq!(mock::object_at(common::slot(&bi, 3).cptr), None);
+    assert_eq!(ep.recv().badge, 2);
+    assert_eq!(badges.revoke(gone.get_value() as seL4_Word), Err(BadgeError::NotAllocated));
+
+    assert_eq!(badges.available(), 1);
+    let reused = badges.mint(common::slot(&bi, 1), CapRights::ALL, "new").unwrap();
+    assert_eq!(reused.get_value(), gone.get_value());
+}
@@ -0,0 +1,112 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+extern crate sel4;
+extern crate sel4_sys;
+
+mod common;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use sel4::{BulkError, BulkReceiver, BulkSender, Endpoint, ToCap, bulk_inline_bytes, mock};
+use sel4::mock::Message;
+use sel4_sys::seL4_Word;
+
+const LABEL: seL4_Word = 7;
+const REJECTED: seL4_Word = 0xf_ffff;
+
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 % 251) as u8).collect()
+}
+
+#[test]
+fn small_transfers_are_inline() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+    let data = pattern(bulk_inline_bytes());
+    let mut out = vec![0; data.len()];
+    let sender = unsafe { BulkSender::new(ep, LABEL, std::ptr::null_mut(), 0) };
+    let mut receiver = unsafe { BulkReceiver::new(LABEL, std::ptr::null(), 0) };
+
+    sender.send(&data).unwrap();
+    assert_eq!(mock::pending_messages(ep.to_cap()), 1);
+    assert_eq!(receiver.accept(&ep.recv(), &mut out), Ok(Some(data.len())));
+    assert_eq!(out, data);
+
+    assert_eq!(sender.send(&pattern(bulk_inline_bytes() + 1)), Err(BulkError::TooLarge));
+}
+
+#[test]
+fn large_transfers_are_chunked() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+    let mut frame = vec![0u8; 1024];
+    let shared = frame.as_mut_ptr() as usize;
+    let got = Rc::new(RefCell::new(Vec::new()));
+    let seen = got.clone();
+    mock::set_call_handler(ep.to_cap(), move |msg: Message| {
+        assert_eq!(msg.label, LABEL);
+        assert_eq!(msg.data[1] as usize, seen.borrow().len());
+        let chunk = unsafe { std::slice::from_raw_parts(shared as *const u8, msg.data[2] as _) };
+        seen.borrow_mut().extend_from_slice(chunk);
+        Message::new(0, &[])
+    });
+
+    let data = pattern(3000);
+    let sender = unsafe { BulkSender::new(ep, LABEL, frame.as_mut_ptr(), frame.len()) };
+    sender.send(&data).unwrap();
+    assert_eq!(*got.borrow(), data);
+
+    mock::set_call_handler(ep.to_cap(), |_| Message::new(REJECTED, &[]));
+    assert_eq!(sender.send(&data), Err(BulkError::Rejected));
+}
+
+#[test]
+fn failed_calls_are_kernel_errors() {
+    let bi = common::boot();
+    let ep = Endpoint::from_cap(common::slot(&bi, 0).cptr);
+    let mut frame = vec![0u8; 1024];
+    let sender = unsafe { BulkSender::new(ep, LABEL, frame.as_mut_ptr(), frame.len()) };
+
+    match sender.send(&pattern(3000)) {
+        Err(BulkError::Kernel(_)) => {}
+        other => panic!("expected a kernel error, got {:?}", other),
+    }
+}
+
+#[test]
+fn receiver_reassembles_chunks() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+    let mut frame = vec![0u8; 1024];
+    let data = pattern(2500);
+    let mut out = vec![0; data.len()];
+    let mut receiver = unsafe { BulkReceiver::new(LABEL, frame.as_ptr(), frame.len()) };
+
+    for (i, chunk) in data.chunks(frame.len()).enumerate() {
+        frame[..chunk.len()].copy_from_slice(chunk);
+        let header = [data.len() as seL4_Word, (i * 1024) as _, chunk.len() as _];
+        let call = mock::queue_call(ep.to_cap(), Message::new(LABEL, &header));
+        let done = receiver.accept(&ep.recv(), &mut out).unwrap();
+        assert_eq!(done, if i == 2 { Some(data.len()) } else { None });
+        assert_eq!(mock::take_reply(call).unwrap().label, 0);
+    }
+    assert_eq!(out, data);
+
+    let header = [data.len() as seL4_Word, 1024, 1024];
+    let call = mock::queue_call(ep.to_cap(), Message::new(LABEL, &header));
+    assert_eq!(receiver.accept(&ep.recv(), &mut out), Err(BulkError::Malformed));
+    assert_eq!(mock::take_reply(call).unwrap().label, REJECTED);
+
+    let header = [data.len() as seL4_Word, 0, 1024];
+    let call = mock::queue_call(ep.to_cap(), Message::new(LABEL, &header));
+    assert_eq!(receiver.accept(&ep.recv(), &mut out[..2000]), Err(BulkError::TooLarge));
+    assert_eq!(mock::take_reply(call).unwrap().label, REJECTED);
+}
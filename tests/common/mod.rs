@@ -0,0 +1,62 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+#![allow(dead_code, unused_macros)]
+
+use sel4_sys::seL4_Word;
+
+use sel4::{Allocatable, BootInfo, SlotRef, Window, mock};
+
+/// Bind `$slots` to a `BitmapSlotAllocator` over the empty slots of `$bi`, and `$alloc` to an
+/// `UntypedAllocator` using them, with the first untyped added.
+///
+/// This is a macro so that the storage both borrow lives in the test itself.
+macro_rules! untyped_allocator {
+    ($bi:ident, $slots:ident, $alloc:ident) => {
+        let mut bitmap = [0; 64];
+        let $slots = ::sel4::BitmapSlotAllocator::new($bi.empty_slots(),
+                                                      $bi.root_cnode_info(),
+                                                      &mut bitmap)
+            .unwrap();
+        let mut regions = [None; 4];
+        let mut allocations = [None; 16];
+        let $alloc = ::sel4::UntypedAllocator::new(&$slots,
+                                                   $bi.root_cnode(),
+                                                   &mut regions,
+                                                   &mut allocations);
+        $alloc.add_untyped($bi.untyped().next().unwrap()).unwrap();
+    }
+}
+
+/// Boot a fresh simulated kernel for this test.
+pub fn boot() -> BootInfo {
+    unsafe { BootInfo::from_raw(mock::boot()) }
+}
+
+/// The `i`th empty slot of the root CNode.
+pub fn slot(bi: &BootInfo, i: usize) -> SlotRef {
+    bi.empty_slots().slotref_to(&bi.root_cnode_info(), i).unwrap()
+}
+
+/// Create an object in the `i`th empty slot of the root CNode, from the first untyped.
+pub fn create<T: Allocatable>(bi: &BootInfo, i: usize) -> T {
+    create_sized(bi, i, 0)
+}
+
+/// Like `create`, for objects whose size is given by `size_bits`.
+pub fn create_sized<T: Allocatable>(bi: &BootInfo, i: usize, size_bits: seL4_Word) -> T {
+    let empty = bi.empty_slots();
+    let window = Window {
+        cnode: empty.cnode,
+        first_slot_idx: empty.first_slot_idx + i,
+        num_slots: 1,
+    };
+    bi.untyped().next().unwrap().cap.retype::<T>(window, size_bits).unwrap();
+    T::from_cap(slot(bi, i).cptr)
+}
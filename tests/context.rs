@@ -0,0 +1,80 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+extern crate sel4;
+extern crate sel4_sys;
+
+mod common;
+
+use sel4::{ARG_REGISTERS, RegisterSet, Thread, ToCap, UserContext, mock};
+
+#[cfg(target_arch = "x86_64")]
+#[test]
+fn accessors_name_registers_by_role() {
+    let mut regs = UserContext::new();
+    regs.set_pc(0x1000);
+    regs.set_sp(0x2000);
+    regs.set_tls_base(0x3000);
+    for n in 0..ARG_REGISTERS {
+        regs.set_arg(n, 10 + n);
+    }
+    assert_eq!((regs.pc(), regs.sp(), regs.tls_base()), (0x1000, 0x2000, 0x3000));
+    assert_eq!(regs.arg(ARG_REGISTERS - 1), 10 + ARG_REGISTERS - 1);
+
+    // The raw context is still there for anything without a portable name.
+    assert_eq!(regs.raw().rip, 0x1000);
+    assert_eq!(regs.raw().rdi, 10);
+    regs.raw_mut().rax = 7;
+    assert_eq!(regs.return_value(), 7);
+}
+
+#[test]
+#[should_panic(expected = "not passed in a register")]
+fn stack_arguments_panic() {
+    UserContext::new().set_arg(ARG_REGISTERS, 0);
+}
+
+#[test]
+fn registers_round_trip_through_the_kernel() {
+    let bi = common::boot();
+    let source: Thread = common::create(&bi, 0);
+    let dest: Thread = common::create(&bi, 1);
+    let mut regs = UserContext::new();
+    regs.set_pc(0x1000);
+    regs.set_tls_base(0x3000);
+    regs.set_return_value(5);
+
+    source.write_context(false, 0, &regs).unwrap();
+    let read = source.read_context(false, 0).unwrap();
+    assert_eq!((read.pc(), read.return_value(), read.tls_base()), (0x1000, 5, 0x3000));
+    let raw = source.read_registers(false, 0).unwrap();
+    assert_eq!(UserContext::from_raw(raw).pc(), 0x1000);
+
+    // Only the frame registers are copied, and the TLS base is not one of them.
+    source.copy_register_set(dest, true, true, RegisterSet::FRAME, 0).unwrap();
+    let copied = mock::registers(dest.to_cap()).unwrap();
+    assert_eq!((copied.pc(), copied.return_value(), copied.tls_base()), (0x1000, 5, 0));
+    assert!(!mock::thread_state(source.to_cap()).unwrap().running);
+    assert!(mock::thread_state(dest.to_cap()).unwrap().running);
+
+    source.copy_registers(dest, false, false, true, true, 0).unwrap();
+    assert_eq!(mock::registers(dest.to_cap()).unwrap().tls_base(), 0x3000);
+}
+
+#[test]
+fn register_sets_combine() {
+    let all = RegisterSet::FRAME | RegisterSet::INTEGER;
+    assert_eq!(all, RegisterSet::ALL);
+    assert!(all.frame() && all.integer());
+    assert!(all.contains(RegisterSet::FRAME));
+    assert!(!RegisterSet::FRAME.contains(all));
+    assert_eq!(all - RegisterSet::FRAME, RegisterSet::INTEGER);
+    assert_eq!(RegisterSet::FRAME & RegisterSet::INTEGER, RegisterSet::NONE);
+    assert!(!RegisterSet::NONE.frame() && !RegisterSet::NONE.integer());
+}
@@ -0,0 +1,148 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+extern crate sel4;
+extern crate sel4_sys;
+
+mod common;
+
+use sel4::{Badge, CNode, CNodeInfo, CSpace, CSpaceError, CapRights, Endpoint, ErrorDetails, ToCap,
+           mock};
+use sel4::mock::ObjectKind;
+
+#[test]
+fn copy_and_delete() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+    let src = common::slot(&bi, 0);
+    let dest = common::slot(&bi, 1);
+
+    src.copy(dest, CapRights::ALL).unwrap();
+    assert!(mock::same_object(ep.to_cap(), dest.cptr));
+
+    dest.delete().unwrap();
+    assert_eq!(mock::object_at(dest.cptr), None);
+    assert_eq!(mock::object_at(src.cptr), Some(ObjectKind::Endpoint));
+}
+
+#[test]
+fn mint_applies_badge_and_rights() {
+    let bi = common::boot();
+    let _: Endpoint = common::create(&bi, 0);
+    let dest = common::slot(&bi, 1);
+
+    common::slot(&bi, 0).mint(dest, CapRights::W, Badge::new(7)).unwrap();
+    assert_eq!(mock::badge_at(dest.cptr), Some(7));
+    assert_eq!(mock::rights_at(dest.cptr), Some((false, true, false)));
+}
+
+#[test]
+fn copy_into_occupied_slot() {
+    let bi = common::boot();
+    let _: Endpoint = common::create(&bi, 0);
+    let _: Endpoint = common::create(&bi, 1);
+
+    let err = common::slot(&bi, 0).copy(common::slot(&bi, 1), CapRights::ALL).unwrap_err();
+    assert_eq!(err.details(), Some(ErrorDetails::DeleteFirst));
+}
+
+#[test]
+fn move_clears_source() {
+    let bi = common::boot();
+    let _: Endpoint = common::create(&bi, 0);
+    let src = common::slot(&bi, 0);
+    let dest = common::slot(&bi, 1);
+
+    src.move_(dest).unwrap();
+    assert_eq!(mock::object_at(src.cptr), None);
+    assert_eq!(mock::object_at(dest.cptr), Some(ObjectKind::Endpoint));
+}
+
+#[test]
+fn revoke_deletes_children() {
+    let bi = common::boot();
+    let _: Endpoint = common::create(&bi, 0);
+    let src = common::slot(&bi, 0);
+    let child = common::slot(&bi, 1);
+    let grandchild = common::slot(&bi, 2);
+
+    src.copy(child, CapRights::ALL).unwrap();
+    child.copy(grandchild, CapRights::ALL).unwrap();
+    src.revoke().unwrap();
+
+    assert_eq!(mock::object_at(src.cptr), Some(ObjectKind::Endpoint));
+    assert_eq!(mock::object_at(child.cptr), None);
+    assert_eq!(mock::object_at(grandchild.cptr), None);
+}
+
+#[test]
+fn typed_slot_tracks_the_capability() {
+    let bi = common::boot();
+    let _: Endpoint = common::create(&bi, 0);
+    let typed = common::slot(&bi, 0).typed::<Endpoint>();
+
+    let moved = typed.move_(common::slot(&bi, 1)).unwrap();
+    assert_eq!(moved.cap().to_cap(), common::slot(&bi, 1).cptr);
+    assert_eq!(mock::object_at(moved.cap().to_cap()), Some(ObjectKind::Endpoint));
+
+    let empty = moved.delete().unwrap();
+    assert_eq!(mock::object_at(empty.cptr), None);
+}
+
+#[test]
+fn two_level_cspace() {
+    let bi = common::boot();
+    let root: CNode = common::create_sized(&bi, 0, 4);
+    let _: CNode = common::create_sized(&bi, 1, 4);
+    let ep: Endpoint = common::create(&bi, 2);
+
+    let info = CNodeInfo {
+        guard_val: 0,
+        radix_bits: 4,
+        guard_bits: 0,
+        prefix_bits: 0,
+    };
+    let mut storage = [None; 4];
+    let mut cspace = CSpace::new(root, info, &mut storage);
+    let src = common::slot(&bi, 1);
+    let child = cspace.link(0, 2, src, CNode::from_cap(src.cptr), 4, 3, 5).unwrap();
+    assert_eq!(cspace.child(0, 2), Some(child));
+
+    let slot = cspace.resolve(&[2, 9]).unwrap();
+    assert_eq!(slot.depth, 11);
+    assert_eq!(slot.cptr, (((2 << 3) | 5) << 4) | 9);
+
+    common::slot(&bi, 2).copy(slot, CapRights::ALL).unwrap();
+    slot.copy(common::slot(&bi, 3), CapRights::ALL).unwrap();
+    assert!(mock::same_object(ep.to_cap(), common::slot(&bi, 3).cptr));
+
+    assert_eq!(cspace.resolve(&[2, 16]), None);
+    assert_eq!(cspace.resolve(&[3, 0]), None);
+}
+
+#[test]
+fn word_wide_radix_is_rejected() {
+    let bi = common::boot();
+    let root: CNode = common::create_sized(&bi, 0, 4);
+    let info = CNodeInfo {
+        guard_val: 0,
+        radix_bits: 4,
+        guard_bits: 0,
+        prefix_bits: 0,
+    };
+    let mut storage = [None; 4];
+    let mut cspace = CSpace::new(root, info, &mut storage);
+    let src = common::slot(&bi, 1);
+    let word_bits = 8 * std::mem::size_of::<sel4_sys::seL4_Word>() as u8;
+    assert_eq!(cspace.link(0, 2, src, CNode::from_cap(src.cptr), word_bits, 0, 0),
+               Err(CSpaceError::TooDeep));
+    assert_eq!(cspace.link(0, 2, src, CNode::from_cap(src.cptr), 4, word_bits, 0),
+               Err(CSpaceError::TooDeep));
+    assert_eq!(cspace.resolve(&[!0, 0]), None);
+}
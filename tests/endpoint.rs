@@ -0,0 +1,222 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+extern crate sel4;
+extern crate sel4_sys;
+
+mod common;
+
+use sel4::{Badge, CapRights, Endpoint, ErrorDetails, Notification, Reply, ServeAction, ToCap,
+           mock};
+use sel4::mock::{Message, ObjectKind};
+use sel4_sys::seL4_MsgMaxLength;
+
+#[test]
+fn send_then_recv() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+
+    ep.send_data(&[1, 2, 3]).unwrap();
+    assert_eq!(mock::pending_messages(ep.to_cap()), 1);
+
+    let token = ep.recv();
+    assert_eq!(token.badge, 0);
+    assert_eq!(token.words_transferred(), 3);
+    let mut data = [0; 3];
+    token.get_data(&mut data).unwrap();
+    assert_eq!(data, [1, 2, 3]);
+    assert_eq!(mock::pending_messages(ep.to_cap()), 0);
+}
+
+#[test]
+fn recv_reports_badge() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+    let badged = common::slot(&bi, 1);
+    common::slot(&bi, 0).mint(badged, CapRights::ALL, Badge::new(0x42)).unwrap();
+
+    Endpoint::from_cap(badged.cptr).send_data(&[]).unwrap();
+    assert_eq!(ep.recv().badge, 0x42);
+}
+
+#[test]
+fn try_recv_on_empty_endpoint() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+
+    let err = ep.try_recv().unwrap_err();
+    assert_eq!(err.details(), Some(ErrorDetails::WouldBlock));
+}
+
+#[test]
+fn try_recv_takes_pending_message() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+
+    ep.send_data(&[7]).unwrap();
+    let token = ep.try_recv().unwrap();
+    assert_eq!(token.badge, 0);
+    assert_eq!(token.words_transferred(), 1);
+    assert!(ep.try_recv().is_err());
+}
+
+#[test]
+fn try_send_without_receiver_is_dropped() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+
+    ep.try_send_message(&[1], &[]).unwrap();
+    assert_eq!(mock::pending_messages(ep.to_cap()), 0);
+}
+
+#[test]
+fn send_to_empty_slot() {
+    let bi = common::boot();
+    let ep = Endpoint::from_cap(common::slot(&bi, 0).cptr);
+
+    let err = ep.send_data(&[]).unwrap_err();
+    assert_eq!(err.details(), Some(ErrorDetails::InvalidCapability { which: 0 }));
+}
+
+#[test]
+fn cap_transfer() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+    let ntfn: Notification = common::create(&bi, 1);
+    let dest = common::slot(&bi, 2);
+
+    ep.send_cap(ntfn).unwrap();
+    sel4::set_cap_destination(dest);
+    let token = ep.recv();
+
+    assert_eq!(sel4::get_cap_destination(), dest);
+    assert_eq!(token.received_slot(), Some(dest));
+    assert!(mock::same_object(dest.cptr, ntfn.to_cap()));
+}
+
+#[test]
+fn call_is_answered_by_handler() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+    mock::set_call_handler(ep.to_cap(), |msg: Message| Message::new(0, &[msg.data[0] + 1]));
+
+    unsafe {
+        (*bi.ipc_buffer()).msg[0] = 41;
+    }
+    let info = ep.call(1, 0).unwrap();
+    assert_eq!(info.get_length(), 1);
+    assert_eq!(unsafe { (*bi.ipc_buffer()).msg[0] }, 42);
+}
+
+#[test]
+fn call_message_returns_reply() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+    mock::set_call_handler(ep.to_cap(), |msg: Message| {
+        Message::new(msg.data.len() as _, &[msg.data[0] * 2, msg.data[1] * 2])
+    });
+
+    let token = ep.call_message(&[4, 5, 6], &[]).unwrap();
+    assert_eq!(token.badge, 0);
+    assert_eq!(token.label, 3);
+    assert_eq!(token.words_transferred(), 2);
+    let mut reply = [0; 2];
+    token.get_data(&mut reply).unwrap();
+    assert_eq!(reply, [8, 10]);
+
+    let data = [0; seL4_MsgMaxLength + 1];
+    assert_eq!(ep.call_message(&data, &[]).unwrap_err().details(),
+               Some(ErrorDetails::TooMuchData));
+}
+
+#[test]
+fn notification_badges_accumulate() {
+    let bi = common::boot();
+    let ntfn: Notification = common::create(&bi, 0);
+    let a = common::slot(&bi, 1);
+    let b = common::slot(&bi, 2);
+    common::slot(&bi, 0).mint(a, CapRights::ALL, Badge::new(1)).unwrap();
+    common::slot(&bi, 0).mint(b, CapRights::ALL, Badge::new(4)).unwrap();
+
+    assert_eq!(ntfn.poll(), 0);
+    Notification::from_cap(a.cptr).signal();
+    Notification::from_cap(b.cptr).signal();
+    assert_eq!(mock::notification_word(ntfn.to_cap()), Some(5));
+    assert_eq!(ntfn.wait(), 5);
+    assert_eq!(ntfn.poll(), 0);
+}
+
+#[test]
+fn debug_output_is_captured() {
+    use std::fmt::Write;
+
+    common::boot();
+    write!(sel4::DebugOutHandle, "hello {}", 42).unwrap();
+    assert_eq!(mock::debug_output(), "hello 42");
+}
+
+#[test]
+fn reply_recv_answers_and_waits() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+    let first = mock::queue_call(ep.to_cap(), Message::new(0, &[1]));
+    let second = mock::queue_call(ep.to_cap(), Message::new(0, &[2]));
+
+    let token = ep.recv();
+    assert_eq!(token.words_transferred(), 1);
+    let token = ep.reply_recv(&[10], &[]).unwrap();
+    let mut data = [0; 1];
+    token.get_data(&mut data).unwrap();
+    assert_eq!(data, [2]);
+    sel4::reply(&[20, 21], &[]).unwrap();
+
+    assert_eq!(mock::take_reply(first).unwrap().data, vec![10]);
+    assert_eq!(mock::take_reply(second).unwrap().data, vec![20, 21]);
+}
+
+#[test]
+fn saved_reply_is_answered_later() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+    let call = mock::queue_call(ep.to_cap(), Message::new(0, &[]));
+    ep.send_data(&[7]).unwrap();
+
+    ep.recv();
+    let saved = Reply::save(common::slot(&bi, 1)).unwrap();
+    assert_eq!(mock::object_at(saved.to_cap()), Some(ObjectKind::Reply));
+    ep.recv();
+    sel4::reply(&[1], &[]).unwrap();
+    assert_eq!(mock::take_reply(call), None);
+
+    saved.send(&[3], &[]).unwrap();
+    assert_eq!(mock::take_reply(call).unwrap().data, vec![3]);
+    assert_eq!(mock::object_at(common::slot(&bi, 1).cptr), None);
+}
+
+#[test]
+fn serve_loop() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+    let calls: Vec<usize> =
+        (1..4).map(|i| mock::queue_call(ep.to_cap(), Message::new(0, &[i]))).collect();
+
+    ep.serve(|token, reply| {
+        let mut data = [0; 1];
+        token.get_data(&mut data).unwrap();
+        if data[0] == 3 {
+            return ServeAction::Stop;
+        }
+        reply[0] = data[0] * 2;
+        ServeAction::Reply(1)
+    }).unwrap();
+
+    assert_eq!(mock::take_reply(calls[0]).unwrap().data, vec![2]);
+    assert_eq!(mock::take_reply(calls[1]).unwrap().data, vec![4]);
+    assert_eq!(mock::take_reply(calls[2]), None);
+}
@@ -0,0 +1,120 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+extern crate sel4;
+extern crate sel4_sys;
+
+mod common;
+
+use sel4::{CapRights, Endpoint, Error, ErrorDetails, GoOn, LookupFailureKind, SlotRef, Window};
+use sel4_sys::{seL4_MsgMaxLength, seL4_Word};
+
+fn word_bits() -> seL4_Word {
+    (std::mem::size_of::<seL4_Word>() * 8) as seL4_Word
+}
+
+#[test]
+fn delete_first() {
+    let bi = common::boot();
+    let _: Endpoint = common::create(&bi, 0);
+    let window = Window {
+        cnode: bi.empty_slots().cnode,
+        first_slot_idx: bi.empty_slots().first_slot_idx,
+        num_slots: 1,
+    };
+
+    let err = bi.untyped().next().unwrap().cap.retype::<Endpoint>(window, 0).unwrap_err();
+    assert_eq!(err.details(), Some(ErrorDetails::DeleteFirst));
+}
+
+#[test]
+fn not_enough_memory() {
+    let bi = common::boot();
+    let desc = bi.untyped().nth(1).unwrap();
+    let window = Window {
+        cnode: bi.empty_slots().cnode,
+        first_slot_idx: bi.empty_slots().first_slot_idx,
+        num_slots: 1,
+    };
+
+    let size_bits = desc.size_bits as seL4_Word + 1;
+    let err = desc.cap.retype::<sel4::Untyped>(window, size_bits).unwrap_err();
+    assert_eq!(err.details(),
+               Some(ErrorDetails::NotEnoughMemory { bytes_available: desc.size() }));
+}
+
+#[test]
+fn missing_source() {
+    let bi = common::boot();
+
+    let err = common::slot(&bi, 0).copy(common::slot(&bi, 1), CapRights::ALL).unwrap_err();
+    assert_eq!(err.details(),
+               Some(ErrorDetails::FailedLookup {
+                   failed_for_source: true,
+                   lookup_kind: LookupFailureKind::MissingCapability { bits_remaining: 0 },
+               }));
+}
+
+#[test]
+fn depth_mismatch() {
+    let bi = common::boot();
+    let _: Endpoint = common::create(&bi, 0);
+    let dest = SlotRef::new(bi.root_cnode(), common::slot(&bi, 1).cptr, 12);
+
+    let err = common::slot(&bi, 0).copy(dest, CapRights::ALL).unwrap_err();
+    assert_eq!(err.details(),
+               Some(ErrorDetails::FailedLookup {
+                   failed_for_source: false,
+                   lookup_kind: LookupFailureKind::DepthMismatch {
+                       bits_remaining: 12,
+                       bits_resolved: word_bits(),
+                   },
+               }));
+}
+
+#[test]
+fn guard_mismatch() {
+    let bi = common::boot();
+    let _: Endpoint = common::create(&bi, 0);
+    let slot = common::slot(&bi, 1);
+    let dest = SlotRef::new(bi.root_cnode(), slot.cptr | (1 << (word_bits() - 1)), slot.depth);
+
+    let err = common::slot(&bi, 0).copy(dest, CapRights::ALL).unwrap_err();
+    let guard_bits = word_bits() - bi.root_cnode_info().radix_bits as seL4_Word;
+    assert_eq!(err.details(),
+               Some(ErrorDetails::FailedLookup {
+                   failed_for_source: false,
+                   lookup_kind: LookupFailureKind::GuardMismatch {
+                       bits_remaining: word_bits(),
+                       guard: 0,
+                       guard_size: guard_bits,
+                   },
+               }));
+}
+
+#[test]
+fn too_much_data() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+
+    let data = [0; seL4_MsgMaxLength + 1];
+    let err = ep.send_data(&data).unwrap_err();
+    assert_eq!(err, Error(GoOn::TooMuchData));
+    assert_eq!(err.details(), Some(ErrorDetails::TooMuchData));
+}
+
+#[test]
+fn debug_and_display() {
+    let bi = common::boot();
+    let err = common::slot(&bi, 0).copy(common::slot(&bi, 1), CapRights::ALL).unwrap_err();
+
+    let details = err.details().unwrap();
+    assert!(format!("{}", details).contains("bits remaining"));
+    assert!(format!("{:?}", err).contains("MissingCapability"));
+}
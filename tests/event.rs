@@ -0,0 +1,74 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+extern crate sel4;
+extern crate sel4_sys;
+
+mod common;
+
+use std::cell::Cell;
+
+use sel4::{CapRights, EVENT_BITS, EventError, EventMux, EventSource, Notification, ToCap, mock};
+
+#[test]
+fn dispatches_every_set_bit() {
+    let bi = common::boot();
+    let _: Notification = common::create(&bi, 0);
+    let (a, b) = (Cell::new(0), Cell::new(0));
+    let mut on_a = || a.set(a.get() + 1);
+    let mut on_b = || b.set(b.get() + 1);
+    let mut storage: [Option<EventSource<&mut dyn FnMut()>>; 4] = [None, None, None, None];
+    let mut mux = EventMux::new(common::slot(&bi, 0), &mut storage).unwrap();
+
+    assert_eq!(mux.register(common::slot(&bi, 1), &mut on_a), Ok(0));
+    assert_eq!(mux.register(common::slot(&bi, 2), &mut on_b), Ok(1));
+    assert_eq!(mock::badge_at(common::slot(&bi, 2).cptr), Some(0b10));
+
+    Notification::from_cap(common::slot(&bi, 2).cptr).signal();
+    assert_eq!(mux.wait(), 1);
+    Notification::from_cap(common::slot(&bi, 1).cptr).signal();
+    Notification::from_cap(common::slot(&bi, 2).cptr).signal();
+    assert_eq!(mock::notification_word(mux.notification().to_cap()), Some(0b11));
+    assert_eq!(mux.wait(), 2);
+    assert_eq!(mux.poll(), 0);
+    assert_eq!(mux.dispatch(0b1100), 0);
+
+    drop(mux);
+    assert_eq!((a.get(), b.get()), (1, 2));
+}
+
+#[test]
+fn unregister_revokes_and_frees_bit() {
+    let bi = common::boot();
+    let _: Notification = common::create(&bi, 0);
+    let mut storage: [Option<EventSource<&mut dyn FnMut()>>; 1] = [None];
+    let (mut first, mut second, mut third) = (|| {}, || {}, || {});
+    let mut mux = EventMux::new(common::slot(&bi, 0), &mut storage).unwrap();
+
+    assert_eq!(mux.register(common::slot(&bi, 1), &mut first), Ok(0));
+    common::slot(&bi, 1).copy(common::slot(&bi, 2), CapRights::W).unwrap();
+    assert_eq!(mux.register(common::slot(&bi, 3), &mut second).err(),
+               Some(EventError::Exhausted));
+    assert_eq!(mock::object_at(common::slot(&bi, 3).cptr), None);
+
+    assert!(mux.unregister(0).is_ok());
+    assert_eq!(mock::object_at(common::slot(&bi, 1).cptr), None);
+    assert_eq!(mock::object_at(common::slot(&bi, 2).cptr), None);
+    assert_eq!(mux.unregister(0).err(), Some(EventError::NotRegistered));
+    assert_eq!(mux.register(common::slot(&bi, 3), &mut third), Ok(0));
+}
+
+#[test]
+fn too_many_sources() {
+    let bi = common::boot();
+    let mut storage: Vec<Option<EventSource<&mut dyn FnMut()>>> =
+        (0..EVENT_BITS + 1).map(|_| None).collect();
+    assert_eq!(EventMux::new(common::slot(&bi, 0), &mut storage).err(),
+               Some(EventError::TooManySources));
+}
@@ -0,0 +1,287 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+extern crate sel4;
+extern crate sel4_sys;
+
+mod common;
+
+use std::cell::{Cell, RefCell};
+use std::future::{Future, poll_fn};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Poll, Waker};
+
+use sel4::{Badge, CapRights, ENDPOINT_BADGE, Endpoint, Executor, ExecutorError, Notification,
+           Reactor, ToCap, mock};
+use sel4::mock::{Message, ObjectKind};
+
+#[test]
+fn tasks_await_sources() {
+    let bi = common::boot();
+    let ntfn: Notification = common::create(&bi, 0);
+    let reactor = Reactor::new(common::slot(&bi, 0));
+    let source = reactor.source(common::slot(&bi, 1)).unwrap();
+    assert_eq!(source.bit(), 0);
+
+    // Another thread signals the source whenever we block.
+    let signaller = Notification::from_cap(source.slot().cptr);
+    signaller.signal();
+    mock::set_block_handler(ntfn.to_cap(), move || signaller.signal());
+
+    let count = Cell::new(0);
+    let mut signalled = source.signalled();
+    let mut task = poll_fn(|cx| {
+        while count.get() < 3 {
+            match Pin::new(&mut signalled).poll(cx) {
+                Poll::Ready(()) => count.set(count.get() + 1),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(())
+    });
+    let mut other = poll_fn(|_| Poll::Ready(()));
+    let mut storage = [None];
+    let mut executor = Executor::new(&reactor, &mut storage);
+
+    executor.spawn(Pin::new(&mut task), common::slot(&bi, 2)).unwrap();
+    assert_eq!(mock::badge_at(common::slot(&bi, 2).cptr), Some(0b10));
+    assert_eq!(executor.spawn(Pin::new(&mut other), common::slot(&bi, 3)),
+               Err(ExecutorError::TooManyTasks));
+    assert_eq!(executor.run(), Ok(()));
+    assert_eq!(count.get(), 3);
+    assert_eq!(executor.tasks(), 0);
+    assert_eq!(mock::object_at(common::slot(&bi, 2).cptr), None);
+}
+
+#[test]
+fn wakers_signal_task_bits() {
+    let bi = common::boot();
+    let ntfn: Notification = common::create(&bi, 0);
+    let reactor = Reactor::new(common::slot(&bi, 0));
+    let waker: Rc<RefCell<Option<Waker>>> = Rc::new(RefCell::new(None));
+    let stored = waker.clone();
+    let polls = Cell::new(0);
+    let mut task = poll_fn(|cx| {
+        polls.set(polls.get() + 1);
+        if polls.get() == 2 {
+            return Poll::Ready(());
+        }
+        *stored.borrow_mut() = Some(cx.waker().clone());
+        Poll::Pending
+    });
+
+    // The waker is used from somewhere else while the executor is blocked.
+    let woken = waker.clone();
+    mock::set_block_handler(ntfn.to_cap(), move || {
+        woken.borrow_mut().take().unwrap().wake();
+    });
+    let mut storage = [None, None];
+    let mut executor = Executor::new(&reactor, &mut storage);
+    executor.spawn(Pin::new(&mut task), common::slot(&bi, 1)).unwrap();
+    assert_eq!(executor.run(), Ok(()));
+    assert_eq!(polls.get(), 2);
+    assert_eq!(mock::notification_word(ntfn.to_cap()), None);
+}
+
+#[test]
+fn receives_tell_messages_from_notifications() {
+    let bi = common::boot();
+    let _: Notification = common::create(&bi, 0);
+    let ep: Endpoint = common::create(&bi, 1);
+    let reactor = Reactor::new(common::slot(&bi, 0));
+    let source = reactor.source(common::slot(&bi, 2)).unwrap();
+    let (client, bound) = (common::slot(&bi, 3), common::slot(&bi, 4));
+    let badge = Badge::new(ENDPOINT_BADGE as u32 | 7);
+    common::slot(&bi, 1).mint(client, CapRights::W, badge).unwrap();
+    common::slot(&bi, 1).mint(bound, CapRights::W, Badge::new(1)).unwrap();
+
+    // A receive with a badge lacking `ENDPOINT_BADGE` is how the kernel delivers a signal to the
+    // bound notification.
+    mock::queue_call(bound.cptr, Message::new(9, &[]));
+    mock::queue_call(client.cptr, Message::new(5, &[]));
+    mock::set_block_handler(ep.to_cap(), move || {
+        mock::queue_call(client.cptr, Message::new(6, &[]));
+    });
+
+    let labels = RefCell::new(Vec::new());
+    let mut recv = reactor.recv(ep);
+    let mut receiver = poll_fn(|cx| {
+        while labels.borrow().len() < 2 {
+            match Pin::new(&mut recv).poll(cx) {
+                Poll::Ready(token) => labels.borrow_mut().push((token.badge, token.label)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(())
+    });
+    let mut signalled = source.signalled();
+    let mut storage = [None, None];
+    let mut executor = Executor::new(&reactor, &mut storage);
+    executor.spawn(Pin::new(&mut receiver), common::slot(&bi, 5)).unwrap();
+    executor.spawn(Pin::new(&mut signalled), common::slot(&bi, 6)).unwrap();
+
+    assert_eq!(executor.run(), Ok(()));
+    assert_eq!(*labels.borrow(), [(ENDPOINT_BADGE | 7, 5), (ENDPOINT_BADGE | 7, 6)]);
+}
+
+#[test]
+fn completed_tasks_leave_no_wakers() {
+    let bi = common::boot();
+    let ntfn: Notification = common::create(&bi, 0);
+    let reactor = Reactor::new(common::slot(&bi, 0));
+    let source = reactor.source(common::slot(&bi, 1)).unwrap();
+    let task_slot = common::slot(&bi, 2);
+
+    // The first task leaves its waker with the source, then completes.
+    let mut signalled = source.signalled();
+    let first_polls = Cell::new(0);
+    let mut first = poll_fn(|cx| {
+        first_polls.set(first_polls.get() + 1);
+        if first_polls.get() > 1 {
+            return Poll::Ready(());
+        }
+        assert_eq!(Pin::new(&mut signalled).poll(cx), Poll::Pending);
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    });
+    let mut storage = [None, None];
+    let mut executor = Executor::new(&reactor, &mut storage);
+    executor.spawn(Pin::new(&mut first), task_slot).unwrap();
+    assert_eq!(executor.run(), Ok(()));
+
+    // A second task in the same slot must only be woken by its own signal, not by the source.
+    let handled = Rc::new(Cell::new(false));
+    let flag = handled.clone();
+    mock::set_block_handler(ntfn.to_cap(), move || {
+        flag.set(true);
+        Notification::from_cap(task_slot.cptr).signal();
+    });
+    let second_polls = Cell::new(0);
+    let mut second = poll_fn(|_| {
+        second_polls.set(second_polls.get() + 1);
+        if second_polls.get() == 1 {
+            return Poll::Pending;
+        }
+        assert!(handled.get(), "woken through the first task's stale waker");
+        Poll::Ready(())
+    });
+    Notification::from_cap(source.slot().cptr).signal();
+    executor.spawn(Pin::new(&mut second), task_slot).unwrap();
+    assert_eq!(executor.run(), Ok(()));
+    assert_eq!(second_polls.get(), 2);
+}
+
+#[test]
+fn dropped_receives_stop_blocking_on_the_endpoint() {
+    let bi = common::boot();
+    let ntfn: Notification = common::create(&bi, 0);
+    let ep: Endpoint = common::create(&bi, 1);
+    let reactor = Reactor::new(common::slot(&bi, 0));
+    let task_slot = common::slot(&bi, 2);
+
+    // Blocking on the endpoint would panic, as nothing is ever sent to it.
+    mock::set_block_handler(ntfn.to_cap(), move || {
+        Notification::from_cap(task_slot.cptr).signal();
+    });
+    let mut recv = Some(reactor.recv(ep));
+    let mut task = poll_fn(|cx| {
+        match recv.take() {
+            Some(mut pending) => {
+                assert!(Pin::new(&mut pending).poll(cx).is_pending());
+                Poll::Pending
+            }
+            None => Poll::Ready(()),
+        }
+    });
+    let mut storage = [None];
+    let mut executor = Executor::new(&reactor, &mut storage);
+    executor.spawn(Pin::new(&mut task), task_slot).unwrap();
+    assert_eq!(executor.run(), Ok(()));
+}
+
+#[test]
+fn received_messages_survive_other_tasks_sending() {
+    let bi = common::boot();
+    let _: Notification = common::create(&bi, 0);
+    let ep: Endpoint = common::create(&bi, 1);
+    let other: Endpoint = common::create(&bi, 2);
+    let reactor = Reactor::new(common::slot(&bi, 0));
+    let client = common::slot(&bi, 3);
+    common::slot(&bi, 1).mint(client, CapRights::W, Badge::new(ENDPOINT_BADGE as u32)).unwrap();
+    let (sender_slot, receiver_slot) = (common::slot(&bi, 4), common::slot(&bi, 5));
+
+    // While the executor is blocked on the endpoint, a message arrives and the sending task is
+    // woken too. The sender is polled first, and sends a message of its own.
+    mock::set_block_handler(ep.to_cap(), move || {
+        mock::queue_call(client.cptr, Message::new(1, &[11, 12]));
+        Notification::from_cap(sender_slot.cptr).signal();
+    });
+    let sends = Cell::new(0);
+    let mut sender = poll_fn(|_| {
+        sends.set(sends.get() + 1);
+        if sends.get() == 1 {
+            return Poll::Pending;
+        }
+        other.send_message(&[21, 22, 23], &[]).unwrap();
+        Poll::Ready(())
+    });
+    let received = RefCell::new(Vec::new());
+    let mut recv = reactor.recv(ep);
+    let mut receiver = poll_fn(|cx| {
+        match Pin::new(&mut recv).poll(cx) {
+            Poll::Ready(token) => {
+                let mut data = [0; 2];
+                token.get_data(&mut data).unwrap();
+                received.borrow_mut().extend_from_slice(&data);
+                Poll::Ready(())
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    });
+    let mut storage = [None, None];
+    let mut executor = Executor::new(&reactor, &mut storage);
+    executor.spawn(Pin::new(&mut sender), sender_slot).unwrap();
+    executor.spawn(Pin::new(&mut receiver), receiver_slot).unwrap();
+
+    assert_eq!(executor.run(), Ok(()));
+    assert_eq!(*received.borrow(), [11, 12]);
+    assert_eq!(mock::pending_messages(other.to_cap()), 1);
+}
+
+#[test]
+fn task_capabilities_outlive_their_wakers() {
+    let bi = common::boot();
+    let _: Notification = common::create(&bi, 0);
+    let reactor = Reactor::new(common::slot(&bi, 0));
+    let (first_slot, second_slot) = (common::slot(&bi, 1), common::slot(&bi, 2));
+
+    // The task completes, but a clone of its waker is kept somewhere else.
+    let kept = RefCell::new(None);
+    let mut first = poll_fn(|cx| {
+        *kept.borrow_mut() = Some(cx.waker().clone());
+        Poll::Ready(())
+    });
+    let mut second = poll_fn(|_| Poll::Ready(()));
+    let mut storage = [None];
+    let mut executor = Executor::new(&reactor, &mut storage);
+    executor.spawn(Pin::new(&mut first), first_slot).unwrap();
+    assert_eq!(executor.run(), Ok(()));
+    assert_eq!(mock::object_at(first_slot.cptr), Some(ObjectKind::Notification));
+
+    // Waking it signals a capability which is still there.
+    kept.borrow_mut().take().unwrap().wake();
+    assert_eq!(mock::object_at(first_slot.cptr), Some(ObjectKind::Notification));
+
+    // With the last waker gone, the next run deletes it.
+    executor.spawn(Pin::new(&mut second), second_slot).unwrap();
+    assert_eq!(executor.run(), Ok(()));
+    assert_eq!(mock::object_at(first_slot.cptr), None);
+    assert_eq!(mock::object_at(second_slot.cptr), None);
+}
@@ -0,0 +1,141 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+#[macro_use]
+extern crate sel4;
+extern crate sel4_sys;
+
+mod common;
+
+use sel4::{Bytes, DecodeError, Endpoint, Error, GoOn, MessageBuilder, REPLY_OK, REPLY_REJECTED,
+           ToCap, mock};
+use sel4::mock::Message;
+
+sel4_interface! {
+    /// A tiny key-value store.
+    pub trait Store, client StoreClient {
+        /// Store `value` under `key`, returning the previous value.
+        fn put(key: u8, value: u32) -> Option<u32> = 1;
+        fn get(key: u8) -> u32 = 2;
+        fn name() -> Bytes<[u8; 8]> = 3;
+    }
+}
+
+struct Memory {
+    values: [u32; 4],
+}
+
+impl Store for Memory {
+    fn put(&mut self, key: u8, value: u32) -> Option<u32> {
+        let old = self.values[key as usize];
+        self.values[key as usize] = value;
+        if old == 0 { None } else { Some(old) }
+    }
+
+    fn get(&mut self, key: u8) -> u32 {
+        self.values[key as usize]
+    }
+
+    fn name(&mut self) -> Bytes<[u8; 8]> {
+        Bytes::from_slice(b"memory").unwrap()
+    }
+}
+
+/// Serve one queued call with `server`, returning the reply.
+fn serve_one(ep: Endpoint, server: &mut Memory, msg: Message) -> Message {
+    let call = mock::queue_call(ep.to_cap(), msg);
+    server.dispatch(&ep.recv()).unwrap();
+    mock::take_reply(call).unwrap()
+}
+
+#[test]
+fn server_dispatches_by_label() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+    let mut server = Memory { values: [0; 4] };
+
+    let reply = serve_one(ep, &mut server, Message::new(1, &[2, 10]));
+    assert_eq!(reply.label, REPLY_OK);
+    assert_eq!(reply.data, vec![0]);
+    assert_eq!(server.values[2], 10);
+
+    let reply = serve_one(ep, &mut server, Message::new(1, &[2, 11]));
+    assert_eq!(reply.data, vec![1, 10]);
+
+    let reply = serve_one(ep, &mut server, Message::new(2, &[2]));
+    assert_eq!(reply.data, vec![11]);
+}
+
+#[test]
+fn server_rejects_bad_requests() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+    let mut server = Memory { values: [0; 4] };
+
+    let reply = serve_one(ep, &mut server, Message::new(9, &[]));
+    assert_eq!(reply.label, REPLY_REJECTED);
+    assert_eq!(reply.data, vec![3]);
+
+    let reply = serve_one(ep, &mut server, Message::new(1, &[2]));
+    assert_eq!(reply.label, REPLY_REJECTED);
+    assert_eq!(reply.data, vec![0]);
+
+    let reply = serve_one(ep, &mut server, Message::new(2, &[256]));
+    assert_eq!(reply.label, REPLY_REJECTED);
+    assert_eq!(reply.data, vec![2]);
+}
+
+#[test]
+fn client_encodes_and_decodes() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+    mock::set_call_handler(ep.to_cap(), |msg: Message| {
+        match msg.label {
+            1 => Message::new(REPLY_OK, &[1, msg.data[1] * 2]),
+            2 => Message::new(REPLY_REJECTED, &[2]),
+            _ => Message::new(REPLY_OK, &[3, 0x6f6f66]),
+        }
+    });
+    let client = StoreClient::new(ep);
+    assert_eq!(client.endpoint(), ep);
+
+    assert_eq!(client.put(1, 21).unwrap(), Some(42));
+    assert_eq!(client.get(3).unwrap_err(), Error(GoOn::Rejected(DecodeError::InvalidValue)));
+    assert_eq!(client.name().unwrap().as_slice(), b"foo");
+}
+
+#[test]
+fn client_reports_malformed_replies() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+    mock::set_call_handler(ep.to_cap(), |msg: Message| {
+        match msg.label {
+            1 => Message::new(REPLY_OK, &[7]),
+            _ => Message::new(5, &[]),
+        }
+    });
+    let client = StoreClient::new(ep);
+
+    assert_eq!(client.put(1, 2).unwrap_err(), Error(GoOn::Malformed(DecodeError::InvalidValue)));
+    assert_eq!(client.get(1).unwrap_err(), Error(GoOn::Malformed(DecodeError::UnknownLabel)));
+}
+
+#[test]
+fn handle_builds_reply_without_sending() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+    let mut server = Memory { values: [5, 0, 0, 0] };
+
+    let mut msg = MessageBuilder::new(2);
+    msg.push_word(0).unwrap();
+    ep.send_built(msg).unwrap();
+    let reply = server.handle(&ep.recv());
+    assert_eq!(reply.label(), REPLY_OK);
+    assert_eq!(reply.words(), 1);
+}
@@ -0,0 +1,149 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+extern crate sel4;
+#[macro_use]
+extern crate sel4_derive;
+extern crate sel4_sys;
+
+mod common;
+
+use sel4::{Bytes, DecodeError, Endpoint, IpcEncode, Notification, ToCap, TypedCallError, mock};
+use sel4::mock::Message;
+
+#[derive(Debug, PartialEq, IpcEncode, IpcDecode)]
+struct Point {
+    x: i32,
+    y: i32,
+}
+
+#[derive(Debug, PartialEq, IpcEncode, IpcDecode)]
+enum Shape {
+    Empty,
+    Circle(Point, u16),
+    Polygon {
+        corners: [Point; 3],
+        closed: bool,
+    },
+}
+
+#[derive(Debug, PartialEq, IpcEncode, IpcDecode)]
+struct Named {
+    name: Bytes<[u8; 16]>,
+    id: u64,
+}
+
+#[derive(IpcEncode, IpcDecode)]
+struct WithCap(u8, Notification);
+
+#[test]
+fn sizes() {
+    assert_eq!(Point::WORDS, 2);
+    assert_eq!(Shape::WORDS, 1 + 7);
+    assert_eq!(<Bytes<[u8; 16]>>::WORDS, 1 + 16 / std::mem::size_of::<usize>());
+    assert_eq!(WithCap::WORDS, 1);
+    assert_eq!(WithCap::CAPS, 1);
+}
+
+#[test]
+fn struct_and_enum_round_trip() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+    let shapes = [Shape::Empty,
+                  Shape::Circle(Point { x: -1, y: 2 }, 7),
+                  Shape::Polygon {
+                      corners: [Point { x: 0, y: 0 }, Point { x: 1, y: 0 }, Point { x: 0, y: 1 }],
+                      closed: true,
+                  }];
+
+    for shape in shapes.iter() {
+        ep.send_typed(3, shape).unwrap();
+        let token = ep.recv();
+        assert_eq!(token.label, 3);
+        assert_eq!(token.decode::<Shape>().as_ref(), Ok(shape));
+    }
+}
+
+#[test]
+fn byte_strings() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+    let named = Named {
+        name: Bytes::from_slice(b"console").unwrap(),
+        id: 1 << 40,
+    };
+    assert!(<Bytes<[u8; 4]>>::from_slice(b"console").is_none());
+
+    ep.send_typed(0, &named).unwrap();
+    let decoded: Named = ep.recv().decode().unwrap();
+    assert_eq!(decoded.name.as_slice(), b"console");
+    assert_eq!(decoded, named);
+}
+
+#[test]
+fn caps_are_decoded_from_the_receive_slot() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+    let ntfn: Notification = common::create(&bi, 1);
+    let dest = common::slot(&bi, 2);
+
+    ep.send_typed(0, &WithCap(9, ntfn)).unwrap();
+    sel4::set_cap_destination(dest);
+    let WithCap(n, received) = ep.recv().decode().unwrap();
+    assert_eq!(n, 9);
+    assert_eq!(received.to_cap(), dest.cptr);
+    assert!(mock::same_object(received.to_cap(), ntfn.to_cap()));
+
+    ep.send_typed(0, &9u8).unwrap();
+    assert_eq!(ep.recv().decode::<WithCap>().err(), Some(DecodeError::MissingCap));
+}
+
+#[test]
+fn invalid_messages() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+
+    ep.send_data(&[300]).unwrap();
+    assert_eq!(ep.recv().decode::<u8>(), Err(DecodeError::InvalidValue));
+    ep.send_data(&[9]).unwrap();
+    assert_eq!(ep.recv().decode::<Shape>(), Err(DecodeError::InvalidValue));
+    ep.send_data(&[1, 5]).unwrap();
+    assert_eq!(ep.recv().decode::<Shape>(), Err(DecodeError::Truncated));
+    ep.send_data(&[2]).unwrap();
+    assert_eq!(ep.recv().decode::<bool>(), Err(DecodeError::InvalidValue));
+}
+
+#[test]
+fn call_typed() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+    mock::set_call_handler(ep.to_cap(), |msg: Message| {
+        assert_eq!(msg.label, 1);
+        Message::new(0, &[(msg.data[0] as i32 + msg.data[1] as i32) as usize])
+    });
+
+    let sum: i32 = ep.call_typed(1, &Point { x: 2, y: 3 }).unwrap();
+    assert_eq!(sum, 5);
+
+    let err = ep.call_typed::<_, Point>(1, &Point { x: 2, y: 3 }).unwrap_err();
+    assert_eq!(err, TypedCallError::Malformed(DecodeError::Truncated));
+}
+
+#[test]
+fn call_typed_reports_server_labels() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+    mock::set_call_handler(ep.to_cap(), |msg: Message| Message::new(msg.label, &[]));
+
+    // Labels which happen to match kernel error codes are still the server's.
+    assert_eq!(ep.call_typed::<_, i32>(1, &Point { x: 2, y: 3 }),
+               Err(TypedCallError::UnexpectedLabel(1)));
+    assert_eq!(ep.call_typed::<_, i32>(42, &Point { x: 2, y: 3 }),
+               Err(TypedCallError::UnexpectedLabel(42)));
+}
@@ -0,0 +1,116 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+extern crate sel4;
+extern crate sel4_sys;
+
+mod common;
+
+use sel4::{Endpoint, Error, GoOn, MessageBuilder, Notification, ToCap, mock};
+use sel4_sys::{seL4_MsgMaxExtraCaps, seL4_MsgMaxLength};
+
+#[test]
+fn label_and_words() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+
+    let mut msg = MessageBuilder::new(7);
+    msg.push_word(1).unwrap().push_words(&[2, 3]).unwrap();
+    assert_eq!(msg.words(), 3);
+    ep.send_built(msg).unwrap();
+
+    let token = ep.recv();
+    assert_eq!(token.label, 7);
+    let mut reader = token.reader();
+    assert_eq!(reader.label(), 7);
+    assert_eq!(reader.read_word(), Some(1));
+    let mut rest = [0; 2];
+    reader.read_words(&mut rest).unwrap();
+    assert_eq!(rest, [2, 3]);
+    assert_eq!(reader.remaining(), 0);
+    assert_eq!(reader.read_word(), None);
+}
+
+#[test]
+fn bytes_round_trip() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+    let text = b"hello, message registers";
+
+    let mut msg = MessageBuilder::new(0);
+    msg.push_word(text.len()).unwrap().push_bytes(text).unwrap();
+    assert_eq!(msg.words(), 1 + sel4::words_for_bytes(text.len()));
+    ep.send_built(msg).unwrap();
+
+    let token = ep.recv();
+    let mut reader = token.reader();
+    let len = reader.read_word().unwrap();
+    let mut out = vec![0; len];
+    reader.read_bytes(&mut out).unwrap();
+    assert_eq!(&out[..], &text[..]);
+}
+
+#[test]
+fn reads_are_bounds_checked() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+    let mut msg = MessageBuilder::new(0);
+    msg.push_word(1).unwrap();
+    ep.send_built(msg).unwrap();
+
+    let token = ep.recv();
+    let mut reader = token.reader();
+    let mut two = [0; 2];
+    assert_eq!(reader.read_words(&mut two), Err(()));
+    let mut bytes = [0; 9];
+    assert_eq!(reader.read_bytes(&mut bytes), Err(()));
+    assert_eq!(reader.remaining(), 1);
+    assert_eq!(reader.badge(0), None);
+}
+
+#[test]
+fn full_length_message() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+
+    let mut msg = MessageBuilder::new(1);
+    for i in 0..seL4_MsgMaxLength {
+        msg.push_word(i).unwrap();
+    }
+    assert_eq!(msg.push_word(0).unwrap_err(), Error(GoOn::TooMuchData));
+    assert_eq!(msg.push_bytes(&[1]).unwrap_err(), Error(GoOn::TooMuchData));
+    ep.send_built(msg).unwrap();
+
+    let token = ep.recv();
+    let mut reader = token.reader();
+    assert_eq!(reader.remaining(), seL4_MsgMaxLength);
+    for i in 0..seL4_MsgMaxLength {
+        assert_eq!(reader.read_word(), Some(i));
+    }
+}
+
+#[test]
+fn caps_are_transferred() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+    let ntfn: Notification = common::create(&bi, 1);
+    let dest = common::slot(&bi, 2);
+
+    let mut msg = MessageBuilder::new(0);
+    for _ in 0..seL4_MsgMaxExtraCaps {
+        msg.push_cap(ntfn).unwrap();
+    }
+    assert_eq!(msg.push_cap(ntfn).unwrap_err(), Error(GoOn::TooManyCaps));
+    ep.send_built(msg).unwrap();
+
+    sel4::set_cap_destination(dest);
+    let token = ep.recv();
+    assert_eq!(token.reader().extra_caps(), 1);
+    assert!(mock::same_object(dest.cptr, ntfn.to_cap()));
+}
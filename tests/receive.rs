@@ -0,0 +1,78 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+extern crate sel4;
+extern crate sel4_sys;
+
+#[macro_use]
+mod common;
+
+use sel4::{BitmapSlotAllocator, CapReceiveError, CapReceiver, Endpoint, ErrorDetails,
+           Notification, ObjectAllocator, SlotAllocator, ToCap, UntypedAllocator, mock};
+
+#[test]
+fn each_cap_gets_a_fresh_slot() {
+    let bi = common::boot();
+    untyped_allocator!(bi, slots, alloc);
+    let ep: Endpoint = alloc.allocate_object(alloc.allocate_slot().unwrap()).unwrap().unwrap();
+    let a: Notification = alloc.allocate_object(alloc.allocate_slot().unwrap()).unwrap().unwrap();
+    let b: Notification = alloc.allocate_object(alloc.allocate_slot().unwrap()).unwrap().unwrap();
+    let total = slots.available();
+    let mut receiver = CapReceiver::new(&alloc);
+
+    ep.send_cap(a).unwrap();
+    ep.send_data(&[1]).unwrap();
+    ep.send_cap(b).unwrap();
+
+    let first = receiver.recv(ep).unwrap().received_cap::<Notification>().unwrap();
+    assert!(mock::same_object(first.cap().to_cap(), a.to_cap()));
+    assert_eq!(receiver.slot(), None);
+
+    let plain = receiver.recv(ep).unwrap();
+    assert_eq!(plain.received_slot(), None);
+    let armed = receiver.slot().unwrap();
+
+    let second = receiver.recv(ep).unwrap().received_cap::<Notification>().unwrap();
+    assert_eq!(second.slot(), armed);
+    assert!(first.slot() != second.slot());
+    assert!(mock::same_object(second.cap().to_cap(), b.to_cap()));
+    assert_eq!(slots.available(), total - 2);
+
+    let err = receiver.try_recv(ep).unwrap_err();
+    assert_eq!(err, CapReceiveError::Kernel(sel4::Error(sel4::GoOn::WouldBlock)));
+    assert_eq!(slots.available(), total - 3);
+    drop(receiver);
+    assert_eq!(slots.available(), total - 2);
+}
+
+#[test]
+fn no_slot_to_receive_into() {
+    let bi = common::boot();
+    let ep: Endpoint = common::create(&bi, 0);
+    let mut bitmap = [0; 1];
+    let mut window = bi.empty_slots();
+    window.first_slot_idx += 1;
+    window.num_slots = 1;
+    let slots = BitmapSlotAllocator::new(window, bi.root_cnode_info(), &mut bitmap).unwrap();
+    let mut regions = [None; 1];
+    let mut allocations = [None; 1];
+    let alloc = UntypedAllocator::new(&slots, bi.root_cnode(), &mut regions, &mut allocations);
+    let taken = alloc.allocate_slot().unwrap();
+    let mut receiver = CapReceiver::new(&alloc);
+
+    ep.send_data(&[]).unwrap();
+    assert_eq!(receiver.recv(ep).err(), Some(CapReceiveError::NoSlot));
+    assert_eq!(mock::pending_messages(ep.to_cap()), 1);
+
+    alloc.free_slot(taken).unwrap();
+    let token = receiver.recv(ep).unwrap();
+    assert_eq!(token.received_slot(), None);
+    assert_eq!(sel4::get_cap_destination(), taken);
+    assert_eq!(ep.try_recv().unwrap_err().details(), Some(ErrorDetails::WouldBlock));
+}
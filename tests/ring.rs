@@ -0,0 +1,91 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+extern crate sel4;
+extern crate sel4_sys;
+
+mod common;
+
+use sel4::{Notification, RingConsumer, RingError, RingProducer, ToCap, mock, ring_bytes,
+           ring_init, ring_pages};
+
+/// Word-aligned memory standing in for shared frames.
+fn memory(bytes: usize) -> Vec<u64> {
+    vec![0; (bytes + 7) / 8]
+}
+
+#[test]
+fn fifo_until_full() {
+    let bi = common::boot();
+    let ntfn: Notification = common::create(&bi, 0);
+    let mut mem = memory(ring_bytes::<u32>(6));
+    let (ptr, len) = (mem.as_mut_ptr() as *mut u8, mem.len() * 8);
+
+    assert_eq!(unsafe { ring_init::<u32>(ptr, len) }, Ok(4));
+    let mut producer = unsafe { RingProducer::<u32>::attach(ptr, len, ntfn).unwrap() };
+    let mut consumer = unsafe { RingConsumer::<u32>::attach(ptr, len, ntfn).unwrap() };
+
+    for round in 0..3 {
+        for i in 0..4 {
+            producer.push(round * 10 + i).unwrap();
+        }
+        assert_eq!(producer.push(99), Err(99));
+        assert_eq!(consumer.len(), 4);
+        for i in 0..4 {
+            assert_eq!(consumer.pop(), Some(round * 10 + i));
+        }
+        assert_eq!(consumer.pop(), None);
+        assert!(producer.is_empty());
+    }
+    assert_eq!(mock::notification_word(ntfn.to_cap()), None);
+}
+
+#[test]
+fn signals_only_when_armed() {
+    let bi = common::boot();
+    let ntfn: Notification = common::create(&bi, 0);
+    let mut mem = memory(4096);
+    let (ptr, len) = (mem.as_mut_ptr() as *mut u8, mem.len() * 8);
+    unsafe { ring_init::<u64>(ptr, len).unwrap() };
+    let mut producer = unsafe { RingProducer::<u64>::attach(ptr, len, ntfn).unwrap() };
+    let mut consumer = unsafe { RingConsumer::<u64>::attach(ptr, len, ntfn).unwrap() };
+
+    assert!(consumer.arm());
+    producer.push(1).unwrap();
+    assert!(mock::notification_word(ntfn.to_cap()).is_some());
+    ntfn.poll();
+    producer.push(2).unwrap();
+    assert_eq!(mock::notification_word(ntfn.to_cap()), None);
+
+    assert!(!consumer.arm());
+    assert_eq!(consumer.pop_wait(), 1);
+    assert_eq!(consumer.pop_wait(), 2);
+}
+
+#[test]
+fn attach_checks_memory() {
+    let bi = common::boot();
+    let ntfn: Notification = common::create(&bi, 0);
+    let mut mem = memory(4096);
+    let (ptr, len) = (mem.as_mut_ptr() as *mut u8, mem.len() * 8);
+
+    assert_eq!(ring_pages::<u64>(1), 1);
+    assert_eq!(ring_pages::<u64>(512), 2);
+    unsafe {
+        assert_eq!(ring_init::<u64>(ptr, 8).err(), Some(RingError::TooSmall));
+        assert_eq!(ring_init::<u64>(ptr.offset(4), 64).err(), Some(RingError::Misaligned));
+        assert_eq!(RingConsumer::<u64>::attach(ptr, len, ntfn).err(), Some(RingError::Corrupt));
+
+        ring_init::<u64>(ptr, len).unwrap();
+        let small = RingConsumer::<u64>::attach(ptr, len / 2, ntfn);
+        assert_eq!(small.err(), Some(RingError::Corrupt));
+        mem[3] = 3;
+        assert_eq!(RingConsumer::<u64>::attach(ptr, len, ntfn).err(), Some(RingError::Corrupt));
+    }
+}
@@ -0,0 +1,193 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+extern crate sel4;
+extern crate sel4_sys;
+
+#[macro_use]
+mod common;
+
+use std::alloc::{Layout, alloc_zeroed};
+use std::slice;
+use std::sync::Arc;
+
+use sel4::{BitmapSlotAllocator, SpawnError, ThreadBuilder, ThreadMemory, ToCap, UntypedAllocator,
+           mock};
+use sel4::mock::ObjectKind;
+use sel4_sys::{seL4_CapInitThreadIPCBuffer, seL4_CapInitThreadVSpace, seL4_Word};
+
+const PAGE: usize = 4096;
+
+/// A leaked, page-aligned region `pages` pages long.
+fn region(pages: usize) -> &'static mut [u8] {
+    let layout = Layout::from_size_align(pages * PAGE, PAGE).unwrap();
+    unsafe { slice::from_raw_parts_mut(alloc_zeroed(layout), pages * PAGE) }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[test]
+fn spawned_thread_runs_and_joins() {
+    let bi = common::boot();
+    untyped_allocator!(bi, slots, alloc);
+    let memory = region(8);
+    let base = memory.as_ptr() as seL4_Word;
+    let frames = [seL4_CapInitThreadIPCBuffer; 8];
+    let mut used = [false; 8];
+    let memory = ThreadMemory::new(memory, &frames, &mut used);
+
+    let mut builder = ThreadBuilder::new(&alloc, &memory, bi.root_cnode(),
+                                         seL4_CapInitThreadVSpace);
+    builder.priority(100).stack_size(2 * PAGE);
+    let handle = builder.spawn(|| 6 * 7).unwrap();
+    let tcb = handle.thread().to_cap();
+    assert_eq!(memory.available(), 5);
+
+    let state = mock::thread_state(tcb).unwrap();
+    assert!(state.running);
+    assert_eq!(state.priority, 100);
+    assert_eq!(state.vspace_root, seL4_CapInitThreadVSpace);
+    assert_eq!((state.ipc_buffer, state.ipc_buffer_frame), (base, seL4_CapInitThreadIPCBuffer));
+    let regs = mock::registers(tcb).unwrap();
+    let stack = base + PAGE as seL4_Word..base + 3 * PAGE as seL4_Word;
+    assert!(stack.start < regs.sp() && regs.sp() < stack.end);
+    assert_eq!(regs.sp() % 16, 8);
+
+    unsafe { mock::run_thread(tcb) };
+    assert!(!mock::thread_state(tcb).unwrap().running);
+    assert_eq!(handle.join(), 42);
+    assert_eq!(mock::object_at(tcb), None);
+    assert_eq!(memory.available(), 8);
+}
+
+#[cfg(target_arch = "x86_64")]
+#[test]
+fn try_join_returns_the_result_once_finished() {
+    let bi = common::boot();
+    untyped_allocator!(bi, slots, alloc);
+    let frames = [seL4_CapInitThreadIPCBuffer; 8];
+    let mut used = [false; 8];
+    let memory = ThreadMemory::new(region(8), &frames, &mut used);
+
+    let builder = ThreadBuilder::new(&alloc, &memory, bi.root_cnode(), seL4_CapInitThreadVSpace);
+    let handle = builder.spawn(|| 6 * 7).unwrap();
+    let tcb = handle.thread().to_cap();
+    let handle = handle.try_join().err().unwrap();
+    unsafe { mock::run_thread(tcb) };
+    assert_eq!(handle.try_join().ok(), Some(42));
+    assert_eq!(memory.available(), 8);
+}
+
+#[test]
+fn dropping_the_handle_destroys_the_thread() {
+    let bi = common::boot();
+    untyped_allocator!(bi, slots, alloc);
+    let total = slots.available();
+    let frames = [seL4_CapInitThreadIPCBuffer; 8];
+    let mut used = [false; 8];
+    let memory = ThreadMemory::new(region(8), &frames, &mut used);
+
+    fn entry(_: seL4_Word) {}
+    let builder = ThreadBuilder::new(&alloc, &memory, bi.root_cnode(), seL4_CapInitThreadVSpace);
+    let handle = builder.spawn_fn(entry, 7).unwrap();
+    let tcb = handle.thread().to_cap();
+    assert_eq!(mock::object_at(tcb), Some(ObjectKind::Thread));
+    assert_eq!(slots.available(), total - 2);
+    assert_eq!(memory.available(), 3);
+
+    drop(handle);
+    assert_eq!(mock::object_at(tcb), None);
+    assert_eq!(slots.available(), total);
+    assert_eq!(memory.available(), 8);
+}
+
+#[test]
+fn spawn_needs_memory() {
+    let bi = common::boot();
+    untyped_allocator!(bi, slots, alloc);
+    let total = slots.available();
+    let frames = [seL4_CapInitThreadIPCBuffer; 4];
+    let mut used = [false; 4];
+    let memory = ThreadMemory::new(region(4), &frames, &mut used);
+    let mut builder = ThreadBuilder::new(&alloc, &memory, bi.root_cnode(),
+                                         seL4_CapInitThreadVSpace);
+
+    builder.stack_size(0);
+    assert_eq!(builder.spawn(|| ()).err(), Some(SpawnError::StackTooSmall));
+    // The IPC buffer takes a page, leaving three for a four-page stack.
+    builder.stack_size(4 * PAGE);
+    assert_eq!(builder.spawn(|| ()).err(), Some(SpawnError::NoMemory));
+    assert_eq!(memory.available(), 4);
+    assert_eq!(slots.available(), total);
+    builder.stack_size(3 * PAGE);
+    let handle = builder.spawn(|| ()).unwrap();
+    assert_eq!(builder.spawn(|| ()).err(), Some(SpawnError::NoMemory));
+    drop(handle);
+    assert_eq!(memory.available(), 4);
+
+}
+
+#[test]
+fn spawn_needs_an_allocator_with_memory() {
+    let bi = common::boot();
+    let mut bitmap = [0; 64];
+    let slots = BitmapSlotAllocator::new(bi.empty_slots(), bi.root_cnode_info(), &mut bitmap)
+        .unwrap();
+    let mut regions = [None; 4];
+    let mut allocations = [None; 16];
+    let alloc = UntypedAllocator::new(&slots, bi.root_cnode(), &mut regions, &mut allocations);
+    let frames = [seL4_CapInitThreadIPCBuffer; 8];
+    let mut used = [false; 8];
+    let memory = ThreadMemory::new(region(8), &frames, &mut used);
+
+    let builder = ThreadBuilder::new(&alloc, &memory, bi.root_cnode(), seL4_CapInitThreadVSpace);
+    assert_eq!(builder.spawn(|| ()).err(), Some(SpawnError::OutOfMemory));
+    assert_eq!(memory.available(), 8);
+}
+
+#[test]
+fn entry_stack_is_aligned_as_if_called() {
+    let bi = common::boot();
+    untyped_allocator!(bi, slots, alloc);
+    let frames = [seL4_CapInitThreadIPCBuffer; 8];
+    let mut used = [false; 8];
+    let memory = ThreadMemory::new(region(8), &frames, &mut used);
+
+    let builder = ThreadBuilder::new(&alloc, &memory, bi.root_cnode(), seL4_CapInitThreadVSpace);
+    let handle = builder.spawn(|| ()).unwrap();
+    let sp = mock::registers(handle.thread().to_cap()).unwrap().sp();
+    // Arguments on the stack are 16-byte aligned, below a return address of zero.
+    if cfg!(target_arch = "x86") {
+        assert_eq!(sp % 16, 12);
+    } else if cfg!(target_arch = "x86_64") {
+        assert_eq!(sp % 16, 8);
+    } else {
+        assert_eq!(sp % 16, 0);
+    }
+    if cfg!(any(target_arch = "x86", target_arch = "x86_64")) {
+        assert_eq!(unsafe { *(sp as *const seL4_Word) }, 0);
+    }
+}
+
+#[test]
+fn dropping_the_handle_drops_the_closure() {
+    let bi = common::boot();
+    untyped_allocator!(bi, slots, alloc);
+    let frames = [seL4_CapInitThreadIPCBuffer; 8];
+    let mut used = [false; 8];
+    let memory = ThreadMemory::new(region(8), &frames, &mut used);
+
+    let builder = ThreadBuilder::new(&alloc, &memory, bi.root_cnode(), seL4_CapInitThreadVSpace);
+    let captured = Arc::new(());
+    let moved = captured.clone();
+    let handle = builder.spawn(move || drop(moved)).unwrap();
+    assert_eq!(Arc::strong_count(&captured), 2);
+    let handle = handle.try_join().err().unwrap();
+    drop(handle);
+    assert_eq!(Arc::strong_count(&captured), 1);
+}
@@ -0,0 +1,111 @@
+// Copyright (c) 2015 The Robigalia Project Developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+extern crate sel4;
+extern crate sel4_sys;
+
+mod common;
+
+use sel4::{Condvar, Mutex, Notification, Semaphore, ToCap, mock};
+
+/// Something shared with the simulated threads run by `mock::set_block_handler`.
+fn leak<T>(value: T) -> &'static T {
+    Box::leak(Box::new(value))
+}
+
+#[test]
+fn uncontended_mutex_makes_no_calls() {
+    let bi = common::boot();
+    let ntfn: Notification = common::create(&bi, 0);
+    let mutex = Mutex::new(ntfn, 1);
+
+    {
+        let mut guard = mutex.lock();
+        *guard += 1;
+        assert!(mutex.try_lock().is_none());
+    }
+    assert_eq!(*mutex.try_lock().unwrap(), 2);
+    assert_eq!(mock::notification_word(ntfn.to_cap()), None);
+    assert_eq!(mutex.into_inner(), 2);
+}
+
+#[test]
+fn contended_mutex_blocks_until_unlocked() {
+    let bi = common::boot();
+    let ntfn: Notification = common::create(&bi, 0);
+    let mutex = leak(Mutex::new(ntfn, Vec::new()));
+
+    // Another thread holds the lock, and releases it while we are blocked.
+    let mut other = Some(mutex.lock());
+    mock::set_block_handler(ntfn.to_cap(), move || {
+        let mut guard = other.take().unwrap();
+        guard.push("other");
+    });
+
+    mutex.lock().push("us");
+    assert_eq!(*mutex.lock(), ["other", "us"]);
+}
+
+#[test]
+fn semaphore_counts_permits() {
+    let bi = common::boot();
+    let ntfn: Notification = common::create(&bi, 0);
+    let sem = leak(Semaphore::new(ntfn, 2));
+
+    assert!(sem.try_wait());
+    {
+        let _permit = sem.access();
+        assert_eq!(sem.available(), 0);
+        assert!(!sem.try_wait());
+    }
+    assert_eq!(sem.available(), 1);
+    sem.wait();
+
+    mock::set_block_handler(ntfn.to_cap(), move || sem.post());
+    sem.wait();
+    assert_eq!(sem.available(), 0);
+}
+
+#[test]
+fn condvar_wakes_waiter() {
+    let bi = common::boot();
+    let lock: Notification = common::create(&bi, 0);
+    let wake: Notification = common::create(&bi, 1);
+    let mutex = leak(Mutex::new(lock, false));
+    let cond = leak(Condvar::new(wake));
+
+    cond.notify_one();
+    assert_eq!(mock::notification_word(wake.to_cap()), None);
+
+    mock::set_block_handler(wake.to_cap(), move || {
+        *mutex.lock() = true;
+        cond.notify_all();
+    });
+    let guard = cond.wait_while(mutex.lock(), |ready| !*ready);
+    assert!(*guard);
+}
+
+#[test]
+fn condvar_wakeup_is_not_lost_to_a_later_waiter() {
+    let bi = common::boot();
+    let lock: Notification = common::create(&bi, 0);
+    let wake: Notification = common::create(&bi, 1);
+    let mutex = leak(Mutex::new(lock, 0));
+    let cond = leak(Condvar::new(wake));
+
+    // We are about to block when the notify is sent, and a second waiter arrives and takes the
+    // signal before we do. It must pass the signal on instead of blocking with it.
+    mock::set_block_handler(wake.to_cap(), move || {
+        cond.notify_one();
+        *cond.wait(mutex.lock()) += 1;
+    });
+    *cond.wait(mutex.lock()) += 1;
+    assert_eq!(*mutex.lock(), 2);
+    assert_eq!(mock::notification_word(wake.to_cap()), None);
+}